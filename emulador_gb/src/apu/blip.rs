@@ -0,0 +1,148 @@
+use std::f64::consts::PI;
+
+const PHASE_BITS: u32 = 5;
+const PHASES: usize = 1 << PHASE_BITS; // Positions of a step between two output samples
+const TAPS: usize = 16; // Output samples a step is spread over
+const KERNEL_BITS: u32 = 15; // Fixed point precision of the kernel, every phase adds up to 1 << KERNEL_BITS
+const TIME_BITS: u32 = 32; // Fixed point precision of the time in output samples
+const BASS_SHIFT: u32 = 9; // High-pass filter that removes the DC offset, like the capacitor of the game boy
+const CUTOFF: f64 = 0.9; // Cutoff of the low-pass filter relative to the output Nyquist frequency
+
+/// Sine computed with basic arithmetic only, libm implementations of sin differ in the last bits
+/// between platforms and the kernel has to be the same everywhere
+fn sine(x: f64) -> f64 {
+    let x = x - (x / (2.0 * PI)).round() * 2.0 * PI;
+    let mut term = x;
+    let mut sum = x;
+    for n in 1..12 {
+        term *= -x * x / ((2 * n) as f64 * (2 * n + 1) as f64);
+        sum += term;
+    }
+    sum
+}
+
+fn cosine(x: f64) -> f64 {
+    sine(x + PI / 2.0)
+}
+
+/// Build the band limited impulses, a windowed sinc for each phase in integers that add up to
+/// exactly 1 << KERNEL_BITS so a step always settles at its full height
+fn kernel() -> Vec<[i32; TAPS]> {
+    let mut kernel = Vec::with_capacity(PHASES);
+    for phase in 0..PHASES {
+        let mut impulse = [0.0; TAPS];
+        for (tap, value) in impulse.iter_mut().enumerate() {
+            // Distance to the step in output samples and position in the Blackman window
+            let x = tap as f64 - (TAPS / 2) as f64 + 1.0 - phase as f64 / PHASES as f64;
+            let position = 2.0 * PI * (x + (TAPS / 2) as f64) / TAPS as f64;
+            let window = 0.42 - 0.5 * cosine(position) + 0.08 * cosine(2.0 * position);
+            let sinc = if x == 0.0 { 1.0 } else { sine(PI * CUTOFF * x) / (PI * CUTOFF * x) };
+            *value = sinc * window.max(0.0);
+        }
+        let sum: f64 = impulse.iter().sum();
+        let mut taps = impulse.map(|value| (value / sum * (1 << KERNEL_BITS) as f64).round() as i32);
+        let error = (1 << KERNEL_BITS) - taps.iter().sum::<i32>();
+        taps[TAPS / 2 - 1] += error;
+        kernel.push(taps);
+    }
+    kernel
+}
+
+/// Band limited synthesis of a signal given as amplitude changes, resampled to the output rate.
+/// Everything after building the kernel is integer arithmetic
+#[derive(Clone, Debug)]
+pub(super) struct BlipBuffer {
+    kernel: Vec<[i32; TAPS]>,
+    /// Output samples per input clock, fixed point with TIME_BITS of fraction
+    factor: u64,
+    /// Time of the start of the frame in output samples, fixed point
+    offset: u64,
+    /// Changes added to each output sample, the samples ready are the first ones
+    deltas: Vec<i64>,
+    /// Running sum of the changes, the output before the high-pass filter
+    integrator: i64,
+}
+
+impl BlipBuffer {
+    /// Create a buffer for input clocked at clock_rate and output at sample_rate
+    pub(super) fn new(clock_rate: u32, sample_rate: u32) -> Self {
+        BlipBuffer {
+            kernel: kernel(),
+            factor: ((sample_rate as u64) << TIME_BITS) / clock_rate as u64,
+            offset: 0,
+            deltas: vec![0; TAPS],
+            integrator: 0,
+        }
+    }
+
+    /// Add a change of amplitude at a time in input clocks since the start of the frame
+    pub(super) fn add_delta(&mut self, time: u32, delta: i32) {
+        let position = self.offset + time as u64 * self.factor;
+        let index = (position >> TIME_BITS) as usize;
+        let phase = (position >> (TIME_BITS - PHASE_BITS)) as usize & (PHASES - 1);
+        if self.deltas.len() < index + TAPS {
+            self.deltas.resize(index + TAPS, 0);
+        }
+        for (sample, tap) in self.deltas[index..index + TAPS].iter_mut().zip(self.kernel[phase]) {
+            *sample += delta as i64 * tap as i64;
+        }
+    }
+
+    /// End the frame after a number of input clocks, the output samples it covers are ready
+    pub(super) fn end_frame(&mut self, clocks: u32) {
+        self.offset += clocks as u64 * self.factor;
+        let length = (self.offset >> TIME_BITS) as usize + TAPS;
+        if self.deltas.len() < length {
+            self.deltas.resize(length, 0);
+        }
+    }
+
+    /// Get the number of output samples ready
+    pub(super) fn samples_available(&self) -> usize {
+        (self.offset >> TIME_BITS) as usize
+    }
+
+    /// Take the samples ready, each one is passed to a closure
+    pub(super) fn read_samples(&mut self, mut output: impl FnMut(i16)) {
+        let count = self.samples_available();
+        for delta in self.deltas.drain(..count) {
+            self.integrator += delta;
+            let sample = self.integrator >> KERNEL_BITS;
+            self.integrator -= sample << (KERNEL_BITS - BASS_SHIFT);
+            output(sample.clamp(i16::MIN as i64, i16::MAX as i64) as i16);
+        }
+        self.deltas.resize(self.deltas.len().max(TAPS), 0);
+        self.offset -= (count as u64) << TIME_BITS;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kernel() {
+        assert!((sine(PI / 6.0) - 0.5).abs() < 1e-12);
+        assert!((sine(-7.0) - (-7.0f64).sin()).abs() < 1e-12);
+        for phase in kernel() {
+            assert_eq!(phase.iter().sum::<i32>(), 1 << KERNEL_BITS);
+        }
+    }
+
+    #[test]
+    fn test_step() {
+        let mut blip = BlipBuffer::new(1_048_576, 32_768);
+        blip.add_delta(320, 10_000);
+        blip.end_frame(32 * 64);
+        assert_eq!(blip.samples_available(), 64);
+        let mut samples = Vec::new();
+        blip.read_samples(|sample| samples.push(sample));
+        // The step at sample 10 is centered 7 samples later, it settles near its full height
+        // while the high-pass filter slowly pulls it back to 0
+        assert!(samples[..10].iter().all(|sample| *sample == 0));
+        assert!(samples[15] < 5_000 && samples[19] > 5_000);
+        assert!((9_000..10_500).contains(&samples[30]));
+        assert!(samples[63] < samples[30]);
+        assert_eq!(blip.samples_available(), 0);
+    }
+}
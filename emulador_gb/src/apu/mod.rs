@@ -0,0 +1,644 @@
+use std::fmt;
+
+use crate::audio::AudioSink;
+use crate::memory::OPEN_BUS;
+
+mod blip;
+mod noise;
+mod square;
+mod track;
+mod wave;
+
+use noise::Noise;
+use square::Square;
+use track::Track;
+use wave::Wave;
+
+pub const NR10: u16 = 0xFF10; // Channel 1 sweep
+pub const NR11: u16 = 0xFF11; // Channel 1 duty and length
+pub const NR12: u16 = 0xFF12; // Channel 1 envelope
+pub const NR13: u16 = 0xFF13; // Channel 1 frequency, lower 8 bits
+pub const NR14: u16 = 0xFF14; // Channel 1 trigger, length enable and upper 3 bits of the frequency
+pub const NR21: u16 = 0xFF16; // Channel 2, like channel 1 without the sweep
+pub const NR22: u16 = 0xFF17;
+pub const NR23: u16 = 0xFF18;
+pub const NR24: u16 = 0xFF19;
+pub const NR30: u16 = 0xFF1A; // Channel 3 DAC enable
+pub const NR31: u16 = 0xFF1B; // Channel 3 length
+pub const NR32: u16 = 0xFF1C; // Channel 3 volume
+pub const NR33: u16 = 0xFF1D; // Channel 3 frequency, lower 8 bits
+pub const NR34: u16 = 0xFF1E; // Channel 3 trigger, length enable and upper 3 bits of the frequency
+pub const NR41: u16 = 0xFF20; // Channel 4 length
+pub const NR42: u16 = 0xFF21; // Channel 4 envelope
+pub const NR43: u16 = 0xFF22; // Channel 4 clock shift, LFSR width and divisor
+pub const NR44: u16 = 0xFF23; // Channel 4 trigger and length enable
+pub const NR50: u16 = 0xFF24; // Master volume of each side
+pub const NR51: u16 = 0xFF25; // Channels sent to each side, left in the upper nibble
+pub const NR52: u16 = 0xFF26; // Power and channel status
+pub const WAVE_RAM: u16 = 0xFF30; // 32 4-bit samples of channel 3
+pub const WAVE_RAM_END: u16 = 0xFF3F;
+
+pub const CLOCK_RATE: u32 = 4_194_304; // 4.19MHz cycles per second
+pub const CHANNELS: usize = 4;
+
+const FRAME_SEQUENCER_CYCLES: u32 = CLOCK_RATE / 512; // Cycles between frame sequencer steps
+const MIX_CYCLES: u32 = 4; // The channels are mixed once per machine cycle
+const MIX_RATE: u32 = CLOCK_RATE / MIX_CYCLES;
+const POWER: u8 = 1 << 7;
+const VOLUME_SCALE: i32 = 68; // Brings the loudest mix, 4 channels at 15 times 8, close to i16::MAX
+
+/// Bits that read as 1 for each register from NR10 to NR52, write only bits included
+const READ_MASKS: [u8; 0x17] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // NR20-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // NR40-NR44
+    0x00, 0x00, 0x70, // NR50-NR52
+];
+
+/// Length counter, disables its channel when it runs out while enabled
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Length {
+    counter: u16,
+    max: u16,
+    enabled: bool,
+}
+
+impl Length {
+    fn new(max: u16) -> Self {
+        Length {
+            counter: 0,
+            max,
+            enabled: false,
+        }
+    }
+
+    /// Load the length from NRx1, the counter runs from the maximum minus the value
+    fn load(&mut self, value: u16) {
+        self.counter = self.max - value;
+    }
+
+    /// Clocked by the frame sequencer, returns true when the channel has to be disabled
+    fn clock(&mut self) -> bool {
+        if !self.enabled || self.counter == 0 {
+            return false;
+        }
+        self.counter -= 1;
+        self.counter == 0
+    }
+
+    /// Apply the length enable and trigger bits of NRx4, returns true when the channel has to be
+    /// disabled. When the next frame sequencer step doesn't clock the length, enabling it clocks
+    /// it once more and a length reloaded by the trigger starts one lower
+    fn write_control(&mut self, enabled: bool, trigger: bool, extra_clock: bool) -> bool {
+        let mut disable = false;
+        if extra_clock && !self.enabled && enabled && self.counter > 0 {
+            self.counter -= 1;
+            disable = self.counter == 0 && !trigger;
+        }
+        self.enabled = enabled;
+        if trigger && self.counter == 0 {
+            self.counter = self.max;
+            if enabled && extra_clock {
+                self.counter -= 1;
+            }
+        }
+        disable
+    }
+}
+
+/// Volume envelope of the square and noise channels
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Envelope {
+    /// Value of NRx2, initial volume, direction and period
+    register: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    fn new() -> Self {
+        Envelope {
+            register: 0,
+            volume: 0,
+            timer: 0,
+        }
+    }
+
+    /// The DAC is off when both the initial volume and the direction are 0
+    fn dac_enabled(&self) -> bool {
+        self.register & 0xF8 != 0
+    }
+
+    fn period(&self) -> u8 {
+        self.register & 0x07
+    }
+
+    fn trigger(&mut self) {
+        self.volume = self.register >> 4;
+        self.timer = self.period();
+    }
+
+    /// Clocked by the frame sequencer, a period of 0 stops the envelope
+    fn clock(&mut self) {
+        if self.period() == 0 || self.timer == 0 {
+            return;
+        }
+        self.timer -= 1;
+        if self.timer > 0 {
+            return;
+        }
+        self.timer = self.period();
+        if self.register & 0x08 != 0 {
+            self.volume = (self.volume + 1).min(15);
+        } else {
+            self.volume = self.volume.saturating_sub(1);
+        }
+    }
+}
+
+/// Create a track for the mix or a stem, None and 0 don't produce samples
+fn new_track(sample_rate: Option<u32>) -> Option<Track> {
+    sample_rate.filter(|rate| *rate > 0).map(|rate| Track::new(MIX_RATE, rate.min(MIX_RATE)))
+}
+
+/// Replace the sink of a track, a new sink creates the track at its sample rate
+fn replace_sink(track: &mut Option<Track>, sink: Option<Box<dyn AudioSink>>) -> Option<Box<dyn AudioSink>> {
+    match (track.as_mut(), sink) {
+        (Some(current), None) => current.replace_sink(None),
+        (current, Some(sink)) => {
+            let previous = current.and_then(|current| current.replace_sink(None));
+            *track = Some(Track::with_sink(MIX_RATE, sink));
+            previous
+        }
+        (None, None) => None,
+    }
+}
+
+/// Audio processing unit, four channels mixed into stereo samples
+pub struct Apu {
+    /// Last values written to NR10-NR52, the channels keep the decoded state
+    registers: [u8; 0x17],
+    square1: Square,
+    square2: Square,
+    wave: Wave,
+    noise: Noise,
+    /// Step of the frame sequencer run next, lengths are clocked on even steps, the sweep on 2
+    /// and 6 and the envelopes on 7
+    sequencer_step: u8,
+    /// Cycles since the last frame sequencer step
+    sequencer_cycles: u32,
+    /// Channels left out of the mix
+    muted: [bool; CHANNELS],
+    /// Channels played alone, when any is soloed the others are left out of the mix
+    soloed: [bool; CHANNELS],
+    /// Samples of the mix, None doesn't produce any
+    output: Option<Track>,
+    /// Samples of each channel on its own, with its panning and the master volume
+    stems: [Option<Track>; CHANNELS],
+    /// Mixes since the tracks last ended a frame
+    mix_clocks: u32,
+    /// Cycles since the last mix
+    mix_cycles: u32,
+}
+
+impl fmt::Debug for Apu {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Apu")
+            .field("registers", &self.registers)
+            .field("square1", &self.square1)
+            .field("square2", &self.square2)
+            .field("wave", &self.wave)
+            .field("noise", &self.noise)
+            .field("sequencer_step", &self.sequencer_step)
+            .field("muted", &self.muted)
+            .field("soloed", &self.soloed)
+            .field("output", &self.output)
+            .field("stems", &self.stems)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Apu {
+    /// Create an APU with the state left by the boot ROM, the chime on channel 1 has faded out
+    pub fn new() -> Self {
+        let mut apu = Apu {
+            registers: [0; 0x17],
+            square1: Square::new(true),
+            square2: Square::new(false),
+            wave: Wave::new(),
+            noise: Noise::new(),
+            sequencer_step: 0,
+            sequencer_cycles: 0,
+            muted: [false; CHANNELS],
+            soloed: [false; CHANNELS],
+            output: None,
+            stems: [None, None, None, None],
+            mix_clocks: 0,
+            mix_cycles: 0,
+        };
+        for (address, value) in [(NR52, 0x80), (NR11, 0x80), (NR12, 0xF3), (NR50, 0x77), (NR51, 0xF3)] {
+            apu.write_register(address, value);
+        }
+        apu.square1.play_silently();
+        apu
+    }
+
+    /// Check if the APU is powered on
+    pub fn is_powered(&self) -> bool {
+        self.registers[(NR52 - NR10) as usize] & POWER != 0
+    }
+
+    /// Set the stereo samples produced per second, None stops producing them. The samples are
+    /// band limited, so any rate up to the mixing rate works
+    pub fn set_sample_rate(&mut self, sample_rate: Option<u32>) {
+        let sink = self.output.take().and_then(|mut output| output.replace_sink(None));
+        self.output = new_track(sample_rate);
+        if let Some(output) = self.output.as_mut() {
+            output.replace_sink(sink);
+        }
+    }
+
+    /// Send the samples to a sink at its sample rate instead of keeping them to be taken, returns
+    /// the previous sink. Removing the sink keeps the sample rate
+    pub fn set_audio_sink(&mut self, sink: Option<Box<dyn AudioSink>>) -> Option<Box<dyn AudioSink>> {
+        replace_sink(&mut self.output, sink)
+    }
+
+    /// Get the stereo samples produced per second
+    pub fn sample_rate(&self) -> Option<u32> {
+        self.output.as_ref().map(Track::sample_rate)
+    }
+
+    /// Take the samples produced since the last call, left and right
+    pub fn take_samples(&mut self) -> Vec<[i16; 2]> {
+        self.output.as_mut().map(Track::take_samples).unwrap_or_default()
+    }
+
+    /// Set the samples per second of the stem of a channel, None stops producing them. Stems are
+    /// the channels on their own with their panning and the master volume, they ignore mute and
+    /// solo
+    pub fn set_stem_sample_rate(&mut self, channel: usize, sample_rate: Option<u32>) {
+        if let Some(stem) = self.stems.get_mut(channel.wrapping_sub(1)) {
+            *stem = new_track(sample_rate);
+        }
+    }
+
+    /// Send the stem of a channel to a sink at its sample rate, returns the previous sink
+    pub fn set_stem_sink(&mut self, channel: usize, sink: Option<Box<dyn AudioSink>>) -> Option<Box<dyn AudioSink>> {
+        let stem = self.stems.get_mut(channel.wrapping_sub(1))?;
+        replace_sink(stem, sink)
+    }
+
+    /// Take the samples of the stem of a channel produced since the last call
+    pub fn take_stem_samples(&mut self, channel: usize) -> Vec<[i16; 2]> {
+        match self.stems.get_mut(channel.wrapping_sub(1)) {
+            Some(Some(stem)) => stem.take_samples(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Leave a channel out of the mix or bring it back
+    pub fn set_channel_muted(&mut self, channel: usize, muted: bool) {
+        if let Some(mute) = self.muted.get_mut(channel.wrapping_sub(1)) {
+            *mute = muted;
+        }
+    }
+
+    /// Play a channel alone, soloing several plays them together
+    pub fn set_channel_soloed(&mut self, channel: usize, soloed: bool) {
+        if let Some(solo) = self.soloed.get_mut(channel.wrapping_sub(1)) {
+            *solo = soloed;
+        }
+    }
+
+    /// Check if a channel is heard in the mix, it isn't muted and no other channel is soloed
+    pub fn is_channel_audible(&self, channel: usize) -> bool {
+        let index = channel.wrapping_sub(1);
+        let any_soloed = self.soloed.contains(&true);
+        index < CHANNELS && !self.muted[index] && (!any_soloed || self.soloed[index])
+    }
+
+    /// Check if a channel is playing, channels are numbered from 1 to 4 like in NR52
+    pub fn is_channel_enabled(&self, channel: usize) -> bool {
+        match channel {
+            1 => self.square1.is_enabled(),
+            2 => self.square2.is_enabled(),
+            3 => self.wave.is_enabled(),
+            4 => self.noise.is_enabled(),
+            _ => false,
+        }
+    }
+
+    /// Get the output of each channel, 0-15 while the DAC is on and None while it's off
+    fn channel_outputs(&self) -> [Option<u8>; CHANNELS] {
+        [
+            self.square1.dac_enabled().then(|| self.square1.output()),
+            self.square2.dac_enabled().then(|| self.square2.output()),
+            self.wave.dac_enabled().then(|| self.wave.output()),
+            self.noise.dac_enabled().then(|| self.noise.output()),
+        ]
+    }
+
+    /// Get the stereo level of each channel with the panning of NR51 and the volumes of NR50
+    fn channel_levels(&self) -> [[i32; 2]; CHANNELS] {
+        let nr50 = self.registers[(NR50 - NR10) as usize];
+        let nr51 = self.registers[(NR51 - NR10) as usize];
+        let left_volume = ((nr50 >> 4 & 0x07) as i32 + 1) * VOLUME_SCALE;
+        let right_volume = ((nr50 & 0x07) as i32 + 1) * VOLUME_SCALE;
+        let mut levels = [[0; 2]; CHANNELS];
+        for (channel, output) in self.channel_outputs().into_iter().enumerate() {
+            let output = output.unwrap_or(0) as i32;
+            if nr51 & 0x10 << channel != 0 {
+                levels[channel][0] = output * left_volume;
+            }
+            if nr51 & 0x01 << channel != 0 {
+                levels[channel][1] = output * right_volume;
+            }
+        }
+        levels
+    }
+
+    /// Advance the APU, the cycles are 4.19MHz clock cycles
+    pub fn step(&mut self, cycles: u32) {
+        for _ in 0..cycles {
+            if self.is_powered() {
+                self.sequencer_cycles += 1;
+                if self.sequencer_cycles == FRAME_SEQUENCER_CYCLES {
+                    self.sequencer_cycles = 0;
+                    self.clock_sequencer();
+                }
+                self.square1.tick();
+                self.square2.tick();
+                self.wave.tick();
+                self.noise.tick();
+            }
+            self.mix_cycles += 1;
+            if self.mix_cycles == MIX_CYCLES {
+                self.mix_cycles = 0;
+                self.add_mix();
+            }
+        }
+        self.read_samples();
+    }
+
+    /// Mix the audible channels and set the levels of the tracks
+    fn add_mix(&mut self) {
+        if self.output.is_some() || self.stems.iter().any(Option::is_some) {
+            let levels = self.channel_levels();
+            let mut mix = [0; 2];
+            for (channel, level) in levels.iter().enumerate() {
+                if self.is_channel_audible(channel + 1) {
+                    mix[0] += level[0];
+                    mix[1] += level[1];
+                }
+            }
+            if let Some(output) = self.output.as_mut() {
+                output.set_level(self.mix_clocks, mix);
+            }
+            for (stem, level) in self.stems.iter_mut().zip(levels) {
+                if let Some(stem) = stem.as_mut() {
+                    stem.set_level(self.mix_clocks, level);
+                }
+            }
+        }
+        self.mix_clocks += 1;
+    }
+
+    /// End the frame of the tracks, the samples ready go to the sinks or are kept
+    fn read_samples(&mut self) {
+        for track in self.stems.iter_mut().chain([&mut self.output]).flatten() {
+            track.end_frame(self.mix_clocks);
+        }
+        self.mix_clocks = 0;
+    }
+
+    /// Run a step of the 512Hz frame sequencer
+    fn clock_sequencer(&mut self) {
+        if self.sequencer_step.is_multiple_of(2) {
+            self.square1.clock_length();
+            self.square2.clock_length();
+            self.wave.clock_length();
+            self.noise.clock_length();
+        }
+        if self.sequencer_step == 2 || self.sequencer_step == 6 {
+            self.square1.clock_sweep();
+        }
+        if self.sequencer_step == 7 {
+            self.square1.clock_envelope();
+            self.square2.clock_envelope();
+            self.noise.clock_envelope();
+        }
+        self.sequencer_step = (self.sequencer_step + 1) % 8;
+    }
+
+    /// Read a sound register or wave RAM
+    pub fn read_register(&self, address: u16) -> u8 {
+        match address {
+            NR52 => {
+                let status = (1..=CHANNELS).fold(0, |status, channel| {
+                    status | (self.is_channel_enabled(channel) as u8) << (channel - 1)
+                });
+                READ_MASKS[(NR52 - NR10) as usize] | self.registers[(NR52 - NR10) as usize] & POWER | status
+            }
+            NR10..NR52 => READ_MASKS[(address - NR10) as usize] | self.registers[(address - NR10) as usize],
+            WAVE_RAM..=WAVE_RAM_END => self.wave.read_ram(address - WAVE_RAM),
+            _ => OPEN_BUS,
+        }
+    }
+
+    /// Write a sound register or wave RAM, while the power is off only NR52 and wave RAM can be
+    /// written
+    pub fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            NR52 => {
+                let power = value & POWER != 0;
+                if !power {
+                    self.registers = [0; 0x17];
+                    self.square1 = Square::new(true);
+                    self.square2 = Square::new(false);
+                    self.wave = self.wave.powered_off();
+                    self.noise = Noise::new();
+                    self.sequencer_step = 0;
+                } else if !self.is_powered() {
+                    // The frame sequencer starts over from step 0
+                    self.sequencer_step = 0;
+                }
+                self.registers[(NR52 - NR10) as usize] = value & POWER;
+            }
+            WAVE_RAM..=WAVE_RAM_END => self.wave.write_ram(address - WAVE_RAM, value),
+            NR10..NR52 if self.is_powered() => {
+                self.registers[(address - NR10) as usize] = value;
+                // The length gets an extra clock when the next step won't clock it
+                let extra_clock = self.sequencer_step % 2 == 1;
+                match address {
+                    NR10..=NR14 => self.square1.write((address - NR10) as usize, value, extra_clock),
+                    NR21..=NR24 => self.square2.write((address - NR21) as usize + 1, value, extra_clock),
+                    NR30..=NR34 => self.wave.write((address - NR30) as usize, value, extra_clock),
+                    NR41..=NR44 => self.noise.write((address - NR41) as usize + 1, value, extra_clock),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    #[test]
+    fn test_registers() {
+        let mut apu = Apu::new();
+        assert_eq!(apu.read_register(NR52), 0xF1);
+        assert_eq!(apu.read_register(NR11), 0xBF);
+        assert_eq!(apu.read_register(NR13), 0xFF);
+        assert_eq!(apu.read_register(0xFF27), OPEN_BUS);
+        apu.write_register(WAVE_RAM, 0x12);
+        apu.write_register(NR52, 0x00);
+        assert_eq!(apu.read_register(NR52), 0x70);
+        assert_eq!(apu.read_register(NR50), 0x00);
+        // Only wave RAM and NR52 can be written while the power is off
+        apu.write_register(NR50, 0x77);
+        assert_eq!(apu.read_register(NR50), 0x00);
+        assert_eq!(apu.read_register(WAVE_RAM), 0x12);
+        apu.write_register(NR52, 0x80);
+        apu.write_register(NR50, 0x77);
+        assert_eq!(apu.read_register(NR50), 0x77);
+    }
+
+    #[test]
+    fn test_length() {
+        let mut apu = Apu::new();
+        apu.write_register(NR22, 0xF0);
+        apu.write_register(NR21, 62);
+        apu.write_register(NR24, 0xC0);
+        assert_eq!(apu.read_register(NR52) & 0x02, 0x02);
+        // Steps 0 and 2 clock the length
+        apu.step(FRAME_SEQUENCER_CYCLES * 3 - 1);
+        assert_eq!(apu.read_register(NR52) & 0x02, 0x02);
+        apu.step(1);
+        assert_eq!(apu.read_register(NR52) & 0x02, 0x00);
+    }
+
+    #[test]
+    fn test_length_extra_clock() {
+        let mut apu = Apu::new();
+        apu.write_register(NR22, 0xF0);
+        apu.write_register(NR21, 63);
+        apu.write_register(NR24, 0x80);
+        // After a step that clocked the length, enabling it clocks it right away
+        apu.step(FRAME_SEQUENCER_CYCLES);
+        assert_eq!(apu.read_register(NR52) & 0x02, 0x02);
+        apu.write_register(NR24, 0x40);
+        assert_eq!(apu.read_register(NR52) & 0x02, 0x00);
+    }
+
+    #[test]
+    fn test_samples() {
+        let mut apu = Apu::new();
+        apu.set_sample_rate(Some(44_100));
+        apu.step(CLOCK_RATE / 64);
+        assert_eq!(apu.take_samples().len(), 689);
+        // A square wave at full volume on both sides
+        apu.write_register(NR22, 0xF0);
+        apu.write_register(NR21, 0x80);
+        apu.write_register(NR24, 0x87);
+        apu.step(CLOCK_RATE / 100);
+        let samples = apu.take_samples();
+        // The high-pass filter centers the wave around 0
+        let loudest = samples.iter().map(|[left, _]| *left).max().unwrap();
+        let quietest = samples.iter().map(|[left, _]| *left).min().unwrap();
+        assert!(loudest > 2_000 && quietest < -2_000);
+        assert!(samples.iter().all(|[left, right]| left == right));
+    }
+
+    #[test]
+    fn test_stems() {
+        let mut apu = Apu::new();
+        apu.set_sample_rate(Some(48_000));
+        apu.set_stem_sample_rate(1, Some(48_000));
+        apu.set_stem_sample_rate(2, Some(48_000));
+        // Channel 1 on the left and channel 2 on the right
+        for (address, value) in [(NR51, 0x12), (NR12, 0xF0), (NR14, 0x86), (NR22, 0xA0), (NR24, 0x85)] {
+            apu.write_register(address, value);
+        }
+        apu.step(CLOCK_RATE / 100);
+        let mix = apu.take_samples();
+        let square1 = apu.take_stem_samples(1);
+        let square2 = apu.take_stem_samples(2);
+        assert!(apu.take_stem_samples(3).is_empty());
+        assert_eq!(mix.len(), square1.len());
+        assert!(square1.iter().any(|[left, _]| *left != 0));
+        assert!(square1.iter().all(|[_, right]| *right == 0));
+        assert!(square2.iter().all(|[left, _]| *left == 0));
+        for ((mix, square1), square2) in mix.iter().zip(&square1).zip(&square2) {
+            assert_eq!(*mix, [square1[0], square2[1]]);
+        }
+        // Muting and soloing change the mix but not the stems
+        apu.set_channel_muted(1, true);
+        assert!(!apu.is_channel_audible(1));
+        apu.step(CLOCK_RATE / 10);
+        apu.take_samples();
+        apu.take_stem_samples(1);
+        apu.step(CLOCK_RATE / 100);
+        assert!(apu.take_samples().iter().all(|[left, _]| *left == 0));
+        assert!(apu.take_stem_samples(1).iter().any(|[left, _]| *left != 0));
+        apu.set_channel_muted(1, false);
+        apu.set_channel_soloed(1, true);
+        assert!(apu.is_channel_audible(1) && !apu.is_channel_audible(2));
+        apu.step(CLOCK_RATE / 10);
+        apu.take_samples();
+        apu.step(CLOCK_RATE / 100);
+        assert!(apu.take_samples().iter().all(|[_, right]| *right == 0));
+    }
+
+    /// Sink that keeps the samples where the test can see them
+    struct TestSink(Rc<RefCell<Vec<[i16; 2]>>>);
+
+    impl AudioSink for TestSink {
+        fn sample_rate(&self) -> u32 {
+            48_000
+        }
+
+        fn write_samples(&mut self, samples: &[[i16; 2]]) {
+            self.0.borrow_mut().extend_from_slice(samples);
+        }
+    }
+
+    /// Play a noise and a sweeping square panned to different sides
+    fn record() -> Vec<[i16; 2]> {
+        let samples = Rc::new(RefCell::new(Vec::new()));
+        let mut apu = Apu::new();
+        assert!(apu.set_audio_sink(Some(Box::new(TestSink(samples.clone())))).is_none());
+        assert_eq!(apu.sample_rate(), Some(48_000));
+        let writes = [(NR51, 0x81), (NR10, 0x15), (NR12, 0xF3), (NR14, 0x85), (NR42, 0xA1), (NR43, 0x31), (NR44, 0x80)];
+        for (address, value) in writes {
+            apu.write_register(address, value);
+        }
+        for _ in 0..1_000 {
+            apu.step(71);
+        }
+        assert!(apu.take_samples().is_empty());
+        samples.take()
+    }
+
+    #[test]
+    fn test_sink() {
+        let samples = record();
+        assert_eq!(samples.len(), (71_000 * 48_000 / CLOCK_RATE) as usize);
+        assert!(samples.iter().any(|[left, right]| left != right));
+        // Every run plays exactly the same samples
+        assert_eq!(samples, record());
+    }
+}
@@ -0,0 +1,162 @@
+use super::{Envelope, Length};
+
+const DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112]; // Cycles selected by the lower bits of NR43
+const SHORT_MODE: u8 = 1 << 3; // NR43 bit that makes the LFSR 7 bits long
+
+/// Noise channel, plays the output of a linear feedback shift register
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(super) struct Noise {
+    /// Value of NR43, clock shift, width and divisor
+    register: u8,
+    lfsr: u16,
+    /// Cycles until the next shift of the LFSR
+    timer: u32,
+    length: Length,
+    envelope: Envelope,
+    enabled: bool,
+}
+
+impl Noise {
+    pub(super) fn new() -> Self {
+        Noise {
+            register: 0,
+            lfsr: 0,
+            timer: 0,
+            length: Length::new(64),
+            envelope: Envelope::new(),
+            enabled: false,
+        }
+    }
+
+    pub(super) fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub(super) fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
+
+    fn period(&self) -> u32 {
+        DIVISORS[(self.register & 0x07) as usize] << (self.register >> 4)
+    }
+
+    /// Write NR41-NR44, the register number is the last digit
+    pub(super) fn write(&mut self, register: usize, value: u8, extra_length_clock: bool) {
+        match register {
+            1 => self.length.load((value & 0x3F) as u16),
+            2 => {
+                self.envelope.register = value;
+                if !self.dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.register = value,
+            4 => {
+                let trigger = value & 0x80 != 0;
+                if self.length.write_control(value & 0x40 != 0, trigger, extra_length_clock) {
+                    self.enabled = false;
+                }
+                if trigger {
+                    self.enabled = self.dac_enabled();
+                    self.lfsr = 0x7FFF;
+                    self.timer = self.period();
+                    self.envelope.trigger();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Advance the channel by one cycle, shifts of 14 and 15 stop the LFSR
+    pub(super) fn tick(&mut self) {
+        if self.register >> 4 >= 14 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer > 0 {
+            return;
+        }
+        self.timer = self.period();
+        let feedback = (self.lfsr ^ self.lfsr >> 1) & 0x01;
+        self.lfsr = self.lfsr >> 1 | feedback << 14;
+        if self.register & SHORT_MODE != 0 {
+            self.lfsr = self.lfsr & !(1 << 6) | feedback << 6;
+        }
+    }
+
+    pub(super) fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub(super) fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    /// Get the output of the channel, 0-15, the volume while bit 0 of the LFSR is clear
+    pub(super) fn output(&self) -> u8 {
+        if !self.enabled || self.lfsr & 0x01 != 0 {
+            return 0;
+        }
+        self.envelope.volume
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Count the shifts of the LFSR until it repeats
+    fn lfsr_period(register: u8) -> usize {
+        let mut noise = Noise::new();
+        noise.write(2, 0xF0, false);
+        noise.write(3, register, false);
+        noise.write(4, 0x80, false);
+        let shift = |noise: &mut Noise| {
+            for _ in 0..8 {
+                noise.tick();
+            }
+        };
+        // Let the short mode settle into its loop first
+        for _ in 0..16 {
+            shift(&mut noise);
+        }
+        let start = noise.lfsr;
+        let mut period = 1;
+        shift(&mut noise);
+        while noise.lfsr != start {
+            shift(&mut noise);
+            period += 1;
+        }
+        period
+    }
+
+    #[test]
+    fn test_lfsr() {
+        assert_eq!(lfsr_period(0x00), 0x7FFF);
+        assert_eq!(lfsr_period(SHORT_MODE), 0x7F);
+    }
+
+    #[test]
+    fn test_output() {
+        let mut noise = Noise::new();
+        noise.write(2, 0xA0, false);
+        noise.write(4, 0x80, false);
+        assert_eq!(noise.output(), 0);
+        // 0x7FFF shifts in a 0 on the first clock and outputs 0 until a 0 reaches bit 0
+        for _ in 0..8 * 14 {
+            noise.tick();
+        }
+        assert_eq!(noise.output(), 0);
+        for _ in 0..8 {
+            noise.tick();
+        }
+        assert_eq!(noise.output(), 0x0A);
+        // Shifts of 14 and 15 stop the LFSR
+        noise.write(3, 0xE0, false);
+        let lfsr = noise.lfsr;
+        noise.tick();
+        assert_eq!(noise.lfsr, lfsr);
+    }
+}
@@ -0,0 +1,272 @@
+use super::{Envelope, Length};
+
+/// Waveforms selected by the duty bits of NRx1, 12.5%, 25%, 50% and 75%
+const DUTY_PATTERNS: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+const MAX_FREQUENCY: u16 = 2047;
+
+/// Frequency sweep of channel 1
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Sweep {
+    /// Value of NR10, period, direction and shift
+    register: u8,
+    /// Copy of the frequency the sweep works on
+    shadow: u16,
+    timer: u8,
+    enabled: bool,
+    /// A subtraction was calculated since the last trigger
+    negated: bool,
+}
+
+impl Sweep {
+    fn new() -> Self {
+        Sweep {
+            register: 0,
+            shadow: 0,
+            timer: 0,
+            enabled: false,
+            negated: false,
+        }
+    }
+
+    fn period(&self) -> u8 {
+        self.register >> 4 & 0x07
+    }
+
+    fn shift(&self) -> u8 {
+        self.register & 0x07
+    }
+
+    fn is_negate(&self) -> bool {
+        self.register & 0x08 != 0
+    }
+
+    /// A period of 0 reloads the timer with 8
+    fn reload_timer(&mut self) {
+        self.timer = if self.period() == 0 { 8 } else { self.period() };
+    }
+
+    /// Calculate the next frequency from the shadow frequency
+    fn calculate(&mut self) -> u16 {
+        let delta = self.shadow >> self.shift();
+        if self.is_negate() {
+            self.negated = true;
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        }
+    }
+
+    /// Start the sweep from a frequency, returns false when the first calculation overflows
+    fn trigger(&mut self, frequency: u16) -> bool {
+        self.shadow = frequency;
+        self.negated = false;
+        self.reload_timer();
+        self.enabled = self.period() != 0 || self.shift() != 0;
+        self.shift() == 0 || self.calculate() <= MAX_FREQUENCY
+    }
+
+    /// Write NR10, returns false when leaving negate mode after a subtraction, which disables
+    /// the channel
+    fn write(&mut self, value: u8) -> bool {
+        let was_negate = self.is_negate();
+        self.register = value;
+        !(was_negate && !self.is_negate() && self.negated)
+    }
+
+    /// Clocked by the frame sequencer, updates the frequency and returns false when it overflows
+    fn clock(&mut self, frequency: &mut u16) -> bool {
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer > 0 {
+            return true;
+        }
+        self.reload_timer();
+        if !self.enabled || self.period() == 0 {
+            return true;
+        }
+        let new_frequency = self.calculate();
+        if new_frequency > MAX_FREQUENCY {
+            return false;
+        }
+        if self.shift() != 0 {
+            self.shadow = new_frequency;
+            *frequency = new_frequency;
+            // The new frequency is checked again right away
+            return self.calculate() <= MAX_FREQUENCY;
+        }
+        true
+    }
+}
+
+/// Square wave channel, channel 1 has a frequency sweep and channel 2 doesn't
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(super) struct Square {
+    sweep: Option<Sweep>,
+    duty: u8,
+    /// Step of the duty pattern being played
+    position: u8,
+    frequency: u16,
+    /// Cycles until the next step of the duty pattern
+    timer: u32,
+    length: Length,
+    envelope: Envelope,
+    enabled: bool,
+}
+
+impl Square {
+    pub(super) fn new(sweep: bool) -> Self {
+        Square {
+            sweep: sweep.then(Sweep::new),
+            duty: 0,
+            position: 0,
+            frequency: 0,
+            timer: 0,
+            length: Length::new(64),
+            envelope: Envelope::new(),
+            enabled: false,
+        }
+    }
+
+    /// Leave the channel playing at volume 0, like the boot ROM chime does
+    pub(super) fn play_silently(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.envelope.volume = 0;
+    }
+
+    pub(super) fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub(super) fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 4
+    }
+
+    /// Write NRx0-NRx4, the register number is the last digit
+    pub(super) fn write(&mut self, register: usize, value: u8, extra_length_clock: bool) {
+        match register {
+            0 => {
+                if let Some(sweep) = self.sweep.as_mut() {
+                    if !sweep.write(value) {
+                        self.enabled = false;
+                    }
+                }
+            }
+            1 => {
+                self.duty = value >> 6;
+                self.length.load((value & 0x3F) as u16);
+            }
+            2 => {
+                self.envelope.register = value;
+                if !self.dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.frequency = self.frequency & 0x0700 | value as u16,
+            4 => {
+                self.frequency = self.frequency & 0x00FF | ((value & 0x07) as u16) << 8;
+                let trigger = value & 0x80 != 0;
+                if self.length.write_control(value & 0x40 != 0, trigger, extra_length_clock) {
+                    self.enabled = false;
+                }
+                if trigger {
+                    self.trigger();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled();
+        self.timer = self.period();
+        self.envelope.trigger();
+        if let Some(sweep) = self.sweep.as_mut() {
+            if !sweep.trigger(self.frequency) {
+                self.enabled = false;
+            }
+        }
+    }
+
+    /// Advance the channel by one cycle
+    pub(super) fn tick(&mut self) {
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = self.period();
+            self.position = (self.position + 1) % 8;
+        }
+    }
+
+    pub(super) fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub(super) fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub(super) fn clock_sweep(&mut self) {
+        if let Some(sweep) = self.sweep.as_mut() {
+            if !sweep.clock(&mut self.frequency) {
+                self.enabled = false;
+            }
+        }
+    }
+
+    /// Get the output of the channel, 0-15
+    pub(super) fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        let high = DUTY_PATTERNS[self.duty as usize] >> (7 - self.position) & 0x01;
+        high * self.envelope.volume
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duty() {
+        let mut square = Square::new(false);
+        square.write(1, 0x00, false);
+        square.write(2, 0xF0, false);
+        square.write(3, 0xFF, false);
+        square.write(4, 0x87, false);
+        // Frequency 2047 steps every 4 cycles, 12.5% is high for the last step of 8
+        let mut outputs = Vec::new();
+        for _ in 0..8 {
+            for _ in 0..4 {
+                square.tick();
+            }
+            outputs.push(square.output());
+        }
+        assert_eq!(outputs, [0, 0, 0, 0, 0, 0, 15, 0]);
+    }
+
+    #[test]
+    fn test_sweep() {
+        let mut square = Square::new(true);
+        square.write(2, 0xF0, false);
+        square.write(0, 0x11, false);
+        square.write(3, 0x00, false);
+        square.write(4, 0x84, false);
+        square.clock_sweep();
+        assert_eq!(square.frequency, 0x600);
+        // 0x600 + 0x300 overflows on the check that follows the update
+        assert!(!square.is_enabled());
+        // Leaving negate mode after a subtraction disables the channel
+        square.write(0, 0x19, false);
+        square.write(4, 0x84, false);
+        square.clock_sweep();
+        assert_eq!(square.frequency, 0x200);
+        assert!(square.is_enabled());
+        square.write(0, 0x11, false);
+        assert!(!square.is_enabled());
+    }
+}
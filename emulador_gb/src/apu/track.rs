@@ -0,0 +1,91 @@
+use std::fmt;
+
+use super::blip::BlipBuffer;
+use crate::audio::AudioSink;
+
+/// Stereo signal resampled to a sample rate, the samples go to a sink or are kept to be taken
+pub(super) struct Track {
+    sample_rate: u32,
+    /// Band limited resamplers of the left and right sides, fed with the changes of the signal
+    blips: [BlipBuffer; 2],
+    /// Level last added to the resamplers
+    amplitudes: [i32; 2],
+    /// Samples kept until taken when there's no sink
+    samples: Vec<[i16; 2]>,
+    sink: Option<Box<dyn AudioSink>>,
+}
+
+impl fmt::Debug for Track {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Track")
+            .field("sample_rate", &self.sample_rate)
+            .field("amplitudes", &self.amplitudes)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Track {
+    /// Create a track for a signal that changes at clock_rate, resampled to sample_rate
+    pub(super) fn new(clock_rate: u32, sample_rate: u32) -> Self {
+        Track {
+            sample_rate,
+            blips: [BlipBuffer::new(clock_rate, sample_rate), BlipBuffer::new(clock_rate, sample_rate)],
+            amplitudes: [0; 2],
+            samples: Vec::new(),
+            sink: None,
+        }
+    }
+
+    /// Create a track that sends the samples to a sink at its sample rate
+    pub(super) fn with_sink(clock_rate: u32, sink: Box<dyn AudioSink>) -> Self {
+        let mut track = Self::new(clock_rate, sink.sample_rate());
+        track.sink = Some(sink);
+        track
+    }
+
+    pub(super) fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Replace the sink, None keeps the samples to be taken
+    pub(super) fn replace_sink(&mut self, sink: Option<Box<dyn AudioSink>>) -> Option<Box<dyn AudioSink>> {
+        std::mem::replace(&mut self.sink, sink)
+    }
+
+    /// Set the level of the signal at a time in clocks since the end of the last frame
+    pub(super) fn set_level(&mut self, time: u32, level: [i32; 2]) {
+        for ((blip, amplitude), level) in self.blips.iter_mut().zip(&mut self.amplitudes).zip(level) {
+            if level != *amplitude {
+                blip.add_delta(time, level - *amplitude);
+                *amplitude = level;
+            }
+        }
+    }
+
+    /// End the frame after a number of clocks and send the samples ready to the sink or keep them
+    pub(super) fn end_frame(&mut self, clocks: u32) {
+        let [left, right] = &mut self.blips;
+        left.end_frame(clocks);
+        right.end_frame(clocks);
+        if left.samples_available() == 0 {
+            return;
+        }
+        let start = self.samples.len();
+        left.read_samples(|sample| self.samples.push([sample, 0]));
+        let mut samples = self.samples[start..].iter_mut();
+        right.read_samples(|sample| {
+            if let Some(stereo) = samples.next() {
+                stereo[1] = sample;
+            }
+        });
+        if let Some(sink) = self.sink.as_mut() {
+            sink.write_samples(&self.samples);
+            self.samples.clear();
+        }
+    }
+
+    /// Take the samples kept since the last call, left and right
+    pub(super) fn take_samples(&mut self) -> Vec<[i16; 2]> {
+        std::mem::take(&mut self.samples)
+    }
+}
@@ -0,0 +1,155 @@
+use super::Length;
+
+const WAVE_RAM_SIZE: usize = 16;
+const VOLUME_SHIFTS: [u8; 4] = [4, 0, 1, 2]; // Mute, 100%, 50% and 25% selected by NR32
+
+/// Wave channel, plays the 32 samples of wave RAM
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(super) struct Wave {
+    ram: [u8; WAVE_RAM_SIZE],
+    dac_enabled: bool,
+    volume: u8,
+    frequency: u16,
+    /// Cycles until the next sample
+    timer: u32,
+    /// Sample being played, 0-31
+    position: u8,
+    /// Last sample read from wave RAM
+    sample: u8,
+    length: Length,
+    enabled: bool,
+}
+
+impl Wave {
+    pub(super) fn new() -> Self {
+        Wave {
+            ram: [0; WAVE_RAM_SIZE],
+            dac_enabled: false,
+            volume: 0,
+            frequency: 0,
+            timer: 0,
+            position: 0,
+            sample: 0,
+            length: Length::new(256),
+            enabled: false,
+        }
+    }
+
+    /// Get the channel left by turning the APU off, wave RAM isn't cleared
+    pub(super) fn powered_off(&self) -> Self {
+        Wave {
+            ram: self.ram,
+            ..Self::new()
+        }
+    }
+
+    pub(super) fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub(super) fn dac_enabled(&self) -> bool {
+        self.dac_enabled
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.frequency as u32) * 2
+    }
+
+    /// Read wave RAM, while the channel plays the CPU sees the byte being played
+    pub(super) fn read_ram(&self, offset: u16) -> u8 {
+        let offset = if self.enabled { self.position as usize / 2 } else { offset as usize };
+        self.ram[offset]
+    }
+
+    /// Write wave RAM, while the channel plays the write goes to the byte being played
+    pub(super) fn write_ram(&mut self, offset: u16, value: u8) {
+        let offset = if self.enabled { self.position as usize / 2 } else { offset as usize };
+        self.ram[offset] = value;
+    }
+
+    /// Write NR30-NR34, the register number is the last digit
+    pub(super) fn write(&mut self, register: usize, value: u8, extra_length_clock: bool) {
+        match register {
+            0 => {
+                self.dac_enabled = value & 0x80 != 0;
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            }
+            1 => self.length.load(value as u16),
+            2 => self.volume = value >> 5 & 0x03,
+            3 => self.frequency = self.frequency & 0x0700 | value as u16,
+            4 => {
+                self.frequency = self.frequency & 0x00FF | ((value & 0x07) as u16) << 8;
+                let trigger = value & 0x80 != 0;
+                if self.length.write_control(value & 0x40 != 0, trigger, extra_length_clock) {
+                    self.enabled = false;
+                }
+                if trigger {
+                    // The sample buffer isn't reloaded, the first sample played is the second one
+                    self.enabled = self.dac_enabled;
+                    self.position = 0;
+                    self.timer = self.period();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Advance the channel by one cycle
+    pub(super) fn tick(&mut self) {
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = self.period();
+            self.position = (self.position + 1) % 32;
+            let byte = self.ram[self.position as usize / 2];
+            self.sample = if self.position.is_multiple_of(2) { byte >> 4 } else { byte & 0x0F };
+        }
+    }
+
+    pub(super) fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    /// Get the output of the channel, 0-15
+    pub(super) fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        self.sample >> VOLUME_SHIFTS[self.volume as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_samples() {
+        let mut wave = Wave::new();
+        for offset in 0..WAVE_RAM_SIZE as u16 {
+            wave.write_ram(offset, 0x1F);
+        }
+        wave.write_ram(0, 0x8C);
+        wave.write(0, 0x80, false);
+        wave.write(2, 0x20, false);
+        wave.write(3, 0xFF, false);
+        wave.write(4, 0x87, false);
+        // Frequency 2047 reads a sample every 2 cycles, starting with the second one
+        wave.tick();
+        wave.tick();
+        assert_eq!(wave.output(), 0x0C);
+        assert_eq!(wave.read_ram(5), 0x8C);
+        wave.tick();
+        wave.tick();
+        assert_eq!(wave.output(), 0x01);
+        // 50% volume
+        wave.write(2, 0x40, false);
+        assert_eq!(wave.output(), 0x00);
+        wave.tick();
+        wave.tick();
+        assert_eq!(wave.output(), 0x07);
+    }
+}
@@ -0,0 +1,162 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const WAV_HEADER_SIZE: u32 = 44;
+const CHANNELS: u16 = 2;
+const BYTES_PER_SAMPLE: u16 = 2;
+// Most bytes of samples the 32 bit RIFF size can describe, a whole number of stereo samples
+const MAX_DATA_SIZE: u32 = (u32::MAX - (WAV_HEADER_SIZE - 8)) / 4 * 4;
+
+/// Receives the stereo samples of the APU, implemented by the audio output of the host
+pub trait AudioSink {
+    /// Get the stereo samples per second the sink expects
+    fn sample_rate(&self) -> u32;
+
+    /// Receive stereo samples, left and right
+    fn write_samples(&mut self, samples: &[[i16; 2]]);
+}
+
+/// Records the samples to a 16 bit stereo WAV file. The sizes in the header are filled in every
+/// second of audio, so a recording cut short stays playable, and when the writer is finished or
+/// dropped. The recording stops once the file reaches the 4GiB limit of the format
+#[derive(Debug)]
+pub struct WavWriter<W: Write + Seek> {
+    /// None once finished or after a write failed
+    writer: Option<W>,
+    sample_rate: u32,
+    /// Bytes of samples written
+    data_size: u32,
+}
+
+impl WavWriter<BufWriter<File>> {
+    /// Create a WAV file
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?), sample_rate)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    /// Start a WAV file in a writer
+    pub fn new(mut writer: W, sample_rate: u32) -> io::Result<Self> {
+        let block_align = CHANNELS * BYTES_PER_SAMPLE;
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&(WAV_HEADER_SIZE - 8).to_le_bytes())?;
+        writer.write_all(b"WAVEfmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        // PCM format
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&CHANNELS.to_le_bytes())?;
+        writer.write_all(&sample_rate.to_le_bytes())?;
+        writer.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        writer.write_all(&block_align.to_le_bytes())?;
+        writer.write_all(&(BYTES_PER_SAMPLE * 8).to_le_bytes())?;
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        Ok(WavWriter {
+            writer: Some(writer),
+            sample_rate,
+            data_size: 0,
+        })
+    }
+
+    /// Fill in the sizes of the header and get the writer back
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_sizes()?;
+        self.writer.take().ok_or_else(|| io::Error::other("a previous write failed"))
+    }
+
+    fn write_sizes(&mut self) -> io::Result<()> {
+        let Some(writer) = self.writer.as_mut() else {
+            return Ok(());
+        };
+        writer.seek(SeekFrom::Start(4))?;
+        writer.write_all(&(WAV_HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+        writer.seek(SeekFrom::Start(WAV_HEADER_SIZE as u64 - 4))?;
+        writer.write_all(&self.data_size.to_le_bytes())?;
+        writer.seek(SeekFrom::End(0))?;
+        writer.flush()
+    }
+}
+
+impl<W: Write + Seek> AudioSink for WavWriter<W> {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn write_samples(&mut self, samples: &[[i16; 2]]) {
+        let Some(writer) = self.writer.as_mut() else {
+            return;
+        };
+        let block_align = (CHANNELS * BYTES_PER_SAMPLE) as u32;
+        let room = ((MAX_DATA_SIZE - self.data_size) / block_align) as usize;
+        if room == 0 || samples.is_empty() {
+            return;
+        }
+        let samples = &samples[..samples.len().min(room)];
+        let bytes: Vec<u8> = samples.iter().flatten().flat_map(|sample| sample.to_le_bytes()).collect();
+        let second = (self.sample_rate * block_align).max(1);
+        let previous_seconds = self.data_size / second;
+        let mut result = writer.write_all(&bytes);
+        // The samples were cut to fit, so the size stays within the limit
+        self.data_size += bytes.len() as u32;
+        let full = self.data_size == MAX_DATA_SIZE;
+        if result.is_ok() && (full || self.data_size / second != previous_seconds) {
+            result = self.write_sizes();
+        }
+        if let Err(error) = result {
+            eprintln!("Could not write the WAV file: {}", error);
+            self.writer = None;
+        } else if full {
+            eprintln!("The WAV file reached the 4GiB limit, the rest of the recording is dropped");
+        }
+    }
+}
+
+impl<W: Write + Seek> Drop for WavWriter<W> {
+    fn drop(&mut self) {
+        if let Err(error) = self.write_sizes() {
+            eprintln!("Could not finish the WAV file: {}", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_wav() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), 48_000).unwrap();
+        wav.write_samples(&[[1, -1], [0x1234, 0x5678]]);
+        let data = wav.finish().unwrap().into_inner();
+        assert_eq!(data.len(), 44 + 8);
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(&data[4..8], &44u32.to_le_bytes());
+        assert_eq!(&data[8..16], b"WAVEfmt ");
+        // Channels, sample rate, bytes per second, block size and bits
+        assert_eq!(&data[22..24], &2u16.to_le_bytes());
+        assert_eq!(&data[24..28], &48_000u32.to_le_bytes());
+        assert_eq!(&data[28..32], &192_000u32.to_le_bytes());
+        assert_eq!(&data[32..36], &[4, 0, 16, 0]);
+        assert_eq!(&data[36..44], &[b'd', b'a', b't', b'a', 8, 0, 0, 0]);
+        assert_eq!(&data[44..], &[1, 0, 0xFF, 0xFF, 0x34, 0x12, 0x78, 0x56]);
+    }
+
+    #[test]
+    fn test_wav_size_limit() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), 48_000).unwrap();
+        // Pretend almost 4GiB were recorded, one more sample fits
+        wav.data_size = MAX_DATA_SIZE - 4;
+        wav.write_samples(&[[1, 2], [3, 4]]);
+        assert_eq!(wav.data_size, MAX_DATA_SIZE);
+        wav.write_samples(&[[5, 6]]);
+        assert_eq!(wav.data_size, MAX_DATA_SIZE);
+        let data = wav.finish().unwrap().into_inner();
+        assert_eq!(&data[4..8], &(MAX_DATA_SIZE + 36).to_le_bytes());
+        assert_eq!(&data[40..44], &MAX_DATA_SIZE.to_le_bytes());
+        assert_eq!(&data[44..], &[1, 0, 2, 0]);
+    }
+}
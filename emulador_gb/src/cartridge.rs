@@ -0,0 +1,535 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::mbc::{new_mapper, Accelerometer, InfraredPort, Mapper, RtcClock, RumbleCallback, ROM_BANK_SIZE};
+use crate::save::{save_path, BatterySave};
+
+const HEADER_END: usize = 0x0150; // First byte after the cartridge header
+const TITLE: usize = 0x0134; // Title in upper case ASCII (16 bytes)
+const CGB_FLAG: usize = 0x0143; // Last title byte on CGB cartridges
+const NEW_LICENSEE: usize = 0x0144; // New licensee code (2 ASCII bytes)
+const SGB_FLAG: usize = 0x0146;
+const CARTRIDGE_TYPE: usize = 0x0147; // Mapper and extra hardware
+const ROM_SIZE: usize = 0x0148;
+const RAM_SIZE: usize = 0x0149;
+const OLD_LICENSEE: usize = 0x014B; // 0x33 means the new licensee code is used
+const VERSION: usize = 0x014C;
+const HEADER_CHECKSUM: usize = 0x014D; // Checksum of 0x0134-0x014C
+const GLOBAL_CHECKSUM: usize = 0x014E; // Big endian sum of every other ROM byte
+
+const USE_NEW_LICENSEE: u8 = 0x33;
+
+/// Errors that can happen while loading a cartridge, the ROM size and the global checksum are
+/// only warnings because the hardware never checks them
+#[derive(Debug)]
+pub enum CartridgeError {
+    /// The file could not be read
+    Io(io::Error),
+    /// The image is too small to contain a header
+    Truncated { size: usize },
+    /// The ROM size code at 0x0148 is not valid
+    UnknownRomSize(u8),
+    /// The RAM size code at 0x0149 is not valid
+    UnknownRamSize(u8),
+    /// The cartridge type at 0x0147 uses a mapper that is not emulated
+    UnsupportedCartridgeType(u8),
+    /// The image size doesn't match the ROM size declared in the header
+    RomSizeMismatch { expected: usize, actual: usize },
+    /// The checksum at 0x014D doesn't match the header bytes
+    HeaderChecksum { expected: u8, actual: u8 },
+    /// The checksum at 0x014E-0x014F doesn't match the ROM bytes
+    GlobalChecksum { expected: u16, actual: u16 },
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CartridgeError::Io(error) => write!(f, "could not read the ROM: {}", error),
+            CartridgeError::Truncated { size } => {
+                write!(f, "the ROM is {} bytes long, too small to contain a header", size)
+            }
+            CartridgeError::UnknownRomSize(code) => write!(f, "unknown ROM size code {:#04X}", code),
+            CartridgeError::UnknownRamSize(code) => write!(f, "unknown RAM size code {:#04X}", code),
+            CartridgeError::UnsupportedCartridgeType(code) => {
+                write!(f, "unsupported cartridge type {:#04X}", code)
+            }
+            CartridgeError::RomSizeMismatch { expected, actual } => write!(
+                f,
+                "the header declares {} bytes of ROM but the image has {}",
+                expected, actual
+            ),
+            CartridgeError::HeaderChecksum { expected, actual } => write!(
+                f,
+                "header checksum mismatch, expected {:#04X} but got {:#04X}",
+                expected, actual
+            ),
+            CartridgeError::GlobalChecksum { expected, actual } => write!(
+                f,
+                "global checksum mismatch, expected {:#06X} but got {:#06X}",
+                expected, actual
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CartridgeError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CartridgeError {
+    fn from(error: io::Error) -> Self {
+        CartridgeError::Io(error)
+    }
+}
+
+/// Game boy color support declared at 0x0143
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CgbSupport {
+    /// Game boy only game
+    None,
+    /// Works on both game boy and game boy color
+    Compatible,
+    /// Game boy color only game
+    Only,
+}
+
+/// Publisher of the game
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Licensee {
+    /// Code at 0x014B
+    Old(u8),
+    /// Two ASCII characters at 0x0144-0x0145
+    New(String),
+}
+
+/// Cartridge header stored at 0x0100-0x014F
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub title: String,
+    pub cgb: CgbSupport,
+    pub sgb: bool,
+    pub cartridge_type: u8,
+    pub rom_size: usize,
+    pub ram_size: usize,
+    pub licensee: Licensee,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+}
+
+impl Header {
+    /// Parse the header of a ROM image
+    pub fn parse(rom: &[u8]) -> Result<Self, CartridgeError> {
+        if rom.len() < HEADER_END {
+            return Err(CartridgeError::Truncated { size: rom.len() });
+        }
+        let cgb = match rom[CGB_FLAG] {
+            0xC0 => CgbSupport::Only,
+            0x80 => CgbSupport::Compatible,
+            _ => CgbSupport::None,
+        };
+        // On CGB cartridges the last byte of the title is the CGB flag
+        let title_end = if cgb == CgbSupport::None { TITLE + 16 } else { CGB_FLAG };
+        let title = rom[TITLE..title_end]
+            .iter()
+            .take_while(|&&byte| byte != 0)
+            .filter(|byte| byte.is_ascii_graphic() || **byte == b' ')
+            .map(|&byte| byte as char)
+            .collect::<String>()
+            .trim_end()
+            .to_string();
+        let licensee = if rom[OLD_LICENSEE] == USE_NEW_LICENSEE {
+            Licensee::New(String::from_utf8_lossy(&rom[NEW_LICENSEE..NEW_LICENSEE + 2]).into_owned())
+        } else {
+            Licensee::Old(rom[OLD_LICENSEE])
+        };
+        Ok(Header {
+            title,
+            cgb,
+            sgb: rom[SGB_FLAG] == 0x03,
+            cartridge_type: rom[CARTRIDGE_TYPE],
+            rom_size: rom_size(rom[ROM_SIZE])?,
+            ram_size: ram_size(rom[RAM_SIZE])?,
+            licensee,
+            version: rom[VERSION],
+            header_checksum: rom[HEADER_CHECKSUM],
+            global_checksum: (rom[GLOBAL_CHECKSUM] as u16) << 8 | rom[GLOBAL_CHECKSUM + 1] as u16,
+        })
+    }
+
+    /// Check if the cartridge type keeps its memory with a battery
+    pub fn has_battery(&self) -> bool {
+        matches!(
+            self.cartridge_type,
+            0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E | 0x20 | 0x22 | 0xFE | 0xFF
+        )
+    }
+}
+
+/// Get the ROM size in bytes of a header size code
+fn rom_size(code: u8) -> Result<usize, CartridgeError> {
+    match code {
+        0x00..=0x08 => Ok((2 * ROM_BANK_SIZE) << code),
+        0x52 => Ok(72 * ROM_BANK_SIZE),
+        0x53 => Ok(80 * ROM_BANK_SIZE),
+        0x54 => Ok(96 * ROM_BANK_SIZE),
+        _ => Err(CartridgeError::UnknownRomSize(code)),
+    }
+}
+
+/// Get the RAM size in bytes of a header size code
+fn ram_size(code: u8) -> Result<usize, CartridgeError> {
+    match code {
+        0x00 => Ok(0),
+        0x01 => Ok(0x800),
+        0x02 => Ok(0x2000),
+        0x03 => Ok(0x8000),
+        0x04 => Ok(0x20000),
+        0x05 => Ok(0x10000),
+        _ => Err(CartridgeError::UnknownRamSize(code)),
+    }
+}
+
+/// Compute the checksum of the header bytes 0x0134-0x014C
+pub fn header_checksum(rom: &[u8]) -> u8 {
+    rom[TITLE..HEADER_CHECKSUM]
+        .iter()
+        .fold(0u8, |checksum, &byte| checksum.wrapping_sub(byte).wrapping_sub(1))
+}
+
+/// Compute the sum of every ROM byte except the global checksum itself
+pub fn global_checksum(rom: &[u8]) -> u16 {
+    rom.iter()
+        .enumerate()
+        .filter(|(address, _)| *address != GLOBAL_CHECKSUM && *address != GLOBAL_CHECKSUM + 1)
+        .fold(0u16, |checksum, (_, &byte)| checksum.wrapping_add(byte as u16))
+}
+
+/// Game boy cartridge, the header and the mapper that owns the ROM and external RAM
+#[derive(Debug)]
+pub struct Cartridge {
+    header: Header,
+    mapper: Box<dyn Mapper>,
+    /// Save file of battery backed cartridges
+    battery: Option<BatterySave>,
+    /// Problems found in the image that don't stop it from running
+    warnings: Vec<CartridgeError>,
+    /// Nothing ran since the last call to save, so dropping doesn't save or report an error again
+    saved: bool,
+}
+
+impl Cartridge {
+    /// Load a .gb or .gbc file, battery backed memory is restored from the .sav file next to it
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, CartridgeError> {
+        Cartridge::load_with_clock(path, RtcClock::Emulated)
+    }
+
+    /// Load a .gb or .gbc file choosing the time source of its real-time clock
+    pub fn load_with_clock<P: AsRef<Path>>(path: P, rtc_clock: RtcClock) -> Result<Self, CartridgeError> {
+        let mut cartridge = Cartridge::from_bytes_with_clock(fs::read(&path)?, rtc_clock)?;
+        if cartridge.header.has_battery() {
+            cartridge.attach_save(save_path(&path))?;
+        }
+        Ok(cartridge)
+    }
+
+    /// Create a cartridge from a ROM image, validating its header and checksums
+    pub fn from_bytes(rom: Vec<u8>) -> Result<Self, CartridgeError> {
+        Cartridge::from_bytes_with_clock(rom, RtcClock::Emulated)
+    }
+
+    /// Create a cartridge from a ROM image choosing the time source of its real-time clock.
+    /// Padded or overdumped images and a wrong global checksum only produce warnings
+    pub fn from_bytes_with_clock(rom: Vec<u8>, rtc_clock: RtcClock) -> Result<Self, CartridgeError> {
+        let header = Header::parse(&rom)?;
+        let checksum = header_checksum(&rom);
+        if checksum != header.header_checksum {
+            return Err(CartridgeError::HeaderChecksum {
+                expected: header.header_checksum,
+                actual: checksum,
+            });
+        }
+        let mut warnings = Vec::new();
+        if rom.len() != header.rom_size {
+            warnings.push(CartridgeError::RomSizeMismatch {
+                expected: header.rom_size,
+                actual: rom.len(),
+            });
+        }
+        let checksum = global_checksum(&rom);
+        if checksum != header.global_checksum {
+            warnings.push(CartridgeError::GlobalChecksum {
+                expected: header.global_checksum,
+                actual: checksum,
+            });
+        }
+        let mapper = new_mapper(&header, rom, rtc_clock)?;
+        Ok(Cartridge {
+            header,
+            mapper,
+            battery: None,
+            warnings,
+            saved: false,
+        })
+    }
+
+    /// Get the problems found in the image that don't stop it from running
+    pub fn warnings(&self) -> &[CartridgeError] {
+        &self.warnings
+    }
+
+    /// Restore the battery backed memory from a .sav file and keep it updated from now on
+    pub fn attach_save<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let battery = BatterySave::new(path);
+        battery.load(self.mapper.as_mut())?;
+        self.battery = Some(battery);
+        Ok(())
+    }
+
+    /// Get the save file of the cartridge
+    pub fn battery(&self) -> Option<&BatterySave> {
+        self.battery.as_ref()
+    }
+
+    /// Set how much emulated time the memory stays dirty before it's saved, None only saves on
+    /// demand and on drop
+    pub fn set_autosave_interval(&mut self, interval: Option<Duration>) {
+        if let Some(battery) = self.battery.as_mut() {
+            battery.set_interval(interval);
+        }
+    }
+
+    /// Write the .sav file now, dropping the cartridge right after won't write it again
+    pub fn save(&mut self) -> io::Result<()> {
+        self.saved = true;
+        match self.battery.as_mut() {
+            Some(battery) => battery.flush(self.mapper.as_ref()),
+            None => Ok(()),
+        }
+    }
+
+    /// Get the parsed header
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Read a byte from the ROM area 0x0000-0x7FFF
+    pub fn read_rom(&self, address: u16) -> u8 {
+        self.mapper.read_rom(address)
+    }
+
+    /// Write to the ROM area 0x0000-0x7FFF
+    pub fn write_rom(&mut self, address: u16, value: u8) {
+        self.mapper.write_rom(address, value);
+    }
+
+    /// Read a byte from the external RAM area, the address is relative to 0xA000
+    pub fn read_ram(&self, address: u16) -> u8 {
+        self.mapper.read_ram(address)
+    }
+
+    /// Write a byte to the external RAM area, the address is relative to 0xA000
+    pub fn write_ram(&mut self, address: u16, value: u8) {
+        if !self.mapper.write_ram(address, value) {
+            return;
+        }
+        if let Some(battery) = self.battery.as_mut() {
+            battery.mark_dirty();
+            self.saved = false;
+        }
+    }
+
+    /// Advance the hardware of the cartridge, the cycles are 4.19MHz clock cycles
+    pub fn step(&mut self, cycles: u32) {
+        self.mapper.step(cycles);
+        self.saved = false;
+        let Some(battery) = self.battery.as_mut() else {
+            return;
+        };
+        // A failed save stays dirty and is retried after another interval
+        if battery.step(cycles) {
+            if let Err(error) = battery.flush(self.mapper.as_ref()) {
+                eprintln!("Could not write {}: {}", battery.path().display(), error);
+            }
+        }
+    }
+
+    /// Observe the rumble motor of the cartridge
+    pub fn set_rumble_callback(&mut self, callback: RumbleCallback) {
+        self.mapper.set_rumble_callback(callback);
+    }
+
+    /// Connect the tilt sensor of the host to the cartridge
+    pub fn set_accelerometer(&mut self, accelerometer: Box<dyn Accelerometer>) {
+        self.mapper.set_accelerometer(accelerometer);
+    }
+
+    /// Connect the infrared port of the host to the cartridge
+    pub fn set_infrared(&mut self, infrared: Box<dyn InfraredPort>) {
+        self.mapper.set_infrared(infrared);
+    }
+}
+
+impl Drop for Cartridge {
+    /// Save on shutdown unless it was just saved, the clock is saved even if the memory is clean
+    fn drop(&mut self) {
+        let Some(battery) = self.battery.as_mut().filter(|_| !self.saved) else {
+            return;
+        };
+        if battery.is_dirty() || self.mapper.rtc().is_some() {
+            if let Err(error) = battery.flush(self.mapper.as_ref()) {
+                eprintln!("Could not write {}: {}", battery.path().display(), error);
+            }
+        }
+    }
+}
+
+/// Build a ROM image with a valid header for the tests
+#[cfg(test)]
+pub(crate) fn test_rom(cartridge_type: u8, rom_size_code: u8, ram_size_code: u8) -> Vec<u8> {
+    let mut rom = vec![0; rom_size(rom_size_code).unwrap()];
+    rom[TITLE..TITLE + 4].copy_from_slice(b"TEST");
+    rom[CARTRIDGE_TYPE] = cartridge_type;
+    rom[ROM_SIZE] = rom_size_code;
+    rom[RAM_SIZE] = ram_size_code;
+    // Tag every bank with its number
+    for (bank, chunk) in rom.chunks_mut(ROM_BANK_SIZE).enumerate().skip(1) {
+        chunk[0] = bank as u8;
+    }
+    rom[HEADER_CHECKSUM] = header_checksum(&rom);
+    let checksum = global_checksum(&rom);
+    rom[GLOBAL_CHECKSUM] = (checksum >> 8) as u8;
+    rom[GLOBAL_CHECKSUM + 1] = checksum as u8;
+    rom
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_header() {
+        let cartridge = Cartridge::from_bytes(test_rom(0x03, 0x02, 0x03)).unwrap();
+        let header = cartridge.header();
+        assert_eq!(header.title, "TEST");
+        assert_eq!(header.cgb, CgbSupport::None);
+        assert_eq!(header.cartridge_type, 0x03);
+        assert_eq!(header.rom_size, 0x20000);
+        assert_eq!(header.ram_size, 0x8000);
+        assert_eq!(header.licensee, Licensee::Old(0));
+    }
+
+    #[test]
+    fn test_mapper_selection() {
+        let mut cartridge = Cartridge::from_bytes(test_rom(0x01, 0x02, 0x00)).unwrap();
+        cartridge.write_rom(0x2000, 0x03);
+        assert_eq!(cartridge.read_rom(0x4000), 3);
+        assert!(matches!(
+            Cartridge::from_bytes(test_rom(0xFD, 0x00, 0x00)),
+            Err(CartridgeError::UnsupportedCartridgeType(0xFD))
+        ));
+    }
+
+    #[test]
+    fn test_truncated() {
+        assert!(matches!(
+            Cartridge::from_bytes(vec![0; 0x100]),
+            Err(CartridgeError::Truncated { size: 0x100 })
+        ));
+        // A ROM shorter than the header says still runs, its banks wrap around
+        let mut rom = test_rom(0x00, 0x01, 0x00);
+        rom.truncate(0x8000);
+        let cartridge = Cartridge::from_bytes(rom).unwrap();
+        assert!(matches!(
+            cartridge.warnings()[0],
+            CartridgeError::RomSizeMismatch { expected: 0x10000, actual: 0x8000 }
+        ));
+    }
+
+    #[test]
+    fn test_checksums() {
+        let mut rom = test_rom(0x00, 0x00, 0x00);
+        rom[TITLE] = b'X';
+        assert!(matches!(
+            Cartridge::from_bytes(rom),
+            Err(CartridgeError::HeaderChecksum { .. })
+        ));
+        let mut rom = test_rom(0x00, 0x00, 0x00);
+        rom[0x2000] = 0x42;
+        let cartridge = Cartridge::from_bytes(rom).unwrap();
+        assert!(matches!(cartridge.warnings(), [CartridgeError::GlobalChecksum { .. }]));
+        assert_eq!(cartridge.read_rom(0x2000), 0x42);
+        assert!(Cartridge::from_bytes(test_rom(0x00, 0x00, 0x00)).unwrap().warnings().is_empty());
+    }
+
+    #[test]
+    fn test_battery_save() {
+        let directory = std::env::temp_dir().join(format!("emulador_gb_save_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let rom_path = directory.join("test.gb");
+        fs::write(&rom_path, test_rom(0x03, 0x00, 0x02)).unwrap();
+        {
+            let mut cartridge = Cartridge::load(&rom_path).unwrap();
+            cartridge.set_autosave_interval(Some(Duration::from_secs(1)));
+            cartridge.write_rom(0x0000, 0x0A);
+            cartridge.write_ram(0x0010, 0x42);
+            assert!(cartridge.battery().unwrap().is_dirty());
+            cartridge.step(4_194_304);
+            assert!(!cartridge.battery().unwrap().is_dirty());
+            // Written when the cartridge is dropped
+            cartridge.write_ram(0x0011, 0x43);
+        }
+        let mut cartridge = Cartridge::load(&rom_path).unwrap();
+        cartridge.write_rom(0x0000, 0x0A);
+        assert_eq!(cartridge.read_ram(0x0010), 0x42);
+        assert_eq!(cartridge.read_ram(0x0011), 0x43);
+        assert_eq!(fs::read(directory.join("test.sav")).unwrap().len(), 0x2000);
+        drop(cartridge);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_disabled_ram_write() {
+        let directory = std::env::temp_dir().join(format!("emulador_gb_disabled_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let rom_path = directory.join("test.gb");
+        fs::write(&rom_path, test_rom(0x03, 0x00, 0x02)).unwrap();
+        let mut cartridge = Cartridge::load(&rom_path).unwrap();
+        cartridge.write_ram(0x0010, 0x42);
+        assert!(!cartridge.battery().unwrap().is_dirty());
+        drop(cartridge);
+        assert!(!directory.join("test.sav").exists());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_save_before_drop() {
+        let directory = std::env::temp_dir().join(format!("emulador_gb_saved_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let rom_path = directory.join("test.gb");
+        fs::write(&rom_path, test_rom(0x10, 0x00, 0x02)).unwrap();
+        let save_path = directory.join("test.sav");
+        let mut cartridge = Cartridge::load(&rom_path).unwrap();
+        cartridge.save().unwrap();
+        assert!(save_path.exists());
+        // The clock was just saved so dropping doesn't write it again
+        fs::remove_file(&save_path).unwrap();
+        drop(cartridge);
+        assert!(!save_path.exists());
+        let mut cartridge = Cartridge::load(&rom_path).unwrap();
+        cartridge.save().unwrap();
+        cartridge.step(4);
+        fs::remove_file(&save_path).unwrap();
+        drop(cartridge);
+        assert!(save_path.exists());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
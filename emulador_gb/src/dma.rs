@@ -0,0 +1,132 @@
+pub const DMA: u16 = 0xFF46; // Source address of the OAM DMA divided by 0x100
+
+const TRANSFER_LENGTH: u8 = 160; // Bytes copied to OAM, one per M-cycle
+const STARTUP_CYCLES: u8 = 2; // M-cycles between the write to DMA and the first byte copied
+
+/// OAM DMA controller, copies 160 bytes from XX00 to OAM while the CPU waits in HRAM
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dma {
+    register: u8,
+    /// Transfer being copied, the source address and the next byte
+    active: Option<(u16, u8)>,
+    /// Transfer requested by the last write, the source address and the M-cycles until it starts.
+    /// A running transfer keeps going until the new one starts
+    pending: Option<(u16, u8)>,
+    /// Last byte read from the source, seen by the CPU when it accesses the same bus
+    value: u8,
+    /// T-cycles that don't add up to an M-cycle yet
+    cycles: u32,
+}
+
+impl Default for Dma {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Dma {
+    pub fn new() -> Self {
+        Dma {
+            register: 0xFF,
+            active: None,
+            pending: None,
+            value: 0xFF,
+            cycles: 0,
+        }
+    }
+
+    /// Read the DMA register, it keeps the last value written
+    pub fn read_register(&self) -> u8 {
+        self.register
+    }
+
+    /// Write the DMA register, starting a transfer from value * 0x100
+    pub fn write_register(&mut self, value: u8) {
+        self.register = value;
+        self.pending = Some(((value as u16) << 8, STARTUP_CYCLES));
+    }
+
+    /// Get the source address of the transfer being copied
+    pub fn source(&self) -> Option<u16> {
+        self.active.map(|(source, _)| source)
+    }
+
+    /// Check if a transfer is being copied, the CPU can only access HRAM meanwhile
+    pub fn is_active(&self) -> bool {
+        self.active.is_some()
+    }
+
+    /// Get the last byte read from the source
+    pub fn value(&self) -> u8 {
+        self.value
+    }
+
+    /// Record the byte read from the source
+    pub fn latch(&mut self, value: u8) {
+        self.value = value;
+    }
+
+    /// Add 4.19MHz clock cycles, returns the M-cycles to tick
+    pub fn advance(&mut self, cycles: u32) -> u32 {
+        self.cycles += cycles;
+        let m_cycles = self.cycles / 4;
+        self.cycles %= 4;
+        m_cycles
+    }
+
+    /// Advance the transfer by one M-cycle, returns the address to read and the OAM offset to
+    /// write when a byte is copied
+    pub fn tick(&mut self) -> Option<(u16, u16)> {
+        let copy = self.active.map(|(source, index)| {
+            self.active = (index + 1 < TRANSFER_LENGTH).then_some((source, index + 1));
+            (source + index as u16, index as u16)
+        });
+        if let Some((source, delay)) = self.pending {
+            if delay == 1 {
+                self.active = Some((source, 0));
+                self.pending = None;
+            } else {
+                self.pending = Some((source, delay - 1));
+            }
+        }
+        copy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transfer_timing() {
+        let mut dma = Dma::new();
+        dma.write_register(0xC1);
+        assert_eq!(dma.read_register(), 0xC1);
+        assert_eq!(dma.tick(), None);
+        assert!(!dma.is_active());
+        assert_eq!(dma.tick(), None);
+        assert!(dma.is_active());
+        for index in 0..TRANSFER_LENGTH as u16 {
+            assert_eq!(dma.tick(), Some((0xC100 + index, index)));
+        }
+        assert!(!dma.is_active());
+        assert_eq!(dma.tick(), None);
+    }
+
+    #[test]
+    fn test_restart() {
+        let mut dma = Dma::new();
+        dma.write_register(0xC0);
+        dma.advance(4 * 12);
+        for _ in 0..12 {
+            dma.tick();
+        }
+        // The old transfer keeps copying while the new one starts up
+        dma.write_register(0xD0);
+        assert_eq!(dma.tick(), Some((0xC00A, 0x0A)));
+        assert_eq!(dma.tick(), Some((0xC00B, 0x0B)));
+        assert_eq!(dma.tick(), Some((0xD000, 0x00)));
+        assert_eq!(dma.advance(7), 1);
+        assert_eq!(dma.advance(1), 1);
+    }
+}
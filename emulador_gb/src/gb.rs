@@ -1,16 +1,19 @@
-use crate::operations::{add, dec, inc, adc, sub, sbc, and, or, xor, cp, add_sp,rlc,rrc,rl,rr,sla, sra};
-
-const MEMORY_SIZE: usize = 65536;
-const ROM_BANK_0: usize = 0x0000; // ROM Bank 0 (32KB) HOME BANK
-const ROM_BANK_1: usize = 0x4000; // ROM Bank 1 (32KB)
-const VRAM: usize = 0x8000; // VRAM (8KB) Background tiles
-const CARTRIDGE_RAM:usize = 0xA000;
-const WORK_RAM: usize = 0xC000; // RAM Bank 0 (8KB)
-// Space not used
-const OAM: usize = 0xFE00; // OAM (Sprites) (160 bytes) also tiles
-//Space not used
-const IO_REGISTERS: usize = 0xFF00; // IO Registros (80 bytes)
-const HIGH_RAM: usize = 0xFF80; // Memoria de alto rendimiento (128 bytes) //Acceso un ciclo mas rapido
+use std::io;
+
+use crate::apu::Apu;
+use crate::audio::AudioSink;
+use crate::cartridge::Cartridge;
+use crate::interrupts::Interrupt;
+use crate::joypad::Button;
+use crate::mbc::{Accelerometer, InfraredPort, RumbleCallback};
+use crate::memory::{Memory, MemoryBus, Model};
+use crate::ppu::{Ppu, Renderer};
+use crate::serial::SerialDevice;
+use crate::timer::DIV;
+use crate::operations::{add, dec, inc, adc, sub, sbc, and, or, xor, cp, add_sp,rlc,rrc,rl,rr,sla, sra, swap, srl, bit, res, set};
+
+const SPEED_SWITCH_CYCLES: u32 = 2050; // M-cycles the CPU is paused while switching speed
+const FRAME_CYCLES: u32 = 17556; // M-cycles of a frame, 154 lines of 456 dots
 
 /// Register of the game boy CPU
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -48,42 +51,60 @@ fn get_flag_bit(flag: Flag) -> u8 {
 
 /// Implement the Register struct, setting the values of the registers to the default start values
 impl Register {
-    fn new() -> Self {
-        Register {
-            a: 0x01,
-            b: 0x00,
-            c: 0x13,
-            d: 0x00,
-            e: 0xD8,
-            h: 0x01,
-            l: 0x4D,
-            f: 0xB0,
-            sp: 0xFFFE,
-            pc: 0x0100,
-        }
-    }
-}
-
-/// TODO game boy memory
-/// Just a placeholder for now
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
-pub struct Memory {
-    data: [u8; MEMORY_SIZE],
-}
-
-/// Implement the Memory struct
-impl Memory {
-    fn new() -> Self {
-        Memory {
-            data: [0; MEMORY_SIZE],
+    /// Get the values left by the boot ROM of a model, games check A to detect the CGB
+    fn new(model: Model) -> Self {
+        match model {
+            Model::Dmg => Register {
+                a: 0x01,
+                b: 0x00,
+                c: 0x13,
+                d: 0x00,
+                e: 0xD8,
+                h: 0x01,
+                l: 0x4D,
+                f: 0xB0,
+                sp: 0xFFFE,
+                pc: 0x0100,
+            },
+            Model::Cgb => Register {
+                a: 0x11,
+                b: 0x00,
+                c: 0x00,
+                d: 0xFF,
+                e: 0x56,
+                h: 0x00,
+                l: 0x0D,
+                f: 0x80,
+                sp: 0xFFFE,
+                pc: 0x0100,
+            },
         }
     }
 }
 
 /// CPU struct, containing the registers and memory
+#[allow(clippy::upper_case_acronyms)]
 pub struct CPU {
     registers: Register,
     memory: Memory,
+    /// Interrupt master enable
+    ime: bool,
+    /// Instructions left until EI takes effect
+    ei_delay: u8,
+    /// HALT mode, left when an interrupt is pending
+    halted: bool,
+    /// STOP mode, left when a joypad line goes low
+    stopped: bool,
+    /// The next fetch doesn't increment PC
+    halt_bug: bool,
+    /// M-cycles left the CPU is paused by a speed switch or by the VRAM DMA
+    stall_cycles: u32,
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 /// Implement the CPU struct
@@ -91,10 +112,219 @@ impl CPU{
     
     /// Create a new CPU struct
     pub fn new() -> Self {
+        Self::with_model(Model::Dmg)
+    }
+
+    /// Create a CPU of a model with the state left by its boot ROM
+    pub fn with_model(model: Model) -> Self {
         CPU {
-            registers: Register::new(),
-            memory: Memory::new(),
+            registers: Register::new(model),
+            memory: Memory::with_model(model),
+            ime: false,
+            ei_delay: 0,
+            halted: false,
+            stopped: false,
+            halt_bug: false,
+            stall_cycles: 0,
+        }
+    }
+
+    /// Create a CPU of the model selected by the CGB flag of a cartridge, with the cartridge
+    /// inserted
+    pub fn with_cartridge(cartridge: Cartridge) -> Self {
+        let mut cpu = Self::with_model(Model::for_cartridge(&cartridge));
+        cpu.load_cartridge(cartridge);
+        cpu
+    }
+
+    /// Get the model being emulated
+    pub fn model(&self) -> Model {
+        self.memory.model()
+    }
+
+    /// Insert a cartridge into the memory bus
+    pub fn load_cartridge(&mut self, cartridge: Cartridge) {
+        self.memory.load_cartridge(cartridge);
+    }
+
+    /// Observe the rumble motor of the inserted cartridge
+    pub fn set_rumble_callback(&mut self, callback: RumbleCallback) {
+        if let Some(cartridge) = self.memory.cartridge_mut() {
+            cartridge.set_rumble_callback(callback);
+        }
+    }
+
+    /// Write the save file of the inserted cartridge
+    pub fn save(&mut self) -> io::Result<()> {
+        match self.memory.cartridge_mut() {
+            Some(cartridge) => cartridge.save(),
+            None => Ok(()),
+        }
+    }
+
+    /// Connect the tilt sensor of the host to the inserted cartridge
+    pub fn set_accelerometer(&mut self, accelerometer: Box<dyn Accelerometer>) {
+        if let Some(cartridge) = self.memory.cartridge_mut() {
+            cartridge.set_accelerometer(accelerometer);
+        }
+    }
+
+    /// Connect the infrared port of the host to the inserted cartridge
+    pub fn set_infrared(&mut self, infrared: Box<dyn InfraredPort>) {
+        if let Some(cartridge) = self.memory.cartridge_mut() {
+            cartridge.set_infrared(infrared);
+        }
+    }
+
+    /// Hold a button of the joypad, a falling line requests the Joypad interrupt and leaves STOP
+    pub fn press(&mut self, button: Button) {
+        self.memory.press(button);
+    }
+
+    /// Let go of a button of the joypad
+    pub fn release(&mut self, button: Button) {
+        self.memory.release(button);
+    }
+
+    /// Plug a device into the link port, like a SerialCapture to read the output of test ROMs
+    pub fn set_serial_device(&mut self, device: Box<dyn SerialDevice>) {
+        self.memory.set_serial_device(device);
+    }
+
+    /// Check if the CPU is in HALT mode
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Check if the CPU is in STOP mode
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// Check if the CPU is running in CGB double speed mode
+    pub fn is_double_speed(&self) -> bool {
+        self.memory.is_double_speed()
+    }
+
+    /// Run the CPU for one step and advance the rest of the hardware by the same time,
+    /// returning the M-cycles used
+    pub fn step(&mut self) -> u8 {
+        let cycles = self.run();
+        // The components are clocked with 4.19MHz cycles, 4 per M-cycle
+        if !self.stopped {
+            self.memory.step(cycles as u32 * 4);
+        } else {
+            self.memory.step_stopped(cycles as u32 * 4);
+        }
+        cycles
+    }
+
+    /// Run until the PPU completes a frame, or for the time of a frame while the LCD is off
+    pub fn run_frame(&mut self) {
+        let frame_count = self.memory.ppu().frame_count();
+        let mut cycles = 0;
+        while self.memory.ppu().frame_count() == frame_count && cycles < FRAME_CYCLES {
+            cycles += self.step() as u32;
+        }
+    }
+
+    /// Get the last complete frame, 160x144 shades from 0 (white) to 3 (black) row by row
+    pub fn frame(&self) -> &[u8] {
+        self.memory.ppu().frame()
+    }
+
+    /// Get the last complete frame, 160x144 RGB555 colors row by row, red in the lowest bits
+    pub fn frame_rgb555(&self) -> &[u16] {
+        self.memory.ppu().frame_rgb555()
+    }
+
+    /// Get the PPU, to inspect its state while debugging
+    pub fn ppu(&self) -> &Ppu {
+        self.memory.ppu()
+    }
+
+    /// Get the PPU mutably, to enable its debugging aids like the sprite log
+    pub fn ppu_mut(&mut self) -> &mut Ppu {
+        self.memory.ppu_mut()
+    }
+
+    /// Get the APU, to inspect its state while debugging
+    pub fn apu(&self) -> &Apu {
+        self.memory.apu()
+    }
+
+    /// Get the APU mutably, to mute or solo channels and record their stems
+    pub fn apu_mut(&mut self) -> &mut Apu {
+        self.memory.apu_mut()
+    }
+
+    /// Set the stereo samples produced per second, None stops producing them
+    pub fn set_sample_rate(&mut self, sample_rate: Option<u32>) {
+        self.memory.apu_mut().set_sample_rate(sample_rate);
+    }
+
+    /// Take the samples produced since the last call, left and right
+    pub fn take_samples(&mut self) -> Vec<[i16; 2]> {
+        self.memory.apu_mut().take_samples()
+    }
+
+    /// Send the samples to a sink like a WAV file instead of keeping them, returns the previous one
+    pub fn set_audio_sink(&mut self, sink: Option<Box<dyn AudioSink>>) -> Option<Box<dyn AudioSink>> {
+        self.memory.apu_mut().set_audio_sink(sink)
+    }
+
+    /// Choose between the fast scanline renderer and the accurate pixel FIFO
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.memory.ppu_mut().set_renderer(renderer);
+    }
+
+    /// Service a pending interrupt or execute the next instruction, returning the cycles used
+    fn run(&mut self) -> u8 {
+        // The VRAM DMA started by the last instruction or the last HBlank stalls the CPU
+        self.stall_cycles += self.memory.take_stall_cycles();
+        if self.stall_cycles > 0 {
+            let cycles = self.stall_cycles.min(u8::MAX as u32);
+            self.stall_cycles -= cycles;
+            return cycles as u8;
+        }
+        if self.stopped {
+            // The P1 lines wake the CPU, IF may still hold an old joypad request
+            if !self.memory.joypad().is_line_low() {
+                return 1;
+            }
+            self.stopped = false;
+        }
+        if self.halted {
+            // HALT is left on any pending interrupt, even with IME clear
+            if self.memory.pending_interrupts() == 0 {
+                return 1;
+            }
+            self.halted = false;
+        }
+        if let Some(cycles) = self.handle_interrupts() {
+            return cycles;
         }
+        let cycles = self.execute();
+        if self.ei_delay > 0 {
+            self.ei_delay -= 1;
+            if self.ei_delay == 0 {
+                self.ime = true;
+            }
+        }
+        cycles
+    }
+
+    /// Jump to the vector of the highest priority interrupt if IME is set
+    fn handle_interrupts(&mut self) -> Option<u8> {
+        if !self.ime {
+            return None;
+        }
+        let interrupt = Interrupt::highest(self.memory.pending_interrupts())?;
+        self.ime = false;
+        self.memory.clear_interrupt(interrupt);
+        self.push(self.registers.pc);
+        self.registers.pc = interrupt.vector();
+        Some(5)
     }
 
     /// Get the value of a flag
@@ -157,35 +387,40 @@ impl CPU{
 
     /// Get the value of the next instruction
     fn next_instruction(&mut self) -> u8 {
-        let instruction: u8 = self.memory.data[self.registers.pc as usize];
-        self.registers.pc = self.registers.pc.wrapping_add(1);
+        let instruction: u8 = self.memory.read8(self.registers.pc);
+        if self.halt_bug {
+            // HALT bug, the byte after HALT is read twice
+            self.halt_bug = false;
+        } else {
+            self.registers.pc = self.registers.pc.wrapping_add(1);
+        }
         instruction
     }
 
     /// Get the value of the next two instructions
     fn read_word(&mut self) -> u16 {
-        let instruction: u16 = self.memory.data[self.registers.pc as usize] as u16 | (self.memory.data[self.registers.pc.wrapping_add(1) as usize] as u16) << 8;
+        let instruction: u16 = self.memory.read16(self.registers.pc);
         self.registers.pc = self.registers.pc.wrapping_add(2);
         instruction
     }
 
     /// Get the value of the ram
     fn pop(&mut self) -> u16 {
-        let value = self.memory.data[self.registers.sp as usize] as u16 | (self.memory.data[self.registers.sp.wrapping_add(1) as usize] as u16) << 8;
-        self.registers.sp = self.registers.sp.wrapping_add(1);
+        let value = self.memory.read16(self.registers.sp);
+        self.registers.sp = self.registers.sp.wrapping_add(2);
         value
     }
 
     /// Set the value of the ram
     fn push(&mut self, value: u16){
         self.registers.sp = self.registers.sp.wrapping_sub(1);
-        self.memory.data[self.registers.sp as usize] = (value >> 8) as u8;
+        self.memory.write8(self.registers.sp, (value >> 8) as u8);
         self.registers.sp = self.registers.sp.wrapping_sub(1);
-        self.memory.data[self.registers.sp as usize] = (value & 0xFF) as u8;
+        self.memory.write8(self.registers.sp, (value & 0xFF) as u8);
     }
 
     /// Execute the next instruction
-    fn execute(&mut self) -> u8{
+    pub fn execute(&mut self) -> u8{
         match self.next_instruction() {
             0x00 => {
                 // NOP
@@ -199,7 +434,7 @@ impl CPU{
                             },
             0x02 => {
                 // LD (BC), A
-                self.memory.data[self.get_bc() as usize] = self.registers.a;
+                self.memory.write8(self.get_bc(), self.registers.a);
                 2
                             },
             0x03 => {
@@ -244,8 +479,8 @@ impl CPU{
             0x08 => {
                 // LD (a16), SP
                 let address = self.next_instruction() as u16 | (self.next_instruction() as u16) << 8;
-                self.memory.data[address as usize] = self.registers.sp as u8;
-                self.memory.data[(address + 1) as usize] = (self.registers.sp >> 8) as u8;
+                self.memory.write8(address, self.registers.sp as u8);
+                self.memory.write8(address.wrapping_add(1), (self.registers.sp >> 8) as u8);
                 5
                             },
             0x09 => {
@@ -259,7 +494,7 @@ impl CPU{
                             },
             0x0A => {
                 // LD A, (BC)
-                self.registers.a = self.memory.data[self.get_bc() as usize];
+                self.registers.a = self.memory.read8(self.get_bc());
                 2
                             },
             0x0B => {
@@ -302,13 +537,15 @@ impl CPU{
             },
             0x10 => {
                 // STOP
-                // TODO: Implement Execution of a STOP instruction stops both the system clock and oscillator circuit.
-                // STOP mode is entered and the LCD controller also stops. However, the status of the internal RAM register ports remains unchanged.
-                // STOP mode can be cancelled by a reset signal.
-                // If the RESET terminal goes LOW in STOP mode, it becomes that of a normal reset status.
-                // The following conditions should be met before a STOP instruction is executed and stop mode is entered:
-                // All interrupt-enable (IE) flags are reset.
-                // Input to P10-P13 is LOW for all.
+                // STOP is followed by a padding byte that is skipped
+                self.next_instruction();
+                self.memory.write8(DIV, 0);
+                if self.memory.switch_speed() {
+                    // A prepared speed switch is performed instead of entering STOP mode
+                    self.stall_cycles = SPEED_SWITCH_CYCLES;
+                    return 1
+                }
+                self.stopped = true;
                 1
             },
             0x11 => {
@@ -319,7 +556,7 @@ impl CPU{
             },
             0x12 => {
                 // LD (DE), A
-                self.memory.data[self.get_de() as usize] = self.registers.a;
+                self.memory.write8(self.get_de(), self.registers.a);
                 2
             },
             0x13 => {
@@ -362,7 +599,7 @@ impl CPU{
             },
             0x18 => {
                 // JR s8
-                let offset = self.next_instruction() as u8 as u16;
+                let offset = self.next_instruction() as i8 as u16;
                 self.registers.pc = self.registers.pc.wrapping_add(offset);
                 3
             },
@@ -377,7 +614,7 @@ impl CPU{
             },
             0x1A => {
                 // LD A, (DE)
-                self.registers.a = self.memory.data[self.get_de() as usize];
+                self.registers.a = self.memory.read8(self.get_de());
                 2
             },
             0x1B => {
@@ -421,7 +658,7 @@ impl CPU{
             },
             0x20 => {
                 // JR NZ, s8
-                let offset = self.next_instruction() as u8 as u16;
+                let offset = self.next_instruction() as i8 as u16;
                 if !self.get_flag(Flag::Z) {
                     self.registers.pc = self.registers.pc.wrapping_add(offset);
                     return 3
@@ -436,7 +673,7 @@ impl CPU{
             },
             0x22 => {
                 // LDI (HL), A
-                self.memory.data[self.get_hl() as usize] = self.registers.a;
+                self.memory.write8(self.get_hl(), self.registers.a);
                 self.set_hl(self.get_hl().wrapping_add(1));
                 2
             },
@@ -491,7 +728,7 @@ impl CPU{
             },
             0x28 => {
                 // JR Z, s8
-                let offset = self.next_instruction() as u8 as u16;
+                let offset = self.next_instruction() as i8 as u16;
                 if self.get_flag(Flag::Z) {
                     self.registers.pc = self.registers.pc.wrapping_add(offset);
                     return 3
@@ -509,7 +746,7 @@ impl CPU{
             },
             0x2A => {
                 // LDI A, (HL)
-                self.registers.a = self.memory.data[self.get_hl() as usize];
+                self.registers.a = self.memory.read8(self.get_hl());
                 self.set_hl(self.get_hl().wrapping_add(1));
                 2
             },
@@ -551,7 +788,7 @@ impl CPU{
             },
             0x30 => {
                 // JR NC, s8
-                let offset = self.next_instruction() as u8 as u16;
+                let offset = self.next_instruction() as i8 as u16;
                 if !self.get_flag(Flag::C) {
                     self.registers.pc = self.registers.pc.wrapping_add(offset);
                     return 3
@@ -566,7 +803,7 @@ impl CPU{
             },
             0x32 => {
                 // LDD (HL), A
-                self.memory.data[self.get_hl() as usize] = self.registers.a;
+                self.memory.write8(self.get_hl(), self.registers.a);
                 self.set_hl(self.get_hl().wrapping_sub(1));
                 2
             },
@@ -577,8 +814,8 @@ impl CPU{
             },
             0x34 => {
                 // INC (HL)
-                let result = inc(self.memory.data[self.get_hl() as usize]);
-                self.memory.data[self.get_hl() as usize] = result.value;
+                let result = inc(self.memory.read8(self.get_hl()));
+                self.memory.write8(self.get_hl(), result.value);
                 self.set_flag(Flag::Z, result.zero.unwrap());
                 self.set_flag(Flag::N, result.add_sub.unwrap());
                 self.set_flag(Flag::H, result.half_carry.unwrap());
@@ -586,8 +823,8 @@ impl CPU{
             },
             0x35 => {
                 // DEC (HL)
-                let result = dec(self.memory.data[self.get_hl() as usize]);
-                self.memory.data[self.get_hl() as usize] = result.value;
+                let result = dec(self.memory.read8(self.get_hl()));
+                self.memory.write8(self.get_hl(), result.value);
                 self.set_flag(Flag::Z, result.zero.unwrap());
                 self.set_flag(Flag::N, result.add_sub.unwrap());
                 self.set_flag(Flag::H, result.half_carry.unwrap());
@@ -595,7 +832,8 @@ impl CPU{
             },
             0x36 => {
                 // LD (HL), d8
-                self.memory.data[self.get_hl() as usize] = self.next_instruction();
+                let value = self.next_instruction();
+                self.memory.write8(self.get_hl(), value);
                 3
             },
             0x37 => {
@@ -607,7 +845,7 @@ impl CPU{
             },
             0x38 => {
                 // JR C, s8
-                let offset = self.next_instruction() as u8 as u16;
+                let offset = self.next_instruction() as i8 as u16;
                 if self.get_flag(Flag::C) {
                     self.registers.pc = self.registers.pc.wrapping_add(offset);
                     return 3
@@ -625,7 +863,7 @@ impl CPU{
             },
             0x3A => {
                 // LDD A, (HL)
-                self.registers.a = self.memory.data[self.get_hl() as usize];
+                self.registers.a = self.memory.read8(self.get_hl());
                 self.set_hl(self.get_hl().wrapping_sub(1));
                 2
             },
@@ -667,7 +905,7 @@ impl CPU{
             },
             0x40 => {
                 // LD B, B
-                self.registers.b = self.registers.b;
+                // No operation, the register keeps its value
                 1
             },
             0x41 => {
@@ -697,7 +935,7 @@ impl CPU{
             },
             0x46 => {
                 // LD B, (HL)
-                self.registers.b = self.memory.data[self.get_hl() as usize];
+                self.registers.b = self.memory.read8(self.get_hl());
                 2
             },
             0x47 => {
@@ -712,7 +950,7 @@ impl CPU{
             },
             0x49 => {
                 // LD C, C
-                self.registers.c = self.registers.c;
+                // No operation, the register keeps its value
                 1
             },
             0x4A => {
@@ -737,7 +975,7 @@ impl CPU{
             },
             0x4E => {
                 // LD C, (HL)
-                self.registers.c = self.memory.data[self.get_hl() as usize];
+                self.registers.c = self.memory.read8(self.get_hl());
                 2
             },
             0x4F => {
@@ -757,7 +995,7 @@ impl CPU{
             },
             0x52 => {
                 // LD D, D
-                self.registers.d = self.registers.d;
+                // No operation, the register keeps its value
                 1
             },
             0x53 => {
@@ -777,7 +1015,7 @@ impl CPU{
             },
             0x56 => {
                 // LD D, (HL)
-                self.registers.d = self.memory.data[self.get_hl() as usize];
+                self.registers.d = self.memory.read8(self.get_hl());
                 2
             },
             0x57 => {
@@ -802,7 +1040,7 @@ impl CPU{
             },
             0x5B => {
                 // LD E, E
-                self.registers.e = self.registers.e;
+                // No operation, the register keeps its value
                 1
             },
             0x5C => {
@@ -817,7 +1055,7 @@ impl CPU{
             },
             0x5E => {
                 // LD E, (HL)
-                self.registers.e = self.memory.data[self.get_hl() as usize];
+                self.registers.e = self.memory.read8(self.get_hl());
                 2
             },
             0x5F => {
@@ -847,7 +1085,7 @@ impl CPU{
             },
             0x64 => {
                 // LD H, H
-                self.registers.h = self.registers.h;
+                // No operation, the register keeps its value
                 1
             },
             0x65 => {
@@ -857,7 +1095,7 @@ impl CPU{
             },
             0x66 => {
                 // LD H, (HL)
-                self.registers.h = self.memory.data[self.get_hl() as usize];
+                self.registers.h = self.memory.read8(self.get_hl());
                 2
             },
             0x67 => {
@@ -892,12 +1130,12 @@ impl CPU{
             },
             0x6D => {
                 // LD L, L
-                self.registers.l = self.registers.l;
+                // No operation, the register keeps its value
                 1
             },
             0x6E => {
                 // LD L, (HL)
-                self.registers.l = self.memory.data[self.get_hl() as usize];
+                self.registers.l = self.memory.read8(self.get_hl());
                 2
             },
             0x6F => {
@@ -907,54 +1145,48 @@ impl CPU{
             },
             0x70 => {
                 // LD (HL), B
-                self.memory.data[self.get_hl() as usize] = self.registers.b;
+                self.memory.write8(self.get_hl(), self.registers.b);
                 2
             },
             0x71 => {
                 // LD (HL), C
-                self.memory.data[self.get_hl() as usize] = self.registers.c;
+                self.memory.write8(self.get_hl(), self.registers.c);
                 2
             },
             0x72 => {
                 // LD (HL), D
-                self.memory.data[self.get_hl() as usize] = self.registers.d;
+                self.memory.write8(self.get_hl(), self.registers.d);
                 2
             },
             0x73 => {
                 // LD (HL), E
-                self.memory.data[self.get_hl() as usize] = self.registers.e;
+                self.memory.write8(self.get_hl(), self.registers.e);
                 2
             },
             0x74 => {
                 // LD (HL), H
-                self.memory.data[self.get_hl() as usize] = self.registers.h;
+                self.memory.write8(self.get_hl(), self.registers.h);
                 2
             },
             0x75 => {
                 // LD (HL), L
-                self.memory.data[self.get_hl() as usize] = self.registers.l;
+                self.memory.write8(self.get_hl(), self.registers.l);
                 2
             },
             0x76 =>{
                 // HALT
-                //TODO After a HALT instruction is executed, the system clock is stopped and HALT mode is entered. Although the system clock is stopped in this status, the oscillator circuit and LCD controller continue to operate.
-
-                // In addition, the status of the internal RAM register ports remains unchanged.
-
-                // HALT mode is cancelled by an interrupt or reset signal.
-
-                // The program counter is halted at the step after the HALT instruction. If both the interrupt request flag and the corresponding interrupt enable flag are set, HALT mode is exited, even if the interrupt master enable flag is not set.
-
-                // Once HALT mode is cancelled, the program starts from the address indicated by the program counter.
-
-                // If the interrupt master enable flag is set, the contents of the program coounter are pushed to the stack and control jumps to the starting address of the interrupt.
-
-                // If the RESET terminal goes LOW in HALT mode, the mode becomes that of a normal reset.
+                // HALT mode is entered until an interrupt is pending. With IME clear and an
+                // interrupt already pending HALT is not entered and the HALT bug is triggered
+                if !self.ime && self.memory.pending_interrupts() != 0 {
+                    self.halt_bug = true;
+                } else {
+                    self.halted = true;
+                }
                 1
             },
             0x77 => {
                 // LD (HL), A
-                self.memory.data[self.get_hl() as usize] = self.registers.a;
+                self.memory.write8(self.get_hl(), self.registers.a);
                 2
             },
             0x78 => {
@@ -989,12 +1221,12 @@ impl CPU{
             },
             0x7E => {
                 // LD A, (HL)
-                self.registers.a = self.memory.data[self.get_hl() as usize];
+                self.registers.a = self.memory.read8(self.get_hl());
                 1
             },
             0x7F => {
                 // LD A, A
-                self.registers.a = self.registers.a;
+                // No operation, the register keeps its value
                 1
             },
             0x80 => {
@@ -1059,7 +1291,7 @@ impl CPU{
             },
             0x86 => {
                 // ADD A, (HL)
-                let result = add(self.registers.a, self.memory.data[self.get_hl() as usize]);
+                let result = add(self.registers.a, self.memory.read8(self.get_hl()));
                 self.registers.a = result.value;
                 self.set_flag(Flag::Z, result.zero.unwrap());
                 self.set_flag(Flag::N, false);
@@ -1139,7 +1371,7 @@ impl CPU{
             },
             0x8E => {
                 // ADC A, (HL)
-                let result = adc(self.registers.a, self.memory.data[self.get_hl() as usize], self.get_flag(Flag::C));
+                let result = adc(self.registers.a, self.memory.read8(self.get_hl()), self.get_flag(Flag::C));
                 self.registers.a = result.value;
                 self.set_flag(Flag::Z, result.zero.unwrap());
                 self.set_flag(Flag::N, false);
@@ -1219,7 +1451,7 @@ impl CPU{
             },
             0x96 =>{
                 // SUB (HL)
-                let result = sub(self.registers.a, self.memory.data[self.get_hl() as usize]);
+                let result = sub(self.registers.a, self.memory.read8(self.get_hl()));
                 self.registers.a = result.value;
                 self.set_flag(Flag::Z, result.zero.unwrap());
                 self.set_flag(Flag::N, true);
@@ -1299,7 +1531,7 @@ impl CPU{
             },
             0x9E => {
                 // SBC A, (HL)
-                let result = sbc(self.registers.a, self.memory.data[self.get_hl() as usize], self.get_flag(Flag::C));
+                let result = sbc(self.registers.a, self.memory.read8(self.get_hl()), self.get_flag(Flag::C));
                 self.registers.a = result.value;
                 self.set_flag(Flag::Z, result.zero.unwrap());
                 self.set_flag(Flag::N, true);
@@ -1379,7 +1611,7 @@ impl CPU{
             },
             0xA6 => {
                 // AND (HL)
-                let result = and(self.registers.a, self.memory.data[self.get_hl() as usize]);
+                let result = and(self.registers.a, self.memory.read8(self.get_hl()));
                 self.registers.a = result.value;
                 self.set_flag(Flag::Z, result.zero.unwrap());
                 self.set_flag(Flag::N, false);
@@ -1459,7 +1691,7 @@ impl CPU{
             },
             0xAE => {
                 // XOR (HL)
-                let result = xor(self.registers.a, self.memory.data[self.get_hl() as usize]);
+                let result = xor(self.registers.a, self.memory.read8(self.get_hl()));
                 self.registers.a = result.value;
                 self.set_flag(Flag::Z, result.zero.unwrap());
                 self.set_flag(Flag::N, false);
@@ -1539,7 +1771,7 @@ impl CPU{
             },
             0xB6 => {
                 // OR (HL)
-                let result = or(self.registers.a, self.memory.data[self.get_hl() as usize]);
+                let result = or(self.registers.a, self.memory.read8(self.get_hl()));
                 self.registers.a = result.value;
                 self.set_flag(Flag::Z, result.zero.unwrap());
                 self.set_flag(Flag::N, false);
@@ -1613,7 +1845,7 @@ impl CPU{
             },
             0xBE => {
                 // CP (HL)
-                let result = cp(self.registers.a, self.memory.data[self.get_hl() as usize]);
+                let result = cp(self.registers.a, self.memory.read8(self.get_hl()));
                 self.set_flag(Flag::Z, result.zero.unwrap());
                 self.set_flag(Flag::N, true);
                 self.set_flag(Flag::H, result.half_carry.unwrap());
@@ -1675,7 +1907,7 @@ impl CPU{
             },
             0xC6 => {
                 // ADD A, d8
-                let value = add(self.registers.a,self.memory.data[self.registers.sp as usize]);
+                let value = add(self.registers.a,self.memory.read8(self.registers.sp));
                 self.registers.a = value.value;
                 self.set_flag(Flag::Z,value.zero.unwrap());
                 self.set_flag(Flag::N,false);
@@ -1713,7 +1945,7 @@ impl CPU{
                 3
             },
             0xCB => {
-                // PREFIX CB TODO
+                // PREFIX CB
                 let opcode = self.next_instruction();
                 self.execute_cb_instruction(opcode)
             },
@@ -1736,7 +1968,7 @@ impl CPU{
             },
             0xCE => {
                 // ADC A, d8
-                let value = adc(self.registers.a,self.memory.data[self.registers.sp as usize],self.get_flag(Flag::C));
+                let value = adc(self.registers.a,self.memory.read8(self.registers.sp),self.get_flag(Flag::C));
                 self.registers.a = value.value;
                 self.set_flag(Flag::Z,value.zero.unwrap());
                 self.set_flag(Flag::N,false);
@@ -1790,7 +2022,7 @@ impl CPU{
             },
             0xD6 => {
                 // SUB d8
-                let value = sub(self.registers.a,self.memory.data[self.registers.sp as usize]);
+                let value = sub(self.registers.a,self.memory.read8(self.registers.sp));
                 self.registers.a = value.value;
                 self.set_flag(Flag::Z,value.zero.unwrap());
                 self.set_flag(Flag::N,true);
@@ -1814,12 +2046,10 @@ impl CPU{
             },
             0xD9 => {
                 // RETI
-                // TODO
-                // Used when an interrupt-service routine finishes.
-                // The address for the return from the interrupt is loaded in the program counter PC. The master interrupt enable flag is returned to its pre-interrupt status.
-                // The contents of the address specified by the stack pointer SP are loaded in the lower-order byte of PC, and the contents of SP are incremented by 1. 
-                //The contents of the address specified by the new SP value are then loaded in the higher-order byte of PC, and the contents of SP are incremented by 1 again. 
-                //(THe value of SP is 2 larger than before instruction execution.) The next instruction is fetched from the address specified by the content of PC (as usual)
+                // Return from an interrupt-service routine, IME is set without the EI delay
+                self.registers.pc = self.pop();
+                self.ime = true;
+                self.ei_delay = 0;
                 4
             },
             0xDA => {
@@ -1843,7 +2073,7 @@ impl CPU{
             },
             0xDE => {
                 // SBC A, d8
-                let value = sbc(self.registers.a,self.memory.data[self.registers.sp as usize],self.get_flag(Flag::C));
+                let value = sbc(self.registers.a,self.memory.read8(self.registers.sp),self.get_flag(Flag::C));
                 self.registers.a = value.value;
                 self.set_flag(Flag::Z,value.zero.unwrap());
                 self.set_flag(Flag::N,true);
@@ -1860,7 +2090,7 @@ impl CPU{
             0xE0 => {
                 // LDH (a8), A
                 let address = 0xFF00 + self.next_instruction() as u16;
-                self.memory.data[address as usize] = self.registers.a;
+                self.memory.write8(address, self.registers.a);
                 3
             },
             0xE1 => {
@@ -1872,7 +2102,7 @@ impl CPU{
             0xE2 => {
                 // LD (C), A
                 let address = 0xFF00 + self.registers.c as u16;
-                self.memory.data[address as usize] = self.registers.a;
+                self.memory.write8(address, self.registers.a);
                 2
             },
             0xE5 => {
@@ -1882,7 +2112,7 @@ impl CPU{
             },
             0xE6 => {
                 // AND d8
-                let value = and(self.registers.a,self.memory.data[self.registers.sp as usize]);
+                let value = and(self.registers.a,self.memory.read8(self.registers.sp));
                 self.registers.a = value.value;
                 self.set_flag(Flag::Z,value.zero.unwrap());
                 self.set_flag(Flag::N,false);
@@ -1898,7 +2128,7 @@ impl CPU{
             },
             0xE8 => {
                 // ADD SP, r8
-                let value = self.memory.data[self.registers.sp as usize];
+                let value = self.memory.read8(self.registers.sp);
                 let result = add_sp(self.registers.sp,value);
                 self.registers.sp = result.value;
                 self.set_flag(Flag::Z,result.zero.unwrap());
@@ -1915,12 +2145,12 @@ impl CPU{
             0xEA => {
                 // LD (a16), A
                 let address = self.read_word();
-                self.memory.data[address as usize] = self.registers.a;
+                self.memory.write8(address, self.registers.a);
                 4
             },
             0xEE => {
                 // XOR d8
-                let value = xor(self.registers.a,self.memory.data[self.registers.sp as usize]);
+                let value = xor(self.registers.a,self.memory.read8(self.registers.sp));
                 self.registers.a = value.value;
                 self.set_flag(Flag::Z,value.zero.unwrap());
                 self.set_flag(Flag::N,false);
@@ -1937,7 +2167,7 @@ impl CPU{
             0xF0 => {
                 // LDH A, (a8)
                 let address = 0xFF00 + self.next_instruction() as u16;
-                self.registers.a = self.memory.data[address as usize];
+                self.registers.a = self.memory.read8(address);
                 3
             },
             0xF1 => {
@@ -1949,15 +2179,14 @@ impl CPU{
             0xF2 => {
                 // LD A, (C)
                 let address = 0xFF00 + self.registers.c as u16;
-                self.registers.a = self.memory.data[address as usize];
+                self.registers.a = self.memory.read8(address);
                 2
             },
             0xF3 => {
                 // DI
-                // TODO
-                // Disables interrupts but not immediately. Interrupts are disabled after instruction after DI is executed.
-                // The DI instruction disables maskable interrupts but not non-maskable interrupts. 
-                // The interrupt enable flag is reset to 0. The next instruction is fetched from the address specified by the content of the program counter PC.
+                // Disables interrupts immediately and cancels a pending EI
+                self.ime = false;
+                self.ei_delay = 0;
                 1
             },
             0xF5 => {
@@ -1967,7 +2196,7 @@ impl CPU{
             },
             0xF6 => {
                 // OR d8
-                let value = or(self.registers.a,self.memory.data[self.registers.sp as usize]);
+                let value = or(self.registers.a,self.memory.read8(self.registers.sp));
                 self.registers.a = value.value;
                 self.set_flag(Flag::Z,value.zero.unwrap());
                 self.set_flag(Flag::N,false);
@@ -1983,7 +2212,7 @@ impl CPU{
             },
             0xF8 => {
                 // LD HL, SP+r8
-                let value = self.memory.data[self.registers.sp as usize];
+                let value = self.memory.read8(self.registers.sp);
                 let result = add_sp(self.registers.sp,value);
                 self.set_hl(result.value);
                 self.set_flag(Flag::Z,result.zero.unwrap());
@@ -2000,20 +2229,20 @@ impl CPU{
             0xFA => {
                 // LD A, (a16)
                 let address = self.read_word();
-                self.registers.a = self.memory.data[address as usize];
+                self.registers.a = self.memory.read8(address);
                 4
             },
             0xFB => {
                 // EI
-                // TODO
-                // Enables interrupts but not immediately. Interrupts are enabled after instruction after
-                // EI
-                // is executed. The EI instruction enables maskable interrupts. The interrupt enable flag is set to 1. The next instruction is fetched from the address specified by the content of the program counter PC.
+                // Interrupts are enabled after the instruction following EI is executed
+                if !self.ime && self.ei_delay == 0 {
+                    self.ei_delay = 2;
+                }
                 1
             },
             0xFE => {
                 // CP d8
-                let value = cp(self.registers.a,self.memory.data[self.registers.sp as usize]);
+                let value = cp(self.registers.a,self.memory.read8(self.registers.sp));
                 self.set_flag(Flag::Z,value.zero.unwrap());
                 self.set_flag(Flag::N,true);
                 self.set_flag(Flag::H,value.half_carry.unwrap());
@@ -2098,8 +2327,8 @@ impl CPU{
             },
             0x06 => {
                 // RLC (HL)
-                let value = rlc(self.memory.data[self.get_hl() as usize]);
-                self.memory.data[self.get_hl() as usize] = value.value;
+                let value = rlc(self.memory.read8(self.get_hl()));
+                self.memory.write8(self.get_hl(), value.value);
                 self.set_flag(Flag::Z,value.zero.unwrap());
                 self.set_flag(Flag::N,false);
                 self.set_flag(Flag::H,false);
@@ -2178,8 +2407,8 @@ impl CPU{
             },
             0x0E => {
                 // RRC (HL)
-                let value = rrc(self.memory.data[self.get_hl() as usize]);
-                self.memory.data[self.get_hl() as usize] = value.value;
+                let value = rrc(self.memory.read8(self.get_hl()));
+                self.memory.write8(self.get_hl(), value.value);
                 self.set_flag(Flag::Z,value.zero.unwrap());
                 self.set_flag(Flag::N,false);
                 self.set_flag(Flag::H,false);
@@ -2258,8 +2487,8 @@ impl CPU{
             },
             0x16 => {
                 // RL HL
-                let value = rl(self.memory.data[self.get_hl() as usize],self.registers.f);
-                self.memory.data[self.get_hl() as usize] = value.value;
+                let value = rl(self.memory.read8(self.get_hl()),self.registers.f);
+                self.memory.write8(self.get_hl(), value.value);
                 self.set_flag(Flag::Z,value.zero.unwrap());
                 self.set_flag(Flag::N,false);
                 self.set_flag(Flag::H,false);
@@ -2338,8 +2567,8 @@ impl CPU{
             },
             0x1E => {
                 // RR (HL)
-                let value = rr(self.memory.data[self.get_hl() as usize],self.registers.f);
-                self.memory.data[self.get_hl() as usize] = value.value;
+                let value = rr(self.memory.read8(self.get_hl()),self.registers.f);
+                self.memory.write8(self.get_hl(), value.value);
                 self.set_flag(Flag::Z,value.zero.unwrap());
                 self.set_flag(Flag::N,false);
                 self.set_flag(Flag::H,false);
@@ -2418,8 +2647,8 @@ impl CPU{
             },
             0x26 => {
                 // SLA (HL)
-                let value = sla(self.memory.data[self.get_hl() as usize]);
-                self.memory.data[self.get_hl() as usize] = value.value;
+                let value = sla(self.memory.read8(self.get_hl()));
+                self.memory.write8(self.get_hl(), value.value);
                 self.set_flag(Flag::Z,value.zero.unwrap());
                 self.set_flag(Flag::N,false);
                 self.set_flag(Flag::H,false);
@@ -2498,8 +2727,8 @@ impl CPU{
             },
             0x2E => {
                 // SRA (HL)
-                let value = sra(self.memory.data[self.get_hl() as usize]);
-                self.memory.data[self.get_hl() as usize] = value.value;
+                let value = sra(self.memory.read8(self.get_hl()));
+                self.memory.write8(self.get_hl(), value.value);
                 self.set_flag(Flag::Z,value.zero.unwrap());
                 self.set_flag(Flag::N,false);
                 self.set_flag(Flag::H,false);
@@ -2516,11 +2745,1744 @@ impl CPU{
                 self.set_flag(Flag::C,value.carry.unwrap());
                 2
             },
-
-            _ => {
-                // Unhandled instruction
-                panic!("This should not panic");
-            }
-        }
+            0x30 => {
+                // SWAP B
+                let value = swap(self.registers.b);
+                self.registers.b = value.value;
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,false);
+                self.set_flag(Flag::C,value.carry.unwrap());
+                2
+            },
+            0x31 => {
+                // SWAP C
+                let value = swap(self.registers.c);
+                self.registers.c = value.value;
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,false);
+                self.set_flag(Flag::C,value.carry.unwrap());
+                2
+            },
+            0x32 => {
+                // SWAP D
+                let value = swap(self.registers.d);
+                self.registers.d = value.value;
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,false);
+                self.set_flag(Flag::C,value.carry.unwrap());
+                2
+            },
+            0x33 => {
+                // SWAP E
+                let value = swap(self.registers.e);
+                self.registers.e = value.value;
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,false);
+                self.set_flag(Flag::C,value.carry.unwrap());
+                2
+            },
+            0x34 => {
+                // SWAP H
+                let value = swap(self.registers.h);
+                self.registers.h = value.value;
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,false);
+                self.set_flag(Flag::C,value.carry.unwrap());
+                2
+            },
+            0x35 => {
+                // SWAP L
+                let value = swap(self.registers.l);
+                self.registers.l = value.value;
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,false);
+                self.set_flag(Flag::C,value.carry.unwrap());
+                2
+            },
+            0x36 => {
+                // SWAP (HL)
+                let value = swap(self.memory.read8(self.get_hl()));
+                self.memory.write8(self.get_hl(), value.value);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,false);
+                self.set_flag(Flag::C,value.carry.unwrap());
+                4
+            },
+            0x37 => {
+                // SWAP A
+                let value = swap(self.registers.a);
+                self.registers.a = value.value;
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,false);
+                self.set_flag(Flag::C,value.carry.unwrap());
+                2
+            },
+            0x38 => {
+                // SRL B
+                let value = srl(self.registers.b);
+                self.registers.b = value.value;
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,false);
+                self.set_flag(Flag::C,value.carry.unwrap());
+                2
+            },
+            0x39 => {
+                // SRL C
+                let value = srl(self.registers.c);
+                self.registers.c = value.value;
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,false);
+                self.set_flag(Flag::C,value.carry.unwrap());
+                2
+            },
+            0x3A => {
+                // SRL D
+                let value = srl(self.registers.d);
+                self.registers.d = value.value;
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,false);
+                self.set_flag(Flag::C,value.carry.unwrap());
+                2
+            },
+            0x3B => {
+                // SRL E
+                let value = srl(self.registers.e);
+                self.registers.e = value.value;
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,false);
+                self.set_flag(Flag::C,value.carry.unwrap());
+                2
+            },
+            0x3C => {
+                // SRL H
+                let value = srl(self.registers.h);
+                self.registers.h = value.value;
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,false);
+                self.set_flag(Flag::C,value.carry.unwrap());
+                2
+            },
+            0x3D => {
+                // SRL L
+                let value = srl(self.registers.l);
+                self.registers.l = value.value;
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,false);
+                self.set_flag(Flag::C,value.carry.unwrap());
+                2
+            },
+            0x3E => {
+                // SRL (HL)
+                let value = srl(self.memory.read8(self.get_hl()));
+                self.memory.write8(self.get_hl(), value.value);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,false);
+                self.set_flag(Flag::C,value.carry.unwrap());
+                4
+            },
+            0x3F => {
+                // SRL A
+                let value = srl(self.registers.a);
+                self.registers.a = value.value;
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,false);
+                self.set_flag(Flag::C,value.carry.unwrap());
+                2
+            },
+            0x40 => {
+                // BIT 0, B
+                let value = bit(self.registers.b, 0);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x41 => {
+                // BIT 0, C
+                let value = bit(self.registers.c, 0);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x42 => {
+                // BIT 0, D
+                let value = bit(self.registers.d, 0);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x43 => {
+                // BIT 0, E
+                let value = bit(self.registers.e, 0);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x44 => {
+                // BIT 0, H
+                let value = bit(self.registers.h, 0);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x45 => {
+                // BIT 0, L
+                let value = bit(self.registers.l, 0);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x46 => {
+                // BIT 0, (HL)
+                let value = bit(self.memory.read8(self.get_hl()), 0);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                3
+            },
+            0x47 => {
+                // BIT 0, A
+                let value = bit(self.registers.a, 0);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x48 => {
+                // BIT 1, B
+                let value = bit(self.registers.b, 1);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x49 => {
+                // BIT 1, C
+                let value = bit(self.registers.c, 1);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x4A => {
+                // BIT 1, D
+                let value = bit(self.registers.d, 1);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x4B => {
+                // BIT 1, E
+                let value = bit(self.registers.e, 1);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x4C => {
+                // BIT 1, H
+                let value = bit(self.registers.h, 1);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x4D => {
+                // BIT 1, L
+                let value = bit(self.registers.l, 1);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x4E => {
+                // BIT 1, (HL)
+                let value = bit(self.memory.read8(self.get_hl()), 1);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                3
+            },
+            0x4F => {
+                // BIT 1, A
+                let value = bit(self.registers.a, 1);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x50 => {
+                // BIT 2, B
+                let value = bit(self.registers.b, 2);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x51 => {
+                // BIT 2, C
+                let value = bit(self.registers.c, 2);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x52 => {
+                // BIT 2, D
+                let value = bit(self.registers.d, 2);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x53 => {
+                // BIT 2, E
+                let value = bit(self.registers.e, 2);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x54 => {
+                // BIT 2, H
+                let value = bit(self.registers.h, 2);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x55 => {
+                // BIT 2, L
+                let value = bit(self.registers.l, 2);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x56 => {
+                // BIT 2, (HL)
+                let value = bit(self.memory.read8(self.get_hl()), 2);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                3
+            },
+            0x57 => {
+                // BIT 2, A
+                let value = bit(self.registers.a, 2);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x58 => {
+                // BIT 3, B
+                let value = bit(self.registers.b, 3);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x59 => {
+                // BIT 3, C
+                let value = bit(self.registers.c, 3);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x5A => {
+                // BIT 3, D
+                let value = bit(self.registers.d, 3);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x5B => {
+                // BIT 3, E
+                let value = bit(self.registers.e, 3);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x5C => {
+                // BIT 3, H
+                let value = bit(self.registers.h, 3);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x5D => {
+                // BIT 3, L
+                let value = bit(self.registers.l, 3);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x5E => {
+                // BIT 3, (HL)
+                let value = bit(self.memory.read8(self.get_hl()), 3);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                3
+            },
+            0x5F => {
+                // BIT 3, A
+                let value = bit(self.registers.a, 3);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x60 => {
+                // BIT 4, B
+                let value = bit(self.registers.b, 4);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x61 => {
+                // BIT 4, C
+                let value = bit(self.registers.c, 4);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x62 => {
+                // BIT 4, D
+                let value = bit(self.registers.d, 4);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x63 => {
+                // BIT 4, E
+                let value = bit(self.registers.e, 4);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x64 => {
+                // BIT 4, H
+                let value = bit(self.registers.h, 4);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x65 => {
+                // BIT 4, L
+                let value = bit(self.registers.l, 4);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x66 => {
+                // BIT 4, (HL)
+                let value = bit(self.memory.read8(self.get_hl()), 4);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                3
+            },
+            0x67 => {
+                // BIT 4, A
+                let value = bit(self.registers.a, 4);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x68 => {
+                // BIT 5, B
+                let value = bit(self.registers.b, 5);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x69 => {
+                // BIT 5, C
+                let value = bit(self.registers.c, 5);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x6A => {
+                // BIT 5, D
+                let value = bit(self.registers.d, 5);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x6B => {
+                // BIT 5, E
+                let value = bit(self.registers.e, 5);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x6C => {
+                // BIT 5, H
+                let value = bit(self.registers.h, 5);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x6D => {
+                // BIT 5, L
+                let value = bit(self.registers.l, 5);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x6E => {
+                // BIT 5, (HL)
+                let value = bit(self.memory.read8(self.get_hl()), 5);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                3
+            },
+            0x6F => {
+                // BIT 5, A
+                let value = bit(self.registers.a, 5);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x70 => {
+                // BIT 6, B
+                let value = bit(self.registers.b, 6);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x71 => {
+                // BIT 6, C
+                let value = bit(self.registers.c, 6);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x72 => {
+                // BIT 6, D
+                let value = bit(self.registers.d, 6);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x73 => {
+                // BIT 6, E
+                let value = bit(self.registers.e, 6);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x74 => {
+                // BIT 6, H
+                let value = bit(self.registers.h, 6);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x75 => {
+                // BIT 6, L
+                let value = bit(self.registers.l, 6);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x76 => {
+                // BIT 6, (HL)
+                let value = bit(self.memory.read8(self.get_hl()), 6);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                3
+            },
+            0x77 => {
+                // BIT 6, A
+                let value = bit(self.registers.a, 6);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x78 => {
+                // BIT 7, B
+                let value = bit(self.registers.b, 7);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x79 => {
+                // BIT 7, C
+                let value = bit(self.registers.c, 7);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x7A => {
+                // BIT 7, D
+                let value = bit(self.registers.d, 7);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x7B => {
+                // BIT 7, E
+                let value = bit(self.registers.e, 7);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x7C => {
+                // BIT 7, H
+                let value = bit(self.registers.h, 7);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x7D => {
+                // BIT 7, L
+                let value = bit(self.registers.l, 7);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x7E => {
+                // BIT 7, (HL)
+                let value = bit(self.memory.read8(self.get_hl()), 7);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                3
+            },
+            0x7F => {
+                // BIT 7, A
+                let value = bit(self.registers.a, 7);
+                self.set_flag(Flag::Z,value.zero.unwrap());
+                self.set_flag(Flag::N,false);
+                self.set_flag(Flag::H,true);
+                2
+            },
+            0x80 => {
+                // RES 0, B
+                let value = res(self.registers.b, 0);
+                self.registers.b = value.value;
+                2
+            },
+            0x81 => {
+                // RES 0, C
+                let value = res(self.registers.c, 0);
+                self.registers.c = value.value;
+                2
+            },
+            0x82 => {
+                // RES 0, D
+                let value = res(self.registers.d, 0);
+                self.registers.d = value.value;
+                2
+            },
+            0x83 => {
+                // RES 0, E
+                let value = res(self.registers.e, 0);
+                self.registers.e = value.value;
+                2
+            },
+            0x84 => {
+                // RES 0, H
+                let value = res(self.registers.h, 0);
+                self.registers.h = value.value;
+                2
+            },
+            0x85 => {
+                // RES 0, L
+                let value = res(self.registers.l, 0);
+                self.registers.l = value.value;
+                2
+            },
+            0x86 => {
+                // RES 0, (HL)
+                let value = res(self.memory.read8(self.get_hl()), 0);
+                self.memory.write8(self.get_hl(), value.value);
+                4
+            },
+            0x87 => {
+                // RES 0, A
+                let value = res(self.registers.a, 0);
+                self.registers.a = value.value;
+                2
+            },
+            0x88 => {
+                // RES 1, B
+                let value = res(self.registers.b, 1);
+                self.registers.b = value.value;
+                2
+            },
+            0x89 => {
+                // RES 1, C
+                let value = res(self.registers.c, 1);
+                self.registers.c = value.value;
+                2
+            },
+            0x8A => {
+                // RES 1, D
+                let value = res(self.registers.d, 1);
+                self.registers.d = value.value;
+                2
+            },
+            0x8B => {
+                // RES 1, E
+                let value = res(self.registers.e, 1);
+                self.registers.e = value.value;
+                2
+            },
+            0x8C => {
+                // RES 1, H
+                let value = res(self.registers.h, 1);
+                self.registers.h = value.value;
+                2
+            },
+            0x8D => {
+                // RES 1, L
+                let value = res(self.registers.l, 1);
+                self.registers.l = value.value;
+                2
+            },
+            0x8E => {
+                // RES 1, (HL)
+                let value = res(self.memory.read8(self.get_hl()), 1);
+                self.memory.write8(self.get_hl(), value.value);
+                4
+            },
+            0x8F => {
+                // RES 1, A
+                let value = res(self.registers.a, 1);
+                self.registers.a = value.value;
+                2
+            },
+            0x90 => {
+                // RES 2, B
+                let value = res(self.registers.b, 2);
+                self.registers.b = value.value;
+                2
+            },
+            0x91 => {
+                // RES 2, C
+                let value = res(self.registers.c, 2);
+                self.registers.c = value.value;
+                2
+            },
+            0x92 => {
+                // RES 2, D
+                let value = res(self.registers.d, 2);
+                self.registers.d = value.value;
+                2
+            },
+            0x93 => {
+                // RES 2, E
+                let value = res(self.registers.e, 2);
+                self.registers.e = value.value;
+                2
+            },
+            0x94 => {
+                // RES 2, H
+                let value = res(self.registers.h, 2);
+                self.registers.h = value.value;
+                2
+            },
+            0x95 => {
+                // RES 2, L
+                let value = res(self.registers.l, 2);
+                self.registers.l = value.value;
+                2
+            },
+            0x96 => {
+                // RES 2, (HL)
+                let value = res(self.memory.read8(self.get_hl()), 2);
+                self.memory.write8(self.get_hl(), value.value);
+                4
+            },
+            0x97 => {
+                // RES 2, A
+                let value = res(self.registers.a, 2);
+                self.registers.a = value.value;
+                2
+            },
+            0x98 => {
+                // RES 3, B
+                let value = res(self.registers.b, 3);
+                self.registers.b = value.value;
+                2
+            },
+            0x99 => {
+                // RES 3, C
+                let value = res(self.registers.c, 3);
+                self.registers.c = value.value;
+                2
+            },
+            0x9A => {
+                // RES 3, D
+                let value = res(self.registers.d, 3);
+                self.registers.d = value.value;
+                2
+            },
+            0x9B => {
+                // RES 3, E
+                let value = res(self.registers.e, 3);
+                self.registers.e = value.value;
+                2
+            },
+            0x9C => {
+                // RES 3, H
+                let value = res(self.registers.h, 3);
+                self.registers.h = value.value;
+                2
+            },
+            0x9D => {
+                // RES 3, L
+                let value = res(self.registers.l, 3);
+                self.registers.l = value.value;
+                2
+            },
+            0x9E => {
+                // RES 3, (HL)
+                let value = res(self.memory.read8(self.get_hl()), 3);
+                self.memory.write8(self.get_hl(), value.value);
+                4
+            },
+            0x9F => {
+                // RES 3, A
+                let value = res(self.registers.a, 3);
+                self.registers.a = value.value;
+                2
+            },
+            0xA0 => {
+                // RES 4, B
+                let value = res(self.registers.b, 4);
+                self.registers.b = value.value;
+                2
+            },
+            0xA1 => {
+                // RES 4, C
+                let value = res(self.registers.c, 4);
+                self.registers.c = value.value;
+                2
+            },
+            0xA2 => {
+                // RES 4, D
+                let value = res(self.registers.d, 4);
+                self.registers.d = value.value;
+                2
+            },
+            0xA3 => {
+                // RES 4, E
+                let value = res(self.registers.e, 4);
+                self.registers.e = value.value;
+                2
+            },
+            0xA4 => {
+                // RES 4, H
+                let value = res(self.registers.h, 4);
+                self.registers.h = value.value;
+                2
+            },
+            0xA5 => {
+                // RES 4, L
+                let value = res(self.registers.l, 4);
+                self.registers.l = value.value;
+                2
+            },
+            0xA6 => {
+                // RES 4, (HL)
+                let value = res(self.memory.read8(self.get_hl()), 4);
+                self.memory.write8(self.get_hl(), value.value);
+                4
+            },
+            0xA7 => {
+                // RES 4, A
+                let value = res(self.registers.a, 4);
+                self.registers.a = value.value;
+                2
+            },
+            0xA8 => {
+                // RES 5, B
+                let value = res(self.registers.b, 5);
+                self.registers.b = value.value;
+                2
+            },
+            0xA9 => {
+                // RES 5, C
+                let value = res(self.registers.c, 5);
+                self.registers.c = value.value;
+                2
+            },
+            0xAA => {
+                // RES 5, D
+                let value = res(self.registers.d, 5);
+                self.registers.d = value.value;
+                2
+            },
+            0xAB => {
+                // RES 5, E
+                let value = res(self.registers.e, 5);
+                self.registers.e = value.value;
+                2
+            },
+            0xAC => {
+                // RES 5, H
+                let value = res(self.registers.h, 5);
+                self.registers.h = value.value;
+                2
+            },
+            0xAD => {
+                // RES 5, L
+                let value = res(self.registers.l, 5);
+                self.registers.l = value.value;
+                2
+            },
+            0xAE => {
+                // RES 5, (HL)
+                let value = res(self.memory.read8(self.get_hl()), 5);
+                self.memory.write8(self.get_hl(), value.value);
+                4
+            },
+            0xAF => {
+                // RES 5, A
+                let value = res(self.registers.a, 5);
+                self.registers.a = value.value;
+                2
+            },
+            0xB0 => {
+                // RES 6, B
+                let value = res(self.registers.b, 6);
+                self.registers.b = value.value;
+                2
+            },
+            0xB1 => {
+                // RES 6, C
+                let value = res(self.registers.c, 6);
+                self.registers.c = value.value;
+                2
+            },
+            0xB2 => {
+                // RES 6, D
+                let value = res(self.registers.d, 6);
+                self.registers.d = value.value;
+                2
+            },
+            0xB3 => {
+                // RES 6, E
+                let value = res(self.registers.e, 6);
+                self.registers.e = value.value;
+                2
+            },
+            0xB4 => {
+                // RES 6, H
+                let value = res(self.registers.h, 6);
+                self.registers.h = value.value;
+                2
+            },
+            0xB5 => {
+                // RES 6, L
+                let value = res(self.registers.l, 6);
+                self.registers.l = value.value;
+                2
+            },
+            0xB6 => {
+                // RES 6, (HL)
+                let value = res(self.memory.read8(self.get_hl()), 6);
+                self.memory.write8(self.get_hl(), value.value);
+                4
+            },
+            0xB7 => {
+                // RES 6, A
+                let value = res(self.registers.a, 6);
+                self.registers.a = value.value;
+                2
+            },
+            0xB8 => {
+                // RES 7, B
+                let value = res(self.registers.b, 7);
+                self.registers.b = value.value;
+                2
+            },
+            0xB9 => {
+                // RES 7, C
+                let value = res(self.registers.c, 7);
+                self.registers.c = value.value;
+                2
+            },
+            0xBA => {
+                // RES 7, D
+                let value = res(self.registers.d, 7);
+                self.registers.d = value.value;
+                2
+            },
+            0xBB => {
+                // RES 7, E
+                let value = res(self.registers.e, 7);
+                self.registers.e = value.value;
+                2
+            },
+            0xBC => {
+                // RES 7, H
+                let value = res(self.registers.h, 7);
+                self.registers.h = value.value;
+                2
+            },
+            0xBD => {
+                // RES 7, L
+                let value = res(self.registers.l, 7);
+                self.registers.l = value.value;
+                2
+            },
+            0xBE => {
+                // RES 7, (HL)
+                let value = res(self.memory.read8(self.get_hl()), 7);
+                self.memory.write8(self.get_hl(), value.value);
+                4
+            },
+            0xBF => {
+                // RES 7, A
+                let value = res(self.registers.a, 7);
+                self.registers.a = value.value;
+                2
+            },
+            0xC0 => {
+                // SET 0, B
+                let value = set(self.registers.b, 0);
+                self.registers.b = value.value;
+                2
+            },
+            0xC1 => {
+                // SET 0, C
+                let value = set(self.registers.c, 0);
+                self.registers.c = value.value;
+                2
+            },
+            0xC2 => {
+                // SET 0, D
+                let value = set(self.registers.d, 0);
+                self.registers.d = value.value;
+                2
+            },
+            0xC3 => {
+                // SET 0, E
+                let value = set(self.registers.e, 0);
+                self.registers.e = value.value;
+                2
+            },
+            0xC4 => {
+                // SET 0, H
+                let value = set(self.registers.h, 0);
+                self.registers.h = value.value;
+                2
+            },
+            0xC5 => {
+                // SET 0, L
+                let value = set(self.registers.l, 0);
+                self.registers.l = value.value;
+                2
+            },
+            0xC6 => {
+                // SET 0, (HL)
+                let value = set(self.memory.read8(self.get_hl()), 0);
+                self.memory.write8(self.get_hl(), value.value);
+                4
+            },
+            0xC7 => {
+                // SET 0, A
+                let value = set(self.registers.a, 0);
+                self.registers.a = value.value;
+                2
+            },
+            0xC8 => {
+                // SET 1, B
+                let value = set(self.registers.b, 1);
+                self.registers.b = value.value;
+                2
+            },
+            0xC9 => {
+                // SET 1, C
+                let value = set(self.registers.c, 1);
+                self.registers.c = value.value;
+                2
+            },
+            0xCA => {
+                // SET 1, D
+                let value = set(self.registers.d, 1);
+                self.registers.d = value.value;
+                2
+            },
+            0xCB => {
+                // SET 1, E
+                let value = set(self.registers.e, 1);
+                self.registers.e = value.value;
+                2
+            },
+            0xCC => {
+                // SET 1, H
+                let value = set(self.registers.h, 1);
+                self.registers.h = value.value;
+                2
+            },
+            0xCD => {
+                // SET 1, L
+                let value = set(self.registers.l, 1);
+                self.registers.l = value.value;
+                2
+            },
+            0xCE => {
+                // SET 1, (HL)
+                let value = set(self.memory.read8(self.get_hl()), 1);
+                self.memory.write8(self.get_hl(), value.value);
+                4
+            },
+            0xCF => {
+                // SET 1, A
+                let value = set(self.registers.a, 1);
+                self.registers.a = value.value;
+                2
+            },
+            0xD0 => {
+                // SET 2, B
+                let value = set(self.registers.b, 2);
+                self.registers.b = value.value;
+                2
+            },
+            0xD1 => {
+                // SET 2, C
+                let value = set(self.registers.c, 2);
+                self.registers.c = value.value;
+                2
+            },
+            0xD2 => {
+                // SET 2, D
+                let value = set(self.registers.d, 2);
+                self.registers.d = value.value;
+                2
+            },
+            0xD3 => {
+                // SET 2, E
+                let value = set(self.registers.e, 2);
+                self.registers.e = value.value;
+                2
+            },
+            0xD4 => {
+                // SET 2, H
+                let value = set(self.registers.h, 2);
+                self.registers.h = value.value;
+                2
+            },
+            0xD5 => {
+                // SET 2, L
+                let value = set(self.registers.l, 2);
+                self.registers.l = value.value;
+                2
+            },
+            0xD6 => {
+                // SET 2, (HL)
+                let value = set(self.memory.read8(self.get_hl()), 2);
+                self.memory.write8(self.get_hl(), value.value);
+                4
+            },
+            0xD7 => {
+                // SET 2, A
+                let value = set(self.registers.a, 2);
+                self.registers.a = value.value;
+                2
+            },
+            0xD8 => {
+                // SET 3, B
+                let value = set(self.registers.b, 3);
+                self.registers.b = value.value;
+                2
+            },
+            0xD9 => {
+                // SET 3, C
+                let value = set(self.registers.c, 3);
+                self.registers.c = value.value;
+                2
+            },
+            0xDA => {
+                // SET 3, D
+                let value = set(self.registers.d, 3);
+                self.registers.d = value.value;
+                2
+            },
+            0xDB => {
+                // SET 3, E
+                let value = set(self.registers.e, 3);
+                self.registers.e = value.value;
+                2
+            },
+            0xDC => {
+                // SET 3, H
+                let value = set(self.registers.h, 3);
+                self.registers.h = value.value;
+                2
+            },
+            0xDD => {
+                // SET 3, L
+                let value = set(self.registers.l, 3);
+                self.registers.l = value.value;
+                2
+            },
+            0xDE => {
+                // SET 3, (HL)
+                let value = set(self.memory.read8(self.get_hl()), 3);
+                self.memory.write8(self.get_hl(), value.value);
+                4
+            },
+            0xDF => {
+                // SET 3, A
+                let value = set(self.registers.a, 3);
+                self.registers.a = value.value;
+                2
+            },
+            0xE0 => {
+                // SET 4, B
+                let value = set(self.registers.b, 4);
+                self.registers.b = value.value;
+                2
+            },
+            0xE1 => {
+                // SET 4, C
+                let value = set(self.registers.c, 4);
+                self.registers.c = value.value;
+                2
+            },
+            0xE2 => {
+                // SET 4, D
+                let value = set(self.registers.d, 4);
+                self.registers.d = value.value;
+                2
+            },
+            0xE3 => {
+                // SET 4, E
+                let value = set(self.registers.e, 4);
+                self.registers.e = value.value;
+                2
+            },
+            0xE4 => {
+                // SET 4, H
+                let value = set(self.registers.h, 4);
+                self.registers.h = value.value;
+                2
+            },
+            0xE5 => {
+                // SET 4, L
+                let value = set(self.registers.l, 4);
+                self.registers.l = value.value;
+                2
+            },
+            0xE6 => {
+                // SET 4, (HL)
+                let value = set(self.memory.read8(self.get_hl()), 4);
+                self.memory.write8(self.get_hl(), value.value);
+                4
+            },
+            0xE7 => {
+                // SET 4, A
+                let value = set(self.registers.a, 4);
+                self.registers.a = value.value;
+                2
+            },
+            0xE8 => {
+                // SET 5, B
+                let value = set(self.registers.b, 5);
+                self.registers.b = value.value;
+                2
+            },
+            0xE9 => {
+                // SET 5, C
+                let value = set(self.registers.c, 5);
+                self.registers.c = value.value;
+                2
+            },
+            0xEA => {
+                // SET 5, D
+                let value = set(self.registers.d, 5);
+                self.registers.d = value.value;
+                2
+            },
+            0xEB => {
+                // SET 5, E
+                let value = set(self.registers.e, 5);
+                self.registers.e = value.value;
+                2
+            },
+            0xEC => {
+                // SET 5, H
+                let value = set(self.registers.h, 5);
+                self.registers.h = value.value;
+                2
+            },
+            0xED => {
+                // SET 5, L
+                let value = set(self.registers.l, 5);
+                self.registers.l = value.value;
+                2
+            },
+            0xEE => {
+                // SET 5, (HL)
+                let value = set(self.memory.read8(self.get_hl()), 5);
+                self.memory.write8(self.get_hl(), value.value);
+                4
+            },
+            0xEF => {
+                // SET 5, A
+                let value = set(self.registers.a, 5);
+                self.registers.a = value.value;
+                2
+            },
+            0xF0 => {
+                // SET 6, B
+                let value = set(self.registers.b, 6);
+                self.registers.b = value.value;
+                2
+            },
+            0xF1 => {
+                // SET 6, C
+                let value = set(self.registers.c, 6);
+                self.registers.c = value.value;
+                2
+            },
+            0xF2 => {
+                // SET 6, D
+                let value = set(self.registers.d, 6);
+                self.registers.d = value.value;
+                2
+            },
+            0xF3 => {
+                // SET 6, E
+                let value = set(self.registers.e, 6);
+                self.registers.e = value.value;
+                2
+            },
+            0xF4 => {
+                // SET 6, H
+                let value = set(self.registers.h, 6);
+                self.registers.h = value.value;
+                2
+            },
+            0xF5 => {
+                // SET 6, L
+                let value = set(self.registers.l, 6);
+                self.registers.l = value.value;
+                2
+            },
+            0xF6 => {
+                // SET 6, (HL)
+                let value = set(self.memory.read8(self.get_hl()), 6);
+                self.memory.write8(self.get_hl(), value.value);
+                4
+            },
+            0xF7 => {
+                // SET 6, A
+                let value = set(self.registers.a, 6);
+                self.registers.a = value.value;
+                2
+            },
+            0xF8 => {
+                // SET 7, B
+                let value = set(self.registers.b, 7);
+                self.registers.b = value.value;
+                2
+            },
+            0xF9 => {
+                // SET 7, C
+                let value = set(self.registers.c, 7);
+                self.registers.c = value.value;
+                2
+            },
+            0xFA => {
+                // SET 7, D
+                let value = set(self.registers.d, 7);
+                self.registers.d = value.value;
+                2
+            },
+            0xFB => {
+                // SET 7, E
+                let value = set(self.registers.e, 7);
+                self.registers.e = value.value;
+                2
+            },
+            0xFC => {
+                // SET 7, H
+                let value = set(self.registers.h, 7);
+                self.registers.h = value.value;
+                2
+            },
+            0xFD => {
+                // SET 7, L
+                let value = set(self.registers.l, 7);
+                self.registers.l = value.value;
+                2
+            },
+            0xFE => {
+                // SET 7, (HL)
+                let value = set(self.memory.read8(self.get_hl()), 7);
+                self.memory.write8(self.get_hl(), value.value);
+                4
+            },
+            0xFF => {
+                // SET 7, A
+                let value = set(self.registers.a, 7);
+                self.registers.a = value.value;
+                2
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::cartridge::{header_checksum, test_rom};
+    use crate::hdma::HDMA5;
+    use crate::interrupts::{INTERRUPT_ENABLE, INTERRUPT_FLAG};
+    use crate::link::LinkCable;
+    use crate::memory::KEY1;
+    use crate::serial::SerialCapture;
+
+    #[test]
+    fn test_interrupt_dispatch() {
+        let mut cpu = CPU::new();
+        cpu.ime = true;
+        cpu.registers.pc = 0x0200;
+        cpu.memory.write8(INTERRUPT_ENABLE, 0x1F);
+        cpu.memory.request_interrupt(Interrupt::Timer);
+        cpu.memory.request_interrupt(Interrupt::Joypad);
+        assert_eq!(cpu.step(), 5);
+        assert_eq!(cpu.registers.pc, 0x50);
+        assert!(!cpu.ime);
+        assert_eq!(cpu.memory.read8(INTERRUPT_FLAG), 0xE0 | Interrupt::Joypad.bit());
+        assert_eq!(cpu.pop(), 0x0200);
+    }
+
+    #[test]
+    fn test_push_pop() {
+        let mut cpu = CPU::new();
+        cpu.registers.pc = 0xC000;
+        cpu.registers.sp = 0xDFFE;
+        cpu.memory.write8(0xC000, 0xC5); // PUSH BC
+        cpu.memory.write8(0xC001, 0xD1); // POP DE
+        cpu.registers.b = 0x12;
+        cpu.registers.c = 0x34;
+        assert_eq!(cpu.execute(), 4);
+        assert_eq!(cpu.registers.sp, 0xDFFC);
+        assert_eq!(cpu.execute(), 3);
+        // POP takes both bytes off the stack
+        assert_eq!(cpu.registers.sp, 0xDFFE);
+        assert_eq!(cpu.get_de(), 0x1234);
+    }
+
+    #[test]
+    fn test_run_frame() {
+        let mut cpu = CPU::new();
+        cpu.registers.pc = 0xC000;
+        cpu.memory.write8(0xC000, 0x18); // JR -2
+        cpu.memory.write8(0xC001, 0xFE);
+        cpu.run_frame();
+        assert_eq!(cpu.memory.ppu().frame_count(), 1);
+        assert_ne!(cpu.memory.read8(INTERRUPT_FLAG) & Interrupt::VBlank.bit(), 0);
+        assert_eq!(cpu.frame().len(), 160 * 144);
+    }
+
+    /// Place a CB instruction at 0xC000 with HL pointing to a byte in work RAM
+    fn cb_instruction(opcode: u8, value: u8) -> CPU {
+        let mut cpu = CPU::new();
+        cpu.registers.pc = 0xC000;
+        cpu.memory.write8(0xC000, 0xCB);
+        cpu.memory.write8(0xC001, opcode);
+        cpu.set_hl(0xC100);
+        cpu.memory.write8(0xC100, value);
+        cpu
+    }
+
+    #[test]
+    fn test_swap_hl() {
+        let mut cpu = cb_instruction(0x36, 0xF1); // SWAP (HL)
+        cpu.set_flag(Flag::C, true);
+        assert_eq!(cpu.execute(), 4);
+        assert_eq!(cpu.registers.pc, 0xC002);
+        assert_eq!(cpu.memory.read8(0xC100), 0x1F);
+        assert_eq!(cpu.registers.f, 0x00);
+        let mut cpu = cb_instruction(0x36, 0x00);
+        assert_eq!(cpu.execute(), 4);
+        assert_eq!(cpu.registers.f, get_flag_bit(Flag::Z));
+    }
+
+    #[test]
+    fn test_bit_hl() {
+        let mut cpu = cb_instruction(0x46, 0xFE); // BIT 0, (HL)
+        cpu.set_flag(Flag::N, true);
+        cpu.set_flag(Flag::C, true);
+        assert_eq!(cpu.execute(), 3);
+        assert_eq!(cpu.memory.read8(0xC100), 0xFE);
+        assert!(cpu.get_flag(Flag::Z));
+        assert!(!cpu.get_flag(Flag::N));
+        assert!(cpu.get_flag(Flag::H));
+        assert!(cpu.get_flag(Flag::C));
+        let mut cpu = cb_instruction(0x46, 0x01);
+        assert_eq!(cpu.execute(), 3);
+        assert!(!cpu.get_flag(Flag::Z));
+    }
+
+    #[test]
+    fn test_res_hl() {
+        let mut cpu = cb_instruction(0x86, 0xFF); // RES 0, (HL)
+        cpu.registers.f = 0xF0;
+        assert_eq!(cpu.execute(), 4);
+        assert_eq!(cpu.registers.pc, 0xC002);
+        assert_eq!(cpu.memory.read8(0xC100), 0xFE);
+        assert_eq!(cpu.registers.f, 0xF0);
+    }
+
+    #[test]
+    fn test_ei_delay() {
+        let mut cpu = CPU::new();
+        cpu.registers.pc = 0xC000;
+        cpu.memory.write8(0xC000, 0xFB); // EI
+        cpu.memory.write8(0xC001, 0x00); // NOP
+        cpu.memory.write8(INTERRUPT_ENABLE, Interrupt::VBlank.bit());
+        cpu.memory.request_interrupt(Interrupt::VBlank);
+        cpu.step();
+        assert!(!cpu.ime);
+        cpu.step();
+        assert!(cpu.ime);
+        assert_eq!(cpu.registers.pc, 0xC002);
+        cpu.step();
+        assert_eq!(cpu.registers.pc, 0x40);
+    }
+
+    #[test]
+    fn test_halt_wakes_without_ime() {
+        let mut cpu = CPU::new();
+        cpu.registers.pc = 0xC000;
+        cpu.memory.write8(0xC000, 0x76); // HALT
+        cpu.memory.write8(0xC001, 0x00); // NOP
+        cpu.memory.write8(INTERRUPT_ENABLE, Interrupt::Timer.bit());
+        cpu.step();
+        assert!(cpu.is_halted());
+        cpu.step();
+        assert_eq!(cpu.registers.pc, 0xC001);
+        cpu.memory.request_interrupt(Interrupt::Timer);
+        cpu.step();
+        assert!(!cpu.is_halted());
+        assert_eq!(cpu.registers.pc, 0xC002);
+    }
+
+    #[test]
+    fn test_halt_bug() {
+        let mut cpu = CPU::new();
+        cpu.registers.pc = 0xC000;
+        cpu.registers.a = 0;
+        cpu.memory.write8(0xC000, 0x76); // HALT
+        cpu.memory.write8(0xC001, 0x3C); // INC A
+        cpu.memory.write8(INTERRUPT_ENABLE, Interrupt::Timer.bit());
+        cpu.memory.request_interrupt(Interrupt::Timer);
+        cpu.step();
+        assert!(!cpu.is_halted());
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.registers.a, 2);
+        assert_eq!(cpu.registers.pc, 0xC002);
+    }
+
+    #[test]
+    fn test_stop_speed_switch() {
+        let mut cpu = CPU::with_model(Model::Cgb);
+        cpu.registers.pc = 0xC000;
+        cpu.memory.write8(0xC000, 0x10); // STOP
+        cpu.memory.write8(KEY1, 0x01);
+        cpu.step();
+        assert!(cpu.is_double_speed());
+        assert!(!cpu.is_stopped());
+        assert_eq!(cpu.memory.read8(KEY1), 0xFE);
+        assert_eq!(cpu.registers.pc, 0xC002);
+    }
+
+    #[test]
+    fn test_vram_dma_stall() {
+        let mut cpu = CPU::with_model(Model::Cgb);
+        cpu.registers.pc = 0xC000;
+        cpu.registers.a = 0x03;
+        cpu.memory.write8(0xC000, 0xE0); // LDH (0x55), A
+        cpu.memory.write8(0xC001, 0x55);
+        cpu.memory.write8(0xC002, 0x00); // NOP
+        assert_eq!(cpu.step(), 3);
+        // 4 blocks of 8 M-cycles before the next instruction
+        assert_eq!(cpu.step(), 32);
+        assert_eq!(cpu.registers.pc, 0xC002);
+        cpu.memory.write8(KEY1, 0x01);
+        cpu.memory.switch_speed();
+        // A block takes twice the M-cycles in double speed
+        cpu.memory.write8(HDMA5, 0x00);
+        assert_eq!(cpu.step(), 16);
+    }
+
+    #[test]
+    fn test_cgb_model() {
+        let mut rom = test_rom(0x00, 0x00, 0x00);
+        rom[0x0143] = 0x80;
+        rom[0x014D] = header_checksum(&rom);
+        let cpu = CPU::with_cartridge(Cartridge::from_bytes(rom).unwrap());
+        assert_eq!(cpu.model(), Model::Cgb);
+        assert_eq!(cpu.registers.a, 0x11);
+        assert_eq!(cpu.registers.f, 0x80);
+        assert_eq!(cpu.get_de(), 0xFF56);
+        assert_eq!(cpu.get_hl(), 0x000D);
+        let cpu = CPU::with_cartridge(Cartridge::from_bytes(test_rom(0x00, 0x00, 0x00)).unwrap());
+        assert_eq!(cpu.model(), Model::Dmg);
+        assert_eq!(cpu.registers.a, 0x01);
+    }
+
+    #[test]
+    fn test_stop_without_speed_switch_on_dmg() {
+        let mut cpu = CPU::new();
+        cpu.registers.pc = 0xC000;
+        cpu.memory.write8(0xC000, 0x10); // STOP
+        cpu.memory.write8(KEY1, 0x01);
+        cpu.step();
+        assert!(!cpu.is_double_speed());
+        assert!(cpu.is_stopped());
+    }
+
+    #[test]
+    fn test_button_leaves_stop() {
+        let mut cpu = CPU::new();
+        cpu.registers.pc = 0xC000;
+        cpu.memory.write8(0xC000, 0x10); // STOP
+        cpu.memory.write8(0xC002, 0x00); // NOP
+        cpu.memory.write8(0xFF00, 0x10); // Select the action buttons
+        cpu.step();
+        assert!(cpu.is_stopped());
+        cpu.step();
+        assert!(cpu.is_stopped());
+        cpu.press(Button::Start);
+        cpu.step();
+        assert!(!cpu.is_stopped());
+        assert_eq!(cpu.registers.pc, 0xC003);
+        assert_eq!(cpu.memory.read8(0xFF00), 0xD7);
+        cpu.release(Button::Start);
+        assert_eq!(cpu.memory.read8(0xFF00), 0xDF);
+    }
+
+    #[test]
+    fn test_stop_ignores_joypad_request() {
+        let mut cpu = CPU::new();
+        cpu.registers.pc = 0xC000;
+        cpu.memory.write8(0xC000, 0x10); // STOP
+        cpu.memory.request_interrupt(Interrupt::Joypad);
+        cpu.step();
+        cpu.step();
+        assert!(cpu.is_stopped());
+        assert_eq!(cpu.registers.pc, 0xC002);
+    }
+
+    #[test]
+    fn test_serial_capture() {
+        let mut cpu = CPU::new();
+        let capture = SerialCapture::new();
+        cpu.set_serial_device(Box::new(capture.clone()));
+        cpu.registers.pc = 0xC000;
+        let program = [
+            0x3E, b'O', // LD A, 'O'
+            0xE0, 0x01, // LDH (SB), A
+            0x3E, 0x81, // LD A, 0x81
+            0xE0, 0x02, // LDH (SC), A
+            0x18, 0xFE, // JR -2
+        ];
+        for (offset, byte) in program.into_iter().enumerate() {
+            cpu.memory.write8(0xC000 + offset as u16, byte);
+        }
+        cpu.run_frame();
+        assert_eq!(capture.text(), "O");
+        assert_ne!(cpu.memory.read8(INTERRUPT_FLAG) & Interrupt::Serial.bit(), 0);
+    }
+
+    #[test]
+    fn test_link_cable() {
+        let (first, second) = LinkCable::pair().unwrap();
+        let run = |cable: LinkCable<_>, value: u8, control: u8| {
+            thread::spawn(move || {
+                let mut cpu = CPU::new();
+                cpu.set_serial_device(Box::new(cable));
+                cpu.registers.pc = 0xC000;
+                let program = [
+                    0x3E, value, // LD A, value
+                    0xE0, 0x01, // LDH (SB), A
+                    0x3E, control, // LD A, control
+                    0xE0, 0x02, // LDH (SC), A
+                    0x18, 0xFE, // JR -2
+                ];
+                for (offset, byte) in program.into_iter().enumerate() {
+                    cpu.memory.write8(0xC000 + offset as u16, byte);
+                }
+                cpu.run_frame();
+                (cpu.memory.read8(0xFF01), cpu.memory.read8(0xFF02))
+            })
+        };
+        let master = run(first, 0x42, 0x81);
+        let slave = run(second, 0x24, 0x80);
+        assert_eq!(master.join().unwrap(), (0x24, 0x7F));
+        assert_eq!(slave.join().unwrap(), (0x42, 0x7E));
     }
 }
@@ -0,0 +1,130 @@
+use crate::memory::OPEN_BUS;
+
+pub const HDMA1: u16 = 0xFF51; // Source address, upper byte
+pub const HDMA2: u16 = 0xFF52; // Source address, lower byte, the lower 4 bits are ignored
+pub const HDMA3: u16 = 0xFF53; // Destination in VRAM, upper byte, the upper 3 bits are ignored
+pub const HDMA4: u16 = 0xFF54; // Destination in VRAM, lower byte, the lower 4 bits are ignored
+pub const HDMA5: u16 = 0xFF55; // Blocks to copy minus 1 and mode, writing it starts the transfer
+
+pub const BLOCK_SIZE: u16 = 0x10; // Bytes copied at once, the whole transfer or one per HBlank
+pub const BLOCK_CYCLES: u32 = 8; // M-cycles the CPU waits for each block, 16 in double speed
+
+const HBLANK_MODE: u8 = 1 << 7; // HDMA5 bit that copies a block per HBlank instead of all at once
+
+/// CGB VRAM DMA controller, copies blocks of 16 bytes from ROM or RAM to VRAM. A general
+/// transfer copies them all at once and an HBlank transfer copies one at the start of each HBlank
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hdma {
+    source: u16,
+    /// Offset in VRAM
+    destination: u16,
+    /// Blocks left to copy
+    remaining: u8,
+    /// An HBlank transfer is running
+    hblank: bool,
+}
+
+impl Default for Hdma {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hdma {
+    pub fn new() -> Self {
+        Hdma {
+            source: 0,
+            destination: 0,
+            remaining: 0,
+            hblank: false,
+        }
+    }
+
+    /// Check if an HBlank transfer is running
+    pub fn is_hblank_active(&self) -> bool {
+        self.hblank
+    }
+
+    /// Read a VRAM DMA register, only HDMA5 can be read. Bit 7 is clear while an HBlank
+    /// transfer runs and the rest are the blocks left minus 1, 0xFF once the transfer is done
+    pub fn read_register(&self, address: u16) -> u8 {
+        match address {
+            HDMA5 => {
+                let mode = if self.hblank { 0 } else { HBLANK_MODE };
+                mode | self.remaining.wrapping_sub(1) & 0x7F
+            }
+            _ => OPEN_BUS,
+        }
+    }
+
+    /// Write a VRAM DMA register, returns the blocks to copy right away for a general transfer.
+    /// Writing HDMA5 with bit 7 clear during an HBlank transfer stops it
+    pub fn write_register(&mut self, address: u16, value: u8) -> u8 {
+        match address {
+            HDMA1 => self.source = self.source & 0x00FF | (value as u16) << 8,
+            HDMA2 => self.source = self.source & 0xFF00 | (value & 0xF0) as u16,
+            HDMA3 => self.destination = self.destination & 0x00FF | ((value & 0x1F) as u16) << 8,
+            HDMA4 => self.destination = self.destination & 0xFF00 | (value & 0xF0) as u16,
+            HDMA5 if self.hblank && value & HBLANK_MODE == 0 => self.hblank = false,
+            HDMA5 => {
+                self.remaining = (value & 0x7F) + 1;
+                self.hblank = value & HBLANK_MODE != 0;
+                if !self.hblank {
+                    return self.remaining;
+                }
+            }
+            _ => {}
+        }
+        0
+    }
+
+    /// Take the next block, returns the source address and the VRAM offset of its first byte
+    pub fn next_block(&mut self) -> Option<(u16, u16)> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let block = (self.source, self.destination);
+        self.source = self.source.wrapping_add(BLOCK_SIZE);
+        self.destination = (self.destination + BLOCK_SIZE) & 0x1FF0;
+        self.remaining -= 1;
+        if self.remaining == 0 {
+            self.hblank = false;
+        }
+        Some(block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_general_transfer() {
+        let mut hdma = Hdma::new();
+        assert_eq!(hdma.read_register(HDMA5), 0xFF);
+        for (address, value) in [(HDMA1, 0xC1), (HDMA2, 0x2F), (HDMA3, 0xE3), (HDMA4, 0x4A)] {
+            assert_eq!(hdma.write_register(address, value), 0);
+        }
+        assert_eq!(hdma.read_register(HDMA1), OPEN_BUS);
+        assert_eq!(hdma.write_register(HDMA5, 0x01), 2);
+        assert_eq!(hdma.next_block(), Some((0xC120, 0x0340)));
+        assert_eq!(hdma.next_block(), Some((0xC130, 0x0350)));
+        assert_eq!(hdma.next_block(), None);
+        assert_eq!(hdma.read_register(HDMA5), 0xFF);
+    }
+
+    #[test]
+    fn test_hblank_transfer() {
+        let mut hdma = Hdma::new();
+        hdma.write_register(HDMA1, 0x40);
+        assert_eq!(hdma.write_register(HDMA5, 0x82), 0);
+        assert!(hdma.is_hblank_active());
+        assert_eq!(hdma.read_register(HDMA5), 0x02);
+        assert_eq!(hdma.next_block(), Some((0x4000, 0x0000)));
+        assert_eq!(hdma.read_register(HDMA5), 0x01);
+        // Bit 7 clear stops it, the blocks left can still be read
+        hdma.write_register(HDMA5, 0x00);
+        assert!(!hdma.is_hblank_active());
+        assert_eq!(hdma.read_register(HDMA5), 0x81);
+    }
+}
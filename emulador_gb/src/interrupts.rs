@@ -0,0 +1,65 @@
+pub const INTERRUPT_FLAG: u16 = 0xFF0F; // IF Interrupt requests
+pub const INTERRUPT_ENABLE: u16 = 0xFFFF; // IE Interrupt enable
+
+/// Interrupt sources of the game boy, declared from highest to lowest priority
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Interrupt {
+    VBlank,
+    Stat,
+    Timer,
+    Serial,
+    Joypad,
+}
+
+impl Interrupt {
+    /// All the interrupts in the order they are serviced
+    pub const PRIORITY: [Interrupt; 5] = [
+        Interrupt::VBlank,
+        Interrupt::Stat,
+        Interrupt::Timer,
+        Interrupt::Serial,
+        Interrupt::Joypad,
+    ];
+
+    /// Get the bit of the interrupt in the IE and IF registers
+    pub fn bit(self) -> u8 {
+        match self {
+            Interrupt::VBlank => 1 << 0,
+            Interrupt::Stat => 1 << 1,
+            Interrupt::Timer => 1 << 2,
+            Interrupt::Serial => 1 << 3,
+            Interrupt::Joypad => 1 << 4,
+        }
+    }
+
+    /// Get the address the CPU jumps to when the interrupt is serviced
+    pub fn vector(self) -> u16 {
+        match self {
+            Interrupt::VBlank => 0x40,
+            Interrupt::Stat => 0x48,
+            Interrupt::Timer => 0x50,
+            Interrupt::Serial => 0x58,
+            Interrupt::Joypad => 0x60,
+        }
+    }
+
+    /// Get the interrupt with the highest priority requested in a IE & IF mask
+    pub fn highest(pending: u8) -> Option<Interrupt> {
+        Interrupt::PRIORITY
+            .into_iter()
+            .find(|interrupt| pending & interrupt.bit() != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_highest() {
+        assert_eq!(Interrupt::highest(0), None);
+        assert_eq!(Interrupt::highest(0b10100), Some(Interrupt::Timer));
+        assert_eq!(Interrupt::highest(0b11111), Some(Interrupt::VBlank));
+        assert_eq!(Interrupt::highest(0b10000), Some(Interrupt::Joypad));
+    }
+}
@@ -0,0 +1,151 @@
+use crate::interrupts::Interrupt;
+
+pub const JOYP: u16 = 0xFF00; // P1 Button matrix, the upper bits select the row read in the lower bits
+
+const SELECT_DPAD: u8 = 1 << 4; // Cleared to read the direction keys
+const SELECT_BUTTONS: u8 = 1 << 5; // Cleared to read the action buttons
+
+/// Keys of the game boy, each one shorts a line of the button matrix when pressed
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+impl Button {
+    /// Get the bit of the button in the pressed mask, the low nibble is the direction row and
+    /// the high nibble the action row
+    fn mask(self) -> u8 {
+        match self {
+            Button::Right => 1 << 0,
+            Button::Left => 1 << 1,
+            Button::Up => 1 << 2,
+            Button::Down => 1 << 3,
+            Button::A => 1 << 4,
+            Button::B => 1 << 5,
+            Button::Select => 1 << 6,
+            Button::Start => 1 << 7,
+        }
+    }
+}
+
+/// Joypad behind P1, the lines of the selected rows read 0 while one of their buttons is held
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Joypad {
+    /// Select bits written to P1, active low
+    select: u8,
+    /// Bit per button being held
+    pressed: u8,
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Joypad {
+    /// Create a joypad with both rows selected and no buttons held, as left by the boot ROM
+    pub fn new() -> Self {
+        Joypad {
+            select: 0,
+            pressed: 0,
+        }
+    }
+
+    /// Get the input lines, active low
+    fn lines(&self) -> u8 {
+        let mut lines = 0;
+        if self.select & SELECT_DPAD == 0 {
+            lines |= self.pressed & 0x0F;
+        }
+        if self.select & SELECT_BUTTONS == 0 {
+            lines |= self.pressed >> 4;
+        }
+        !lines & 0x0F
+    }
+
+    /// Apply a change to the matrix, returns the IF bits when a line goes from high to low
+    fn update(&mut self, change: impl FnOnce(&mut Self)) -> u8 {
+        let lines = self.lines();
+        change(self);
+        if lines & !self.lines() != 0 {
+            Interrupt::Joypad.bit()
+        } else {
+            0
+        }
+    }
+
+    /// Check if a button of a selected row holds its line low, which ends STOP mode
+    pub fn is_line_low(&self) -> bool {
+        self.lines() != 0x0F
+    }
+
+    /// Check if a button is being held
+    pub fn is_pressed(&self, button: Button) -> bool {
+        self.pressed & button.mask() != 0
+    }
+
+    /// Hold a button, returns the IF bits of the interrupts requested
+    pub fn press(&mut self, button: Button) -> u8 {
+        self.update(|joypad| joypad.pressed |= button.mask())
+    }
+
+    /// Let go of a button
+    pub fn release(&mut self, button: Button) {
+        self.pressed &= !button.mask();
+    }
+
+    /// Read P1, the unused upper bits read 1
+    pub fn read_register(&self) -> u8 {
+        0xC0 | self.select | self.lines()
+    }
+
+    /// Write the select bits of P1, selecting a row with a button held also lowers a line.
+    /// Returns the IF bits of the interrupts requested
+    pub fn write_register(&mut self, value: u8) -> u8 {
+        self.update(|joypad| joypad.select = value & (SELECT_DPAD | SELECT_BUTTONS))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matrix() {
+        let mut joypad = Joypad::new();
+        assert_eq!(joypad.read_register(), 0xCF);
+        joypad.press(Button::Down);
+        joypad.press(Button::A);
+        joypad.write_register(SELECT_BUTTONS);
+        assert_eq!(joypad.read_register(), 0xE7);
+        joypad.write_register(SELECT_DPAD);
+        assert_eq!(joypad.read_register(), 0xDE);
+        joypad.write_register(SELECT_DPAD | SELECT_BUTTONS);
+        assert_eq!(joypad.read_register(), 0xFF);
+        joypad.release(Button::A);
+        joypad.write_register(0);
+        assert_eq!(joypad.read_register(), 0xC7);
+        assert!(!joypad.is_pressed(Button::A));
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let mut joypad = Joypad::new();
+        assert_eq!(joypad.press(Button::Start), Interrupt::Joypad.bit());
+        // Down shares the line of Start, which is already low
+        assert_eq!(joypad.press(Button::Down), 0);
+        // Buttons of a row that isn't selected don't change the lines
+        joypad.write_register(SELECT_DPAD);
+        assert_eq!(joypad.press(Button::Left), 0);
+        // Until the row is selected
+        assert_eq!(joypad.write_register(SELECT_BUTTONS), Interrupt::Joypad.bit());
+    }
+}
@@ -0,0 +1,18 @@
+pub mod apu;
+pub mod audio;
+pub mod cartridge;
+pub mod dma;
+pub mod gb;
+pub mod hdma;
+pub mod interrupts;
+pub mod joypad;
+pub mod link;
+pub mod mbc;
+pub mod memory;
+pub mod operations;
+pub mod png;
+pub mod ppu;
+pub mod printer;
+pub mod save;
+pub mod serial;
+pub mod timer;
@@ -0,0 +1,218 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::Path;
+
+use crate::serial::SerialDevice;
+
+pub const DEFAULT_SYNC_INTERVAL: u32 = 4096; // 4.19MHz cycles between syncs, the time of a transfer
+
+const SYNC: u8 = 0x00; // The sender reached the end of a sync interval
+const TRANSFER: u8 = 0x01; // The sender clocked a transfer, followed by the byte it shifts out
+const REPLY: u8 = 0x02; // Answer to a transfer, followed by the byte shifted back
+
+/// Link cable to another emulator over a stream socket.
+///
+/// Both sides stop at the end of every sync interval until the other one gets there, so they
+/// never drift apart by more than an interval. A transfer clocked by one side is answered by the
+/// other one at the end of the interval it was sent in, which makes the cycle the externally
+/// clocked side sees the byte at only depend on the emulated time of both sides.
+///
+/// The calls block on the other side, when both emulators run in the same process each one
+/// needs its own thread
+#[derive(Debug)]
+pub struct LinkCable<S: Read + Write> {
+    /// None once the other side hung up, the cable then acts as if it was unplugged
+    stream: Option<S>,
+    sync_interval: u32,
+    /// 4.19MHz cycles since the last sync
+    cycles: u32,
+    /// Syncs received for intervals this side hasn't finished yet
+    peer_syncs: u32,
+}
+
+impl<S: Read + Write> LinkCable<S> {
+    /// Plug a cable into a connected stream, the other side must use the same sync interval
+    pub fn new(stream: S) -> Self {
+        LinkCable {
+            stream: Some(stream),
+            sync_interval: DEFAULT_SYNC_INTERVAL,
+            cycles: 0,
+            peer_syncs: 0,
+        }
+    }
+
+    /// Set the 4.19MHz cycles between syncs, shorter intervals deliver bytes sooner but wait more
+    pub fn set_sync_interval(&mut self, cycles: u32) {
+        self.sync_interval = cycles.max(1);
+    }
+
+    /// Check if the other side is still there
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    fn send(&mut self, message: u8, value: u8) {
+        let Some(stream) = self.stream.as_mut() else {
+            return;
+        };
+        if let Err(error) = stream.write_all(&[message, value]).and_then(|_| stream.flush()) {
+            self.hang_up(error);
+        }
+    }
+
+    fn receive(&mut self) -> Option<(u8, u8)> {
+        let stream = self.stream.as_mut()?;
+        let mut message = [0; 2];
+        match stream.read_exact(&mut message) {
+            Ok(()) => Some((message[0], message[1])),
+            Err(error) => {
+                self.hang_up(error);
+                None
+            }
+        }
+    }
+
+    fn hang_up(&mut self, error: io::Error) {
+        eprintln!("Link cable disconnected: {}", error);
+        self.stream = None;
+    }
+}
+
+impl LinkCable<TcpStream> {
+    /// Connect to the emulator listening at an address
+    pub fn connect<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+        Self::from_tcp(TcpStream::connect(address)?)
+    }
+
+    /// Wait for an emulator to connect to an address
+    pub fn listen<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+        let (stream, _) = TcpListener::bind(address)?.accept()?;
+        Self::from_tcp(stream)
+    }
+
+    /// Create both ends of a cable over the loopback interface, for two emulators in one process
+    pub fn pair() -> io::Result<(Self, Self)> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let client = TcpStream::connect(listener.local_addr()?)?;
+        let (server, _) = listener.accept()?;
+        Ok((Self::from_tcp(server)?, Self::from_tcp(client)?))
+    }
+
+    fn from_tcp(stream: TcpStream) -> io::Result<Self> {
+        // Every message is waited on, don't let them sit in the send buffer
+        stream.set_nodelay(true)?;
+        Ok(Self::new(stream))
+    }
+}
+
+#[cfg(unix)]
+impl LinkCable<UnixStream> {
+    /// Connect to the emulator listening on a Unix socket
+    pub fn connect_unix<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(UnixStream::connect(path)?))
+    }
+
+    /// Wait for an emulator to connect to a Unix socket
+    pub fn listen_unix<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let (stream, _) = UnixListener::bind(path)?.accept()?;
+        Ok(Self::new(stream))
+    }
+}
+
+impl<S: Read + Write> SerialDevice for LinkCable<S> {
+    fn exchange(&mut self, value: u8) -> u8 {
+        self.send(TRANSFER, value);
+        // The other side answers at the end of this interval, which it can reach while we wait
+        while let Some((message, peer_value)) = self.receive() {
+            match message {
+                SYNC => self.peer_syncs += 1,
+                REPLY => return peer_value,
+                // Both sides drive the clock, neither of them shifts in anything
+                TRANSFER => self.send(REPLY, 0xFF),
+                _ => {}
+            }
+        }
+        0xFF
+    }
+
+    fn step(&mut self, cycles: u32, mut external: Option<u8>) -> Option<u8> {
+        self.cycles += cycles;
+        let mut received = None;
+        while self.cycles >= self.sync_interval && self.is_connected() {
+            self.cycles -= self.sync_interval;
+            self.send(SYNC, 0);
+            // Answer the transfers the other side clocked during the interval
+            while self.peer_syncs == 0 {
+                let Some((message, peer_value)) = self.receive() else {
+                    break;
+                };
+                match message {
+                    SYNC => self.peer_syncs += 1,
+                    TRANSFER => {
+                        // Only a game boy waiting on the external clock shifts the byte in
+                        self.send(REPLY, external.unwrap_or(0xFF));
+                        if external.take().is_some() {
+                            received = Some(peer_value);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            self.peer_syncs = self.peer_syncs.saturating_sub(1);
+        }
+        received
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_transfer_at_sync() {
+        let (mut master, mut slave) = LinkCable::pair().unwrap();
+        let peer = thread::spawn(move || {
+            // The byte comes in at the first sync after the transfer, whatever the host timing
+            let mut cycles = 0;
+            loop {
+                cycles += 4;
+                if let Some(value) = slave.step(4, Some(0x24)) {
+                    return (value, cycles);
+                }
+            }
+        });
+        master.step(1000, None);
+        assert_eq!(master.exchange(0x42), 0x24);
+        master.step(DEFAULT_SYNC_INTERVAL, None);
+        assert_eq!(peer.join().unwrap(), (0x42, DEFAULT_SYNC_INTERVAL));
+    }
+
+    #[test]
+    fn test_both_clocks() {
+        let (mut first, mut second) = LinkCable::pair().unwrap();
+        let peer = thread::spawn(move || {
+            second.step(200, None);
+            let value = second.exchange(0x11);
+            second.step(DEFAULT_SYNC_INTERVAL, None);
+            value
+        });
+        first.step(100, None);
+        assert_eq!(first.exchange(0x22), 0xFF);
+        first.step(DEFAULT_SYNC_INTERVAL, None);
+        assert_eq!(peer.join().unwrap(), 0xFF);
+    }
+
+    #[test]
+    fn test_hang_up() {
+        let (mut cable, peer) = LinkCable::pair().unwrap();
+        drop(peer);
+        assert_eq!(cable.exchange(0x42), 0xFF);
+        assert!(!cable.is_connected());
+        assert_eq!(cable.step(DEFAULT_SYNC_INTERVAL, Some(0x42)), None);
+    }
+}
@@ -1,5 +1,199 @@
-mod gb;
-mod operations;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use emulador_gb::apu::CHANNELS;
+use emulador_gb::audio::WavWriter;
+use emulador_gb::cartridge::{Cartridge, CgbSupport};
+use emulador_gb::gb::CPU;
+use emulador_gb::link::LinkCable;
+use emulador_gb::mbc::RtcClock;
+use emulador_gb::ppu::Renderer;
+use emulador_gb::printer::Printer;
+use emulador_gb::serial::SerialCapture;
+
+const RECORD_SAMPLE_RATE: u32 = 44_100;
+const USAGE: &str = "Usage: emulador_gb [--dmg] [--fifo] [--serial | --listen=ADDRESS | --connect=ADDRESS | --printer=FOLDER] [--record=FILE | --stems=FOLDER] [--mute=CHANNELS] [--solo=CHANNELS] [--frames=N] <rom.gb>";
+
 fn main() {
-    println!("Hello, world!");
-}
\ No newline at end of file
+    // --dmg runs game boy color games on the original game boy when they support it
+    // --fifo selects the pixel FIFO renderer, needed by games with mid-line effects
+    // --serial prints the bytes sent over the link port, where test ROMs report their results
+    // --listen=ADDRESS and --connect=ADDRESS link two emulators with a cable over TCP
+    // --printer=FOLDER plugs in a Game Boy Printer that writes the sheets to PNG files
+    // Only one of them can use the link port
+    // --record=FILE records the audio to a 44.1kHz WAV file
+    // --stems=FOLDER records the mix and each channel on its own to WAV files in a folder, it
+    // can't be combined with --record
+    // --mute=CHANNELS and --solo=CHANNELS take channels out of the mix, like --mute=34
+    // --frames=N quits after running N frames, otherwise typing q and Enter quits
+    let (flags, paths): (Vec<String>, Vec<String>) = env::args().skip(1).partition(|arg| arg.starts_with("--"));
+    let Some(path) = paths.first() else {
+        exit_with_usage();
+    };
+    let count = |prefixes: &[&str]| {
+        prefixes.iter().filter(|prefix| flags.iter().any(|flag| flag.starts_with(*prefix))).count()
+    };
+    if count(&["--serial", "--listen=", "--connect=", "--printer="]) > 1 {
+        eprintln!("--serial, --listen, --connect and --printer all use the link port, choose one");
+        exit_with_usage();
+    }
+    if count(&["--record=", "--stems="]) > 1 {
+        eprintln!("--stems already records the mix to mix.wav, it can't be combined with --record");
+        exit_with_usage();
+    }
+    let is_channel = |channel: char| channel.to_digit(10).is_some_and(|channel| (1..=CHANNELS as u32).contains(&channel));
+    let channel_lists = flags.iter().filter_map(|flag| flag.strip_prefix("--mute=").or(flag.strip_prefix("--solo=")));
+    for list in channel_lists {
+        if list.is_empty() || !list.chars().all(is_channel) {
+            eprintln!("Invalid channels {:?}, the channels are the digits 1 to {}", list, CHANNELS);
+            exit_with_usage();
+        }
+    }
+    let cartridge = match Cartridge::load_with_clock(path, RtcClock::Host) {
+        Ok(cartridge) => cartridge,
+        Err(error) => {
+            eprintln!("Could not load {}: {}", path, error);
+            process::exit(1);
+        }
+    };
+    println!("Loaded {}", cartridge.header().title);
+    for warning in cartridge.warnings() {
+        eprintln!("Warning: {}", warning);
+    }
+    if let Some(battery) = cartridge.battery() {
+        println!("Saving to {}", battery.path().display());
+    }
+
+    let mut cpu = if flags.iter().any(|flag| flag == "--dmg") && cartridge.header().cgb != CgbSupport::Only {
+        let mut cpu = CPU::new();
+        cpu.load_cartridge(cartridge);
+        cpu
+    } else {
+        CPU::with_cartridge(cartridge)
+    };
+    if flags.iter().any(|flag| flag == "--fifo") {
+        cpu.set_renderer(Renderer::Fifo);
+    }
+    let mut serial = flags.iter().any(|flag| flag == "--serial").then(SerialCapture::new);
+    if let Some(capture) = &serial {
+        cpu.set_serial_device(Box::new(capture.clone()));
+    }
+    let listen = flags.iter().find_map(|flag| flag.strip_prefix("--listen="));
+    let connect = flags.iter().find_map(|flag| flag.strip_prefix("--connect="));
+    let cable = match (listen, connect) {
+        (Some(address), _) => {
+            println!("Waiting for the other emulator on {}", address);
+            Some(LinkCable::listen(address))
+        }
+        (None, Some(address)) => Some(LinkCable::connect(address)),
+        (None, None) => None,
+    };
+    match cable {
+        Some(Ok(cable)) => cpu.set_serial_device(Box::new(cable)),
+        Some(Err(error)) => {
+            eprintln!("Could not link the emulators: {}", error);
+            process::exit(1);
+        }
+        None => {}
+    }
+    if let Some(folder) = flags.iter().find_map(|flag| flag.strip_prefix("--printer=")) {
+        cpu.set_serial_device(Box::new(Printer::with_output(folder)));
+    }
+    if let Some(path) = flags.iter().find_map(|flag| flag.strip_prefix("--record=")) {
+        match WavWriter::create(path, RECORD_SAMPLE_RATE) {
+            Ok(wav) => {
+                cpu.set_audio_sink(Some(Box::new(wav)));
+            }
+            Err(error) => {
+                eprintln!("Could not create {}: {}", path, error);
+                process::exit(1);
+            }
+        }
+    }
+    if let Some(folder) = flags.iter().find_map(|flag| flag.strip_prefix("--stems=")) {
+        if let Err(error) = record_stems(&mut cpu, Path::new(folder)) {
+            eprintln!("Could not record the stems to {}: {}", folder, error);
+            process::exit(1);
+        }
+    }
+    for (prefix, soloed) in [("--mute=", false), ("--solo=", true)] {
+        let channels = flags.iter().filter_map(|flag| flag.strip_prefix(prefix)).flat_map(str::chars);
+        for channel in channels.filter_map(|channel| channel.to_digit(10)) {
+            if soloed {
+                cpu.apu_mut().set_channel_soloed(channel as usize, true);
+            } else {
+                cpu.apu_mut().set_channel_muted(channel as usize, true);
+            }
+        }
+    }
+    let frames = match flags.iter().find_map(|flag| flag.strip_prefix("--frames=")) {
+        Some(frames) => match frames.parse::<u64>() {
+            Ok(frames) => Some(frames),
+            Err(error) => {
+                eprintln!("Invalid number of frames {}: {}", frames, error);
+                process::exit(1);
+            }
+        },
+        None => None,
+    };
+    let quit = watch_quit();
+    let mut frame = 0;
+    while !quit.load(Ordering::Relaxed) && frames.is_none_or(|frames| frame < frames) {
+        cpu.run_frame();
+        frame += 1;
+        if let Some(capture) = serial.as_mut() {
+            let mut stdout = io::stdout();
+            let _ = stdout.write_all(&capture.bytes());
+            let _ = stdout.flush();
+            capture.clear();
+        }
+    }
+    let result = cpu.save();
+    // Dropping the emulator finishes the WAV files, the cartridge isn't saved again
+    drop(cpu);
+    if let Err(error) = result {
+        eprintln!("Could not save the game: {}", error);
+        process::exit(1);
+    }
+}
+
+fn exit_with_usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(1);
+}
+
+/// Watch stdin on another thread, the flag is set when a line with q is typed
+fn watch_quit() -> Arc<AtomicBool> {
+    let quit = Arc::new(AtomicBool::new(false));
+    let flag = quit.clone();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if line.trim() == "q" {
+                flag.store(true, Ordering::Relaxed);
+                break;
+            }
+        }
+    });
+    quit
+}
+
+/// Record the mix to mix.wav and each channel to channel_N.wav
+fn record_stems(cpu: &mut CPU, folder: &Path) -> io::Result<()> {
+    fs::create_dir_all(folder)?;
+    let mix = WavWriter::create(folder.join("mix.wav"), RECORD_SAMPLE_RATE)?;
+    cpu.set_audio_sink(Some(Box::new(mix)));
+    for channel in 1..=CHANNELS {
+        let stem = WavWriter::create(folder.join(format!("channel_{}.wav", channel)), RECORD_SAMPLE_RATE)?;
+        cpu.apu_mut().set_stem_sink(channel, Some(Box::new(stem)));
+    }
+    Ok(())
+}
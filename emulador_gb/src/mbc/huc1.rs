@@ -0,0 +1,134 @@
+use std::fmt;
+
+use super::input::{DarkInfrared, InfraredPort};
+use super::{banked, restore, Mapper, RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+const INFRARED_MODE: u8 = 0x0E; // Value written to 0x0000-0x1FFF to map the IR port
+
+/// HuC1 controller, up to 1MB of ROM, 32KB of RAM and an infrared port
+pub struct Huc1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    /// The RAM area accesses the infrared port instead of the RAM
+    infrared_mode: bool,
+    rom_bank: u8,
+    ram_bank: u8,
+    infrared: Box<dyn InfraredPort>,
+}
+
+impl fmt::Debug for Huc1 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Huc1")
+            .field("infrared_mode", &self.infrared_mode)
+            .field("rom_bank", &self.rom_bank)
+            .field("ram_bank", &self.ram_bank)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Huc1 {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+        Huc1 {
+            rom,
+            ram: vec![0; ram_size],
+            infrared_mode: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            infrared: Box::new(DarkInfrared),
+        }
+    }
+
+    /// Get the offset of a RAM address in the RAM image
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if self.ram.is_empty() {
+            return None;
+        }
+        Some((self.ram_bank as usize * RAM_BANK_SIZE + address as usize) % self.ram.len())
+    }
+}
+
+impl Mapper for Huc1 {
+    fn read_rom(&self, address: u16) -> u8 {
+        let offset = address as usize % ROM_BANK_SIZE;
+        if address < 0x4000 {
+            banked(&self.rom, 0, ROM_BANK_SIZE, offset)
+        } else {
+            banked(&self.rom, self.rom_bank as usize, ROM_BANK_SIZE, offset)
+        }
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            // There is no RAM enable, the register only switches between RAM and infrared
+            0x0000..=0x1FFF => self.infrared_mode = value & 0x0F == INFRARED_MODE,
+            0x2000..=0x3FFF => self.rom_bank = value & 0x3F,
+            0x4000..=0x5FFF => self.ram_bank = value & 0x03,
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if self.infrared_mode {
+            return if self.infrared.is_receiving() { 0xC1 } else { 0xC0 };
+        }
+        self.ram_offset(address).map_or(0xFF, |offset| self.ram[offset])
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) -> bool {
+        if self.infrared_mode {
+            self.infrared.set_led(value & 0x01 != 0);
+            return false;
+        }
+        let Some(offset) = self.ram_offset(address) else {
+            return false;
+        };
+        self.ram[offset] = value;
+        true
+    }
+
+    fn set_infrared(&mut self, infrared: Box<dyn InfraredPort>) {
+        self.infrared = infrared;
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        restore(&mut self.ram, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Port that sees its own LED
+    struct Mirror {
+        led: bool,
+    }
+
+    impl InfraredPort for Mirror {
+        fn set_led(&mut self, on: bool) {
+            self.led = on;
+        }
+
+        fn is_receiving(&self) -> bool {
+            self.led
+        }
+    }
+
+    #[test]
+    fn test_infrared_mode() {
+        let mut mbc = Huc1::new(vec![0; 4 * ROM_BANK_SIZE], RAM_BANK_SIZE);
+        mbc.set_infrared(Box::new(Mirror { led: false }));
+        mbc.write_ram(0x0000, 0x42);
+        assert_eq!(mbc.read_ram(0x0000), 0x42);
+        mbc.write_rom(0x0000, INFRARED_MODE);
+        assert_eq!(mbc.read_ram(0x0000), 0xC0);
+        mbc.write_ram(0x0000, 0x01);
+        assert_eq!(mbc.read_ram(0x0000), 0xC1);
+        mbc.write_rom(0x0000, 0x0A);
+        assert_eq!(mbc.read_ram(0x0000), 0x42);
+    }
+}
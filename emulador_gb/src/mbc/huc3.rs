@@ -0,0 +1,264 @@
+use std::fmt;
+
+use super::input::{DarkInfrared, InfraredPort};
+use super::rtc::{unix_time, RtcClock, CYCLES_PER_SECOND};
+use super::{banked, restore, Mapper, RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+const MINUTES_PER_DAY: u16 = 24 * 60;
+const DAYS_LIMIT: u16 = 0x1000; // Days are stored in 12 bits
+
+const RAM_READ_ONLY: u8 = 0x00; // Modes written to 0x0000-0x1FFF
+const RAM_READ_WRITE: u8 = 0x0A;
+const RTC_COMMAND: u8 = 0x0B;
+const RTC_RESPONSE: u8 = 0x0C;
+const RTC_SEMAPHORE: u8 = 0x0D;
+const INFRARED: u8 = 0x0E;
+
+const READ_AND_INCREMENT: u8 = 0x1; // RTC commands, written in bits 4-6
+const WRITE_AND_INCREMENT: u8 = 0x3;
+const SET_ADDRESS_LOW: u8 = 0x4;
+const SET_ADDRESS_HIGH: u8 = 0x5;
+const EXTENDED: u8 = 0x6;
+
+const LOAD_TIME: u8 = 0x0; // Arguments of the extended command
+const STORE_TIME: u8 = 0x1;
+const STATUS: u8 = 0x2;
+
+/// HuC3 controller, up to 2MB of ROM, 32KB of RAM, a clock and an infrared port
+pub struct Huc3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    /// Selects what the RAM area accesses
+    mode: u8,
+    rom_bank: u8,
+    ram_bank: u8,
+    clock: RtcClock,
+    /// Minutes of the current day
+    minutes: u16,
+    days: u16,
+    /// Cycles or host seconds that don't add up to a minute yet
+    seconds_cycles: u64,
+    last_update: u64,
+    /// 256 half bytes accessed through the RTC commands, the time is copied to 0x00-0x05
+    rtc_memory: [u8; 0x100],
+    rtc_address: u8,
+    command: u8,
+    response: u8,
+    infrared: Box<dyn InfraredPort>,
+}
+
+impl fmt::Debug for Huc3 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Huc3")
+            .field("mode", &self.mode)
+            .field("rom_bank", &self.rom_bank)
+            .field("ram_bank", &self.ram_bank)
+            .field("minutes", &self.minutes)
+            .field("days", &self.days)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Huc3 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, clock: RtcClock) -> Self {
+        Huc3 {
+            rom,
+            ram: vec![0; ram_size],
+            mode: RAM_READ_ONLY,
+            rom_bank: 1,
+            ram_bank: 0,
+            clock,
+            minutes: 0,
+            days: 0,
+            seconds_cycles: 0,
+            last_update: unix_time(),
+            rtc_memory: [0; 0x100],
+            rtc_address: 0,
+            command: 0,
+            response: 0,
+            infrared: Box::new(DarkInfrared),
+        }
+    }
+
+    /// Get the offset of a RAM address in the RAM image
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if self.ram.is_empty() {
+            return None;
+        }
+        Some((self.ram_bank as usize * RAM_BANK_SIZE + address as usize) % self.ram.len())
+    }
+
+    /// Advance the clock a number of minutes
+    fn advance(&mut self, minutes: u64) {
+        let total = self.minutes as u64 + minutes;
+        self.minutes = (total % MINUTES_PER_DAY as u64) as u16;
+        let days = self.days as u64 + total / MINUTES_PER_DAY as u64;
+        self.days = (days % DAYS_LIMIT as u64) as u16;
+    }
+
+    /// Catch up with the host clock
+    fn update(&mut self) {
+        if self.clock != RtcClock::Host {
+            return;
+        }
+        let now = unix_time();
+        self.seconds_cycles += now.saturating_sub(self.last_update);
+        self.last_update = now;
+        self.advance(self.seconds_cycles / 60);
+        self.seconds_cycles %= 60;
+    }
+
+    /// Execute a command written in RTC command mode
+    fn execute(&mut self, value: u8) {
+        self.command = (value >> 4) & 0x07;
+        let argument = value & 0x0F;
+        match self.command {
+            READ_AND_INCREMENT => {
+                self.response = self.rtc_memory[self.rtc_address as usize];
+                self.rtc_address = self.rtc_address.wrapping_add(1);
+            }
+            WRITE_AND_INCREMENT => {
+                self.rtc_memory[self.rtc_address as usize] = argument;
+                self.rtc_address = self.rtc_address.wrapping_add(1);
+            }
+            SET_ADDRESS_LOW => self.rtc_address = (self.rtc_address & 0xF0) | argument,
+            SET_ADDRESS_HIGH => self.rtc_address = (self.rtc_address & 0x0F) | argument << 4,
+            EXTENDED => match argument {
+                LOAD_TIME => {
+                    self.update();
+                    let time = self.minutes as u32 | (self.days as u32) << 12;
+                    for nibble in 0..6 {
+                        self.rtc_memory[nibble] = (time >> (nibble * 4)) as u8 & 0x0F;
+                    }
+                }
+                STORE_TIME => {
+                    let time = (0..6).fold(0u32, |time, nibble| {
+                        time | (self.rtc_memory[nibble] as u32) << (nibble * 4)
+                    });
+                    self.minutes = (time & 0xFFF) as u16 % MINUTES_PER_DAY;
+                    self.days = (time >> 12) as u16 % DAYS_LIMIT;
+                    self.seconds_cycles = 0;
+                    self.last_update = unix_time();
+                }
+                STATUS => self.response = 0x01,
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Huc3 {
+    fn read_rom(&self, address: u16) -> u8 {
+        let offset = address as usize % ROM_BANK_SIZE;
+        if address < 0x4000 {
+            banked(&self.rom, 0, ROM_BANK_SIZE, offset)
+        } else {
+            banked(&self.rom, self.rom_bank as usize, ROM_BANK_SIZE, offset)
+        }
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.mode = value & 0x0F,
+            0x2000..=0x3FFF => self.rom_bank = value & 0x7F,
+            0x4000..=0x5FFF => self.ram_bank = value & 0x03,
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        match self.mode {
+            RAM_READ_ONLY | RAM_READ_WRITE => self.ram_offset(address).map_or(0xFF, |offset| self.ram[offset]),
+            RTC_RESPONSE => 0x80 | self.command << 4 | self.response,
+            // Commands complete immediately so the RTC is always ready
+            RTC_SEMAPHORE => 0xFF,
+            INFRARED => {
+                if self.infrared.is_receiving() {
+                    0xC1
+                } else {
+                    0xC0
+                }
+            }
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) -> bool {
+        match self.mode {
+            RAM_READ_WRITE => {
+                let Some(offset) = self.ram_offset(address) else {
+                    return false;
+                };
+                self.ram[offset] = value;
+                return true;
+            }
+            // Only the RAM is saved, the clock restarts with the cartridge
+            RTC_COMMAND => self.execute(value),
+            INFRARED => self.infrared.set_led(value & 0x01 != 0),
+            _ => {}
+        }
+        false
+    }
+
+    fn step(&mut self, cycles: u32) {
+        if self.clock != RtcClock::Emulated {
+            return;
+        }
+        self.seconds_cycles += cycles as u64;
+        let cycles_per_minute = CYCLES_PER_SECOND as u64 * 60;
+        self.advance(self.seconds_cycles / cycles_per_minute);
+        self.seconds_cycles %= cycles_per_minute;
+    }
+
+    fn set_infrared(&mut self, infrared: Box<dyn InfraredPort>) {
+        self.infrared = infrared;
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        restore(&mut self.ram, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run a RTC command and return the response register
+    fn command(mbc: &mut Huc3, command: u8, argument: u8) -> u8 {
+        mbc.write_rom(0x0000, RTC_COMMAND);
+        mbc.write_ram(0x0000, command << 4 | argument);
+        mbc.write_rom(0x0000, RTC_RESPONSE);
+        mbc.read_ram(0x0000) & 0x0F
+    }
+
+    #[test]
+    fn test_clock() {
+        let mut mbc = Huc3::new(vec![0; 4 * ROM_BANK_SIZE], RAM_BANK_SIZE, RtcClock::Emulated);
+        for _ in 0..MINUTES_PER_DAY + 5 {
+            mbc.step(CYCLES_PER_SECOND * 60);
+        }
+        command(&mut mbc, EXTENDED, LOAD_TIME);
+        command(&mut mbc, SET_ADDRESS_LOW, 0x0);
+        command(&mut mbc, SET_ADDRESS_HIGH, 0x0);
+        let nibbles: Vec<u8> = (0..6).map(|_| command(&mut mbc, READ_AND_INCREMENT, 0)).collect();
+        assert_eq!(nibbles, vec![5, 0, 0, 1, 0, 0]);
+    }
+
+    #[test]
+    fn test_store_time() {
+        let mut mbc = Huc3::new(vec![0; 4 * ROM_BANK_SIZE], RAM_BANK_SIZE, RtcClock::Emulated);
+        command(&mut mbc, SET_ADDRESS_LOW, 0x0);
+        for nibble in [0xF, 0x3, 0x0, 0x2, 0x0, 0x0] {
+            command(&mut mbc, WRITE_AND_INCREMENT, nibble);
+        }
+        command(&mut mbc, EXTENDED, STORE_TIME);
+        assert_eq!(mbc.minutes, 0x3F);
+        assert_eq!(mbc.days, 2);
+        assert_eq!(command(&mut mbc, EXTENDED, STATUS), 0x01);
+    }
+}
@@ -0,0 +1,36 @@
+/// Tilt sensor of MBC7 cartridges, implemented by the host
+pub trait Accelerometer {
+    /// Get the tilt on the X and Y axes in g, 0.0 is flat and the range is about -1.0..=1.0
+    fn tilt(&mut self) -> (f32, f32);
+}
+
+/// Infrared LED and receiver of HuC1 and HuC3 cartridges, implemented by the host
+pub trait InfraredPort {
+    /// Turn the LED of the cartridge on or off
+    fn set_led(&mut self, on: bool);
+
+    /// Check if the receiver of the cartridge sees light
+    fn is_receiving(&self) -> bool;
+}
+
+/// Accelerometer that always reports the cartridge lying flat
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct FlatAccelerometer;
+
+impl Accelerometer for FlatAccelerometer {
+    fn tilt(&mut self) -> (f32, f32) {
+        (0.0, 0.0)
+    }
+}
+
+/// Infrared port with nothing in front of it
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DarkInfrared;
+
+impl InfraredPort for DarkInfrared {
+    fn set_led(&mut self, _on: bool) {}
+
+    fn is_receiving(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,205 @@
+use super::{banked, restore, Mapper, RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+/// Logo every licensed cartridge stores at 0x0104, multicarts repeat it in each game
+const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+const LOGO: usize = 0x0104;
+const MULTICART_SIZE: usize = 0x100000; // MBC1M carts are 1MB with a game every 16 banks
+const MULTICART_GAME_SIZE: usize = 0x10 * ROM_BANK_SIZE;
+
+/// MBC1 controller, up to 2MB of ROM and 32KB of RAM
+#[derive(Debug)]
+pub struct Mbc1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    /// 5 bit ROM bank register written at 0x2000-0x3FFF
+    bank1: u8,
+    /// 2 bit register written at 0x4000-0x5FFF, upper ROM bank bits or RAM bank
+    bank2: u8,
+    /// Banking mode written at 0x6000-0x7FFF, when set bank2 also applies to 0x0000 and RAM
+    mode: bool,
+    /// MBC1M wiring, bank2 is shifted by 4 instead of 5
+    multicart: bool,
+}
+
+impl Mbc1 {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+        let multicart = is_multicart(&rom);
+        Mbc1 {
+            rom,
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            bank1: 1,
+            bank2: 0,
+            mode: false,
+            multicart,
+        }
+    }
+
+    /// Check if the cartridge uses the MBC1M wiring
+    pub fn is_multicart(&self) -> bool {
+        self.multicart
+    }
+
+    /// Get the ROM bank mapped at 0x0000-0x3FFF
+    fn low_bank(&self) -> usize {
+        if self.mode {
+            self.upper_bits()
+        } else {
+            0
+        }
+    }
+
+    /// Get the ROM bank mapped at 0x4000-0x7FFF
+    fn high_bank(&self) -> usize {
+        let bank1 = if self.multicart { self.bank1 & 0x0F } else { self.bank1 };
+        self.upper_bits() | bank1 as usize
+    }
+
+    /// Get bank2 in its position of the ROM bank number
+    fn upper_bits(&self) -> usize {
+        let shift = if self.multicart { 4 } else { 5 };
+        (self.bank2 as usize) << shift
+    }
+
+    /// Get the offset of a RAM address in the RAM image
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return None;
+        }
+        let bank = if self.mode { self.bank2 as usize } else { 0 };
+        Some((bank * RAM_BANK_SIZE + address as usize) % self.ram.len())
+    }
+}
+
+/// Check if a 1MB image repeats the logo at the start of the second game
+fn is_multicart(rom: &[u8]) -> bool {
+    rom.len() == MULTICART_SIZE
+        && rom[MULTICART_GAME_SIZE + LOGO..MULTICART_GAME_SIZE + LOGO + NINTENDO_LOGO.len()] == NINTENDO_LOGO
+}
+
+impl Mapper for Mbc1 {
+    fn read_rom(&self, address: u16) -> u8 {
+        let offset = address as usize % ROM_BANK_SIZE;
+        if address < 0x4000 {
+            banked(&self.rom, self.low_bank(), ROM_BANK_SIZE, offset)
+        } else {
+            banked(&self.rom, self.high_bank(), ROM_BANK_SIZE, offset)
+        }
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                // Bank 0 can't be selected, writing 0 to the 5 bit register maps bank 1
+                self.bank1 = value & 0x1F;
+                if self.bank1 == 0 {
+                    self.bank1 = 1;
+                }
+            }
+            0x4000..=0x5FFF => self.bank2 = value & 0x03,
+            _ => self.mode = value & 0x01 != 0,
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        self.ram_offset(address).map_or(0xFF, |offset| self.ram[offset])
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) -> bool {
+        let Some(offset) = self.ram_offset(address) else {
+            return false;
+        };
+        self.ram[offset] = value;
+        true
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        restore(&mut self.ram, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ROM with the bank number written at the start of every bank
+    fn rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0; banks * ROM_BANK_SIZE];
+        for bank in 0..banks {
+            rom[bank * ROM_BANK_SIZE] = bank as u8;
+        }
+        rom
+    }
+
+    #[test]
+    fn test_rom_banking() {
+        let mut mbc = Mbc1::new(rom(128), 0);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        mbc.write_rom(0x2000, 0x05);
+        assert_eq!(mbc.read_rom(0x4000), 5);
+        mbc.write_rom(0x4000, 0x01);
+        assert_eq!(mbc.read_rom(0x4000), 0x25);
+        assert_eq!(mbc.read_rom(0x0000), 0);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_rom(0x0000), 0x20);
+    }
+
+    #[test]
+    fn test_bank_zero_remap() {
+        let mut mbc = Mbc1::new(rom(128), 0);
+        mbc.write_rom(0x4000, 0x02);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 0x41);
+        // Only the lower 5 bits are checked, 0x20 maps bank 0 of the 512KB block
+        mbc.write_rom(0x2000, 0x20);
+        assert_eq!(mbc.read_rom(0x4000), 0x41);
+        // Small ROMs mask the bank number after the remap
+        let mut mbc = Mbc1::new(rom(4), 0);
+        mbc.write_rom(0x2000, 0x04);
+        assert_eq!(mbc.read_rom(0x4000), 0);
+    }
+
+    #[test]
+    fn test_ram_banking() {
+        let mut mbc = Mbc1::new(rom(4), 0x8000);
+        mbc.write_ram(0x0000, 0x42);
+        assert_eq!(mbc.read_ram(0x0000), 0xFF);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(0x0000, 0x42);
+        mbc.write_rom(0x4000, 0x02);
+        assert_eq!(mbc.read_ram(0x0000), 0x42);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(0x0000), 0x00);
+        mbc.write_ram(0x0000, 0x24);
+        mbc.write_rom(0x6000, 0x00);
+        assert_eq!(mbc.read_ram(0x0000), 0x42);
+        mbc.write_rom(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(0x0000), 0xFF);
+    }
+
+    #[test]
+    fn test_multicart() {
+        let mut image = rom(64);
+        for game in 0..4 {
+            let logo = game * MULTICART_GAME_SIZE + LOGO;
+            image[logo..logo + NINTENDO_LOGO.len()].copy_from_slice(&NINTENDO_LOGO);
+        }
+        let mut mbc = Mbc1::new(image, 0);
+        assert!(mbc.is_multicart());
+        mbc.write_rom(0x4000, 0x01);
+        mbc.write_rom(0x2000, 0x12);
+        assert_eq!(mbc.read_rom(0x4000), 0x12);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_rom(0x0000), 0x10);
+    }
+}
@@ -0,0 +1,96 @@
+use super::{banked, restore, Mapper, ROM_BANK_SIZE};
+
+const RAM_SIZE: usize = 0x200; // 512 half bytes built into the controller
+
+/// MBC2 controller, up to 256KB of ROM and 512x4 bits of internal RAM
+#[derive(Debug)]
+pub struct Mbc2 {
+    rom: Vec<u8>,
+    ram: [u8; RAM_SIZE],
+    ram_enabled: bool,
+    rom_bank: u8,
+}
+
+impl Mbc2 {
+    pub fn new(rom: Vec<u8>) -> Self {
+        Mbc2 {
+            rom,
+            ram: [0; RAM_SIZE],
+            ram_enabled: false,
+            rom_bank: 1,
+        }
+    }
+}
+
+impl Mapper for Mbc2 {
+    fn read_rom(&self, address: u16) -> u8 {
+        let offset = address as usize % ROM_BANK_SIZE;
+        if address < 0x4000 {
+            banked(&self.rom, 0, ROM_BANK_SIZE, offset)
+        } else {
+            banked(&self.rom, self.rom_bank as usize, ROM_BANK_SIZE, offset)
+        }
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        if address >= 0x4000 {
+            return;
+        }
+        // Bit 8 of the address selects between the RAM enable and the ROM bank registers
+        if address & 0x0100 == 0 {
+            self.ram_enabled = value & 0x0F == 0x0A;
+        } else {
+            self.rom_bank = value & 0x0F;
+            if self.rom_bank == 0 {
+                self.rom_bank = 1;
+            }
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        // Only the lower 4 bits are stored, the upper bits are open bus
+        self.ram[address as usize % RAM_SIZE] | 0xF0
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) -> bool {
+        if self.ram_enabled {
+            self.ram[address as usize % RAM_SIZE] = value & 0x0F;
+        }
+        self.ram_enabled
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.to_vec()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        restore(&mut self.ram, data);
+        for nibble in self.ram.iter_mut() {
+            *nibble &= 0x0F;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registers_and_ram() {
+        let mut rom = vec![0; 16 * ROM_BANK_SIZE];
+        rom[0x0F * ROM_BANK_SIZE] = 0x0F;
+        let mut mbc = Mbc2::new(rom);
+        mbc.write_rom(0x2100, 0x0F);
+        assert_eq!(mbc.read_rom(0x4000), 0x0F);
+        // Writes with bit 8 clear go to the RAM enable register
+        mbc.write_rom(0x2000, 0x0A);
+        assert_eq!(mbc.read_rom(0x4000), 0x0F);
+        mbc.write_ram(0x0000, 0x5A);
+        assert_eq!(mbc.read_ram(0x0000), 0xFA);
+        // The 512 half bytes are echoed through the whole area
+        assert_eq!(mbc.read_ram(0x0200), 0xFA);
+    }
+}
@@ -0,0 +1,154 @@
+use super::rtc::{Rtc, RtcClock};
+use super::{banked, restore, Mapper, RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+/// MBC3 controller, up to 2MB of ROM, 32KB of RAM and an optional real-time clock
+#[derive(Debug)]
+pub struct Mbc3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rtc: Option<Rtc>,
+    /// Enables both the RAM and the RTC registers
+    ram_enabled: bool,
+    rom_bank: u8,
+    /// RAM bank 0x00-0x07 or RTC register 0x08-0x0C
+    ram_bank: u8,
+    /// Last value written to 0x6000-0x7FFF, the RTC is latched when 0x00 is followed by 0x01
+    latch: u8,
+}
+
+impl Mbc3 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, rtc: Option<RtcClock>) -> Self {
+        Mbc3 {
+            rom,
+            ram: vec![0; ram_size],
+            rtc: rtc.map(Rtc::new),
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            latch: 0xFF,
+        }
+    }
+
+    /// Get the offset of a RAM address in the RAM image
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if !self.ram_enabled || self.ram.is_empty() || self.ram_bank > 0x07 {
+            return None;
+        }
+        Some((self.ram_bank as usize * RAM_BANK_SIZE + address as usize) % self.ram.len())
+    }
+}
+
+impl Mapper for Mbc3 {
+    fn read_rom(&self, address: u16) -> u8 {
+        let offset = address as usize % ROM_BANK_SIZE;
+        if address < 0x4000 {
+            banked(&self.rom, 0, ROM_BANK_SIZE, offset)
+        } else {
+            banked(&self.rom, self.rom_bank as usize, ROM_BANK_SIZE, offset)
+        }
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                // MBC30 uses the 8 bits of the register to address 4MB
+                self.rom_bank = if self.rom.len() > 0x80 * ROM_BANK_SIZE { value } else { value & 0x7F };
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            }
+            0x4000..=0x5FFF => self.ram_bank = value & 0x0F,
+            _ => {
+                if self.latch == 0x00 && value == 0x01 {
+                    if let Some(rtc) = self.rtc.as_mut() {
+                        rtc.latch();
+                    }
+                }
+                self.latch = value;
+            }
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if self.ram_enabled && Rtc::is_register(self.ram_bank) {
+            return self.rtc.as_ref().map_or(0xFF, |rtc| rtc.read(self.ram_bank));
+        }
+        self.ram_offset(address).map_or(0xFF, |offset| self.ram[offset])
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) -> bool {
+        if self.ram_enabled && Rtc::is_register(self.ram_bank) {
+            let Some(rtc) = self.rtc.as_mut() else {
+                return false;
+            };
+            rtc.write(self.ram_bank, value);
+            return true;
+        }
+        let Some(offset) = self.ram_offset(address) else {
+            return false;
+        };
+        self.ram[offset] = value;
+        true
+    }
+
+    fn step(&mut self, cycles: u32) {
+        if let Some(rtc) = self.rtc.as_mut() {
+            rtc.step(cycles);
+        }
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        restore(&mut self.ram, data);
+    }
+
+    fn rtc(&self) -> Option<&Rtc> {
+        self.rtc.as_ref()
+    }
+
+    fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        self.rtc.as_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rom_and_ram_banking() {
+        let mut rom = vec![0; 0x80 * ROM_BANK_SIZE];
+        rom[0x7F * ROM_BANK_SIZE] = 0x7F;
+        let mut mbc = Mbc3::new(rom, 0x8000, None);
+        mbc.write_rom(0x2000, 0x7F);
+        assert_eq!(mbc.read_rom(0x4000), 0x7F);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x03);
+        mbc.write_ram(0x0010, 0x42);
+        mbc.write_rom(0x4000, 0x00);
+        assert_eq!(mbc.read_ram(0x0010), 0x00);
+        mbc.write_rom(0x4000, 0x03);
+        assert_eq!(mbc.read_ram(0x0010), 0x42);
+    }
+
+    #[test]
+    fn test_rtc_latch() {
+        let mut mbc = Mbc3::new(vec![0; 2 * ROM_BANK_SIZE], 0, Some(RtcClock::Emulated));
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x08);
+        mbc.step(4_194_304 * 5);
+        assert_eq!(mbc.read_ram(0x0000), 0);
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(0x0000), 5);
+        mbc.step(4_194_304);
+        assert_eq!(mbc.read_ram(0x0000), 5);
+        // Writing 0x01 again without 0x00 doesn't latch
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(0x0000), 5);
+    }
+}
@@ -0,0 +1,169 @@
+use std::fmt;
+
+use super::{banked, restore, Mapper, RumbleCallback, RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+const RUMBLE_BIT: u8 = 1 << 3; // Bit of the RAM bank register wired to the motor
+
+/// MBC5 controller, up to 8MB of ROM (512 banks) and 128KB of RAM (16 banks)
+pub struct Mbc5 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    /// 9 bit ROM bank, low byte written at 0x2000-0x2FFF and bit 8 at 0x3000-0x3FFF
+    rom_bank: u16,
+    ram_bank: u8,
+    /// Rumble carts use bit 3 of the RAM bank register for the motor
+    has_rumble: bool,
+    rumble: bool,
+    rumble_callback: Option<RumbleCallback>,
+}
+
+impl fmt::Debug for Mbc5 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mbc5")
+            .field("ram_enabled", &self.ram_enabled)
+            .field("rom_bank", &self.rom_bank)
+            .field("ram_bank", &self.ram_bank)
+            .field("has_rumble", &self.has_rumble)
+            .field("rumble", &self.rumble)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Mbc5 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, has_rumble: bool) -> Self {
+        Mbc5 {
+            rom,
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            has_rumble,
+            rumble: false,
+            rumble_callback: None,
+        }
+    }
+
+    /// Check if the rumble motor is on
+    pub fn is_rumbling(&self) -> bool {
+        self.rumble
+    }
+
+    /// Get the offset of a RAM address in the RAM image
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return None;
+        }
+        Some((self.ram_bank as usize * RAM_BANK_SIZE + address as usize) % self.ram.len())
+    }
+
+    /// Write the RAM bank register, on rumble carts bit 3 drives the motor
+    fn write_ram_bank(&mut self, value: u8) {
+        if !self.has_rumble {
+            self.ram_bank = value & 0x0F;
+            return;
+        }
+        self.ram_bank = value & 0x07;
+        let rumble = value & RUMBLE_BIT != 0;
+        if rumble != self.rumble {
+            self.rumble = rumble;
+            if let Some(callback) = self.rumble_callback.as_mut() {
+                callback(rumble);
+            }
+        }
+    }
+}
+
+impl Mapper for Mbc5 {
+    fn read_rom(&self, address: u16) -> u8 {
+        let offset = address as usize % ROM_BANK_SIZE;
+        if address < 0x4000 {
+            banked(&self.rom, 0, ROM_BANK_SIZE, offset)
+        } else {
+            // Unlike MBC1 and MBC3, bank 0 can be mapped at 0x4000
+            banked(&self.rom, self.rom_bank as usize, ROM_BANK_SIZE, offset)
+        }
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value == 0x0A,
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | value as u16,
+            0x3000..=0x3FFF => self.rom_bank = (self.rom_bank & 0xFF) | ((value as u16 & 0x01) << 8),
+            0x4000..=0x5FFF => self.write_ram_bank(value),
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        self.ram_offset(address).map_or(0xFF, |offset| self.ram[offset])
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) -> bool {
+        let Some(offset) = self.ram_offset(address) else {
+            return false;
+        };
+        self.ram[offset] = value;
+        true
+    }
+
+    fn set_rumble_callback(&mut self, callback: RumbleCallback) {
+        self.rumble_callback = Some(callback);
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        restore(&mut self.ram, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_nine_bit_rom_bank() {
+        let mut rom = vec![0; 0x200 * ROM_BANK_SIZE];
+        rom[0x1FF * ROM_BANK_SIZE] = 0x42;
+        rom[0x100 * ROM_BANK_SIZE] = 0x24;
+        let mut mbc = Mbc5::new(rom, 0, false);
+        mbc.write_rom(0x2000, 0xFF);
+        mbc.write_rom(0x3000, 0x01);
+        assert_eq!(mbc.read_rom(0x4000), 0x42);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 0x24);
+        mbc.write_rom(0x3000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 0x00);
+    }
+
+    #[test]
+    fn test_ram_banks() {
+        let mut mbc = Mbc5::new(vec![0; 2 * ROM_BANK_SIZE], 16 * RAM_BANK_SIZE, false);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x0F);
+        mbc.write_ram(0x0000, 0x42);
+        mbc.write_rom(0x4000, 0x07);
+        assert_eq!(mbc.read_ram(0x0000), 0x00);
+        mbc.write_rom(0x4000, 0x0F);
+        assert_eq!(mbc.read_ram(0x0000), 0x42);
+    }
+
+    #[test]
+    fn test_rumble_callback() {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let mut mbc = Mbc5::new(vec![0; 2 * ROM_BANK_SIZE], 4 * RAM_BANK_SIZE, true);
+        let recorded = Rc::clone(&events);
+        mbc.set_rumble_callback(Box::new(move |on| recorded.borrow_mut().push(on)));
+        mbc.write_rom(0x4000, 0x09);
+        mbc.write_rom(0x4000, 0x0A);
+        mbc.write_rom(0x4000, 0x02);
+        assert_eq!(*events.borrow(), vec![true, false]);
+        assert!(!mbc.is_rumbling());
+        assert_eq!(mbc.ram_bank, 0x02);
+    }
+}
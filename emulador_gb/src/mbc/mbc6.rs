@@ -0,0 +1,214 @@
+use super::{banked, restore, Mapper};
+
+const ROM_HALF_BANK_SIZE: usize = 0x2000; // MBC6 maps two independent 8KB windows of ROM
+const RAM_HALF_BANK_SIZE: usize = 0x1000; // and two independent 4KB windows of RAM
+const FLASH_SIZE: usize = 0x100000;
+const FLASH_SECTOR_SIZE: usize = 0x20000;
+
+/// Progress of the command sequence of the flash chip
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum FlashCommand {
+    Idle,
+    /// 0xAA was written to 0x5555
+    Unlock1,
+    /// 0x55 was written to 0x2AAA
+    Unlock2,
+    /// 0xA0 was received, the next write programs a byte
+    Program,
+    /// 0x80 was received, an erase needs a second unlock sequence
+    EraseSetup,
+    EraseUnlock1,
+    EraseUnlock2,
+}
+
+/// Window of 8KB of ROM or flash at 0x4000-0x5FFF or 0x6000-0x7FFF
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct RomWindow {
+    bank: u8,
+    flash: bool,
+}
+
+/// MBC6 controller, 1MB of ROM, 32KB of RAM and 1MB of flash, all in half size banks
+#[derive(Debug)]
+pub struct Mbc6 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    flash: Vec<u8>,
+    ram_enabled: bool,
+    ram_banks: [u8; 2],
+    windows: [RomWindow; 2],
+    flash_enabled: bool,
+    flash_write_enabled: bool,
+    command: FlashCommand,
+}
+
+impl Mbc6 {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+        Mbc6 {
+            rom,
+            ram: vec![0; ram_size],
+            flash: vec![0xFF; FLASH_SIZE],
+            ram_enabled: false,
+            ram_banks: [0; 2],
+            windows: [RomWindow { bank: 0, flash: false }; 2],
+            flash_enabled: false,
+            flash_write_enabled: false,
+            command: FlashCommand::Idle,
+        }
+    }
+
+    /// Get the offset of a RAM address in the RAM image
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return None;
+        }
+        let window = address as usize / RAM_HALF_BANK_SIZE;
+        let offset = address as usize % RAM_HALF_BANK_SIZE;
+        Some((self.ram_banks[window] as usize * RAM_HALF_BANK_SIZE + offset) % self.ram.len())
+    }
+
+    /// Handle a write to a window mapped to flash
+    fn write_flash(&mut self, window: RomWindow, offset: usize, value: u8) {
+        if !self.flash_enabled {
+            return;
+        }
+        let address = (window.bank as usize * ROM_HALF_BANK_SIZE + offset) % FLASH_SIZE;
+        // The unlock addresses are decoded from the flash address bits 0-14
+        let unlock = address & 0x7FFF;
+        self.command = match (self.command, unlock, value) {
+            (FlashCommand::Program, _, _) => {
+                if self.flash_write_enabled {
+                    // Programming can only clear bits
+                    self.flash[address] &= value;
+                }
+                FlashCommand::Idle
+            }
+            (_, _, 0xF0) => FlashCommand::Idle,
+            (FlashCommand::Idle, 0x5555, 0xAA) => FlashCommand::Unlock1,
+            (FlashCommand::Unlock1, 0x2AAA, 0x55) => FlashCommand::Unlock2,
+            (FlashCommand::Unlock2, 0x5555, 0xA0) => FlashCommand::Program,
+            (FlashCommand::Unlock2, 0x5555, 0x80) => FlashCommand::EraseSetup,
+            (FlashCommand::EraseSetup, 0x5555, 0xAA) => FlashCommand::EraseUnlock1,
+            (FlashCommand::EraseUnlock1, 0x2AAA, 0x55) => FlashCommand::EraseUnlock2,
+            (FlashCommand::EraseUnlock2, _, 0x30) => {
+                if self.flash_write_enabled {
+                    let sector = address - address % FLASH_SECTOR_SIZE;
+                    self.flash[sector..sector + FLASH_SECTOR_SIZE].fill(0xFF);
+                }
+                FlashCommand::Idle
+            }
+            (FlashCommand::EraseUnlock2, 0x5555, 0x10) => {
+                if self.flash_write_enabled {
+                    self.flash.fill(0xFF);
+                }
+                FlashCommand::Idle
+            }
+            _ => FlashCommand::Idle,
+        };
+    }
+}
+
+impl Mapper for Mbc6 {
+    fn read_rom(&self, address: u16) -> u8 {
+        let offset = address as usize % ROM_HALF_BANK_SIZE;
+        match address {
+            // The first 16KB are fixed to the start of the ROM
+            0x0000..=0x3FFF => banked(&self.rom, 0, ROM_HALF_BANK_SIZE, address as usize),
+            _ => {
+                let window = self.windows[(address as usize - 0x4000) / ROM_HALF_BANK_SIZE];
+                if window.flash {
+                    banked(&self.flash, window.bank as usize, ROM_HALF_BANK_SIZE, offset)
+                } else {
+                    banked(&self.rom, window.bank as usize, ROM_HALF_BANK_SIZE, offset)
+                }
+            }
+        }
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x03FF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x0400..=0x07FF => self.ram_banks[0] = value & 0x07,
+            0x0800..=0x0BFF => self.ram_banks[1] = value & 0x07,
+            0x0C00..=0x0FFF => self.flash_enabled = value & 0x01 != 0,
+            0x1000 => self.flash_write_enabled = value & 0x01 != 0,
+            0x2000..=0x27FF => self.windows[0].bank = value & 0x7F,
+            0x2800..=0x2FFF => self.windows[0].flash = value == 0x08,
+            0x3000..=0x37FF => self.windows[1].bank = value & 0x7F,
+            0x3800..=0x3FFF => self.windows[1].flash = value == 0x08,
+            0x4000..=0x7FFF => {
+                let window = self.windows[(address as usize - 0x4000) / ROM_HALF_BANK_SIZE];
+                if window.flash {
+                    self.write_flash(window, address as usize % ROM_HALF_BANK_SIZE, value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        self.ram_offset(address).map_or(0xFF, |offset| self.ram[offset])
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) -> bool {
+        let Some(offset) = self.ram_offset(address) else {
+            return false;
+        };
+        self.ram[offset] = value;
+        true
+    }
+
+    /// The flash is saved after the RAM
+    fn save_data(&self) -> Vec<u8> {
+        let mut data = self.ram.clone();
+        data.extend_from_slice(&self.flash);
+        data
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        restore(&mut self.ram, data);
+        if let Some(flash) = data.get(self.ram.len()..) {
+            restore(&mut self.flash, flash);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_independent_windows() {
+        let mut rom = vec![0; 0x100000];
+        rom[3 * ROM_HALF_BANK_SIZE] = 3;
+        rom[7 * ROM_HALF_BANK_SIZE] = 7;
+        let mut mbc = Mbc6::new(rom, 0x8000);
+        mbc.write_rom(0x2000, 3);
+        mbc.write_rom(0x3000, 7);
+        assert_eq!(mbc.read_rom(0x4000), 3);
+        assert_eq!(mbc.read_rom(0x6000), 7);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x0800, 0x01);
+        mbc.write_ram(0x1000, 0x42);
+        mbc.write_rom(0x0400, 0x01);
+        assert_eq!(mbc.read_ram(0x0000), 0x42);
+    }
+
+    #[test]
+    fn test_flash_program() {
+        let mut mbc = Mbc6::new(vec![0; 0x100000], 0);
+        mbc.write_rom(0x0C00, 0x01);
+        mbc.write_rom(0x1000, 0x01);
+        // Window A at flash bank 2 holds 0x4000-0x5FFF, window B at bank 1 holds 0x2000-0x3FFF
+        mbc.write_rom(0x2000, 0x02);
+        mbc.write_rom(0x2800, 0x08);
+        mbc.write_rom(0x3000, 0x01);
+        mbc.write_rom(0x3800, 0x08);
+        mbc.write_rom(0x5555, 0xAA);
+        mbc.write_rom(0x6AAA, 0x55);
+        mbc.write_rom(0x5555, 0xA0);
+        mbc.write_rom(0x4010, 0x42);
+        assert_eq!(mbc.read_rom(0x4010), 0x42);
+        assert_eq!(mbc.read_rom(0x4011), 0xFF);
+    }
+}
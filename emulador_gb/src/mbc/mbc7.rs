@@ -0,0 +1,342 @@
+use std::fmt;
+
+use super::input::{Accelerometer, FlatAccelerometer};
+use super::{banked, Mapper, ROM_BANK_SIZE};
+
+const ACCELEROMETER_CENTER: u16 = 0x81D0; // Value reported when the cartridge is flat
+const ACCELEROMETER_SCALE: f32 = 0x70 as f32; // Change of the value for 1g of tilt
+const ERASE_VALUE: u8 = 0x55; // Written to 0xA000 to prepare a new sample
+const LATCH_VALUE: u8 = 0xAA; // Written to 0xA010 to take a sample
+
+const EEPROM_WORDS: usize = 128; // 93LC56, 128 16 bit words
+const CS: u8 = 1 << 7; // EEPROM pins in the register at 0xA080
+const CLK: u8 = 1 << 6;
+const DI: u8 = 1 << 1;
+const DO: u8 = 1 << 0;
+
+/// State of the serial protocol of the EEPROM
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum EepromState {
+    /// Waiting for the start bit
+    Idle,
+    /// Receiving the 2 bit opcode and the 8 bit address
+    Command,
+    /// Shifting out the bits of a word
+    Read,
+    /// Receiving the 16 bit word of a WRITE or WRAL
+    Write { address: Option<u8> },
+}
+
+/// 93LC56 serial EEPROM used as save memory by MBC7 cartridges
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Eeprom {
+    words: [u16; EEPROM_WORDS],
+    state: EepromState,
+    pins: u8,
+    shift: u16,
+    bits: u8,
+    write_enabled: bool,
+}
+
+impl Eeprom {
+    fn new() -> Self {
+        Eeprom {
+            words: [0xFFFF; EEPROM_WORDS],
+            state: EepromState::Idle,
+            pins: DO,
+            shift: 0,
+            bits: 0,
+            write_enabled: false,
+        }
+    }
+
+    /// Update the pins, the protocol advances on the rising edge of CLK while CS is high.
+    /// Returns true if the words were written or erased
+    fn write(&mut self, value: u8) -> bool {
+        let rising = self.pins & CLK == 0 && value & CLK != 0;
+        let output = self.pins & DO;
+        self.pins = (value & (CS | CLK | DI)) | output;
+        if value & CS == 0 {
+            self.state = EepromState::Idle;
+            self.pins |= DO;
+            return false;
+        }
+        rising && self.clock(value & DI != 0)
+    }
+
+    /// Read the pins, DO holds the output of the EEPROM
+    fn read(&self) -> u8 {
+        self.pins
+    }
+
+    fn set_output(&mut self, high: bool) {
+        if high {
+            self.pins |= DO;
+        } else {
+            self.pins &= !DO;
+        }
+    }
+
+    /// Handle a rising edge of CLK with the bit on DI, returns true if the words changed
+    fn clock(&mut self, input: bool) -> bool {
+        match self.state {
+            EepromState::Idle => {
+                if input {
+                    self.state = EepromState::Command;
+                    self.shift = 0;
+                    self.bits = 0;
+                }
+            }
+            EepromState::Command => {
+                self.shift = self.shift << 1 | input as u16;
+                self.bits += 1;
+                if self.bits == 10 {
+                    return self.command((self.shift >> 8) as u8 & 0x03, self.shift as u8);
+                }
+            }
+            EepromState::Read => {
+                self.set_output(self.shift & 0x8000 != 0);
+                self.shift <<= 1;
+                self.bits -= 1;
+                if self.bits == 0 {
+                    self.state = EepromState::Idle;
+                }
+            }
+            EepromState::Write { address } => {
+                self.shift = self.shift << 1 | input as u16;
+                self.bits += 1;
+                if self.bits == 16 {
+                    if self.write_enabled {
+                        match address {
+                            Some(address) => self.words[address as usize % EEPROM_WORDS] = self.shift,
+                            None => self.words = [self.shift; EEPROM_WORDS],
+                        }
+                    }
+                    self.state = EepromState::Idle;
+                    self.set_output(true);
+                    return self.write_enabled;
+                }
+            }
+        }
+        false
+    }
+
+    /// Decode a command once the opcode and the address have been received, returns true if
+    /// it erased words
+    fn command(&mut self, opcode: u8, address: u8) -> bool {
+        self.shift = 0;
+        self.bits = 0;
+        self.state = EepromState::Idle;
+        match opcode {
+            // READ, a dummy 0 is output before the word
+            0b10 => {
+                self.shift = self.words[address as usize % EEPROM_WORDS];
+                self.bits = 16;
+                self.state = EepromState::Read;
+                self.set_output(false);
+            }
+            // WRITE
+            0b01 => self.state = EepromState::Write { address: Some(address) },
+            // ERASE
+            0b11 => {
+                if self.write_enabled {
+                    self.words[address as usize % EEPROM_WORDS] = 0xFFFF;
+                }
+                return self.write_enabled;
+            }
+            _ => match address >> 6 {
+                0b11 => self.write_enabled = true,
+                0b00 => self.write_enabled = false,
+                // ERAL
+                0b10 => {
+                    if self.write_enabled {
+                        self.words = [0xFFFF; EEPROM_WORDS];
+                    }
+                    return self.write_enabled;
+                }
+                // WRAL
+                _ => self.state = EepromState::Write { address: None },
+            },
+        }
+        false
+    }
+}
+
+/// MBC7 controller, up to 2MB of ROM, an accelerometer and a serial EEPROM
+pub struct Mbc7 {
+    rom: Vec<u8>,
+    rom_bank: u8,
+    /// The RAM area needs 0x0A written to 0x0000-0x1FFF and 0x40 to 0x4000-0x5FFF
+    ram_enabled1: bool,
+    ram_enabled2: bool,
+    /// Sample of the accelerometer, 0x8000 until a sample is latched
+    x: u16,
+    y: u16,
+    latched: bool,
+    eeprom: Eeprom,
+    accelerometer: Box<dyn Accelerometer>,
+}
+
+impl fmt::Debug for Mbc7 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mbc7")
+            .field("rom_bank", &self.rom_bank)
+            .field("x", &self.x)
+            .field("y", &self.y)
+            .field("eeprom", &self.eeprom)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Mbc7 {
+    pub fn new(rom: Vec<u8>) -> Self {
+        Mbc7 {
+            rom,
+            rom_bank: 1,
+            ram_enabled1: false,
+            ram_enabled2: false,
+            x: 0x8000,
+            y: 0x8000,
+            latched: false,
+            eeprom: Eeprom::new(),
+            accelerometer: Box::new(FlatAccelerometer),
+        }
+    }
+
+    /// Take a sample of the accelerometer of the host
+    fn latch(&mut self) {
+        let (x, y) = self.accelerometer.tilt();
+        self.x = (ACCELEROMETER_CENTER as f32 + x * ACCELEROMETER_SCALE) as u16;
+        self.y = (ACCELEROMETER_CENTER as f32 + y * ACCELEROMETER_SCALE) as u16;
+        self.latched = true;
+    }
+}
+
+impl Mapper for Mbc7 {
+    fn read_rom(&self, address: u16) -> u8 {
+        let offset = address as usize % ROM_BANK_SIZE;
+        if address < 0x4000 {
+            banked(&self.rom, 0, ROM_BANK_SIZE, offset)
+        } else {
+            banked(&self.rom, self.rom_bank as usize, ROM_BANK_SIZE, offset)
+        }
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled1 = value == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = value & 0x7F,
+            0x4000..=0x5FFF => self.ram_enabled2 = value == 0x40,
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled1 || !self.ram_enabled2 || address >= 0x1000 {
+            return 0xFF;
+        }
+        // The registers are selected by bits 4-7 of the address
+        match (address >> 4) & 0x0F {
+            0x2 => self.x as u8,
+            0x3 => (self.x >> 8) as u8,
+            0x4 => self.y as u8,
+            0x5 => (self.y >> 8) as u8,
+            0x6 => 0x00,
+            0x8 => self.eeprom.read(),
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) -> bool {
+        if !self.ram_enabled1 || !self.ram_enabled2 || address >= 0x1000 {
+            return false;
+        }
+        match (address >> 4) & 0x0F {
+            0x0 if value == ERASE_VALUE => {
+                self.x = 0x8000;
+                self.y = 0x8000;
+                self.latched = false;
+            }
+            0x1 if value == LATCH_VALUE && !self.latched => self.latch(),
+            0x8 => return self.eeprom.write(value),
+            _ => {}
+        }
+        false
+    }
+
+    fn set_accelerometer(&mut self, accelerometer: Box<dyn Accelerometer>) {
+        self.accelerometer = accelerometer;
+    }
+
+    /// The EEPROM words are saved in little endian
+    fn save_data(&self) -> Vec<u8> {
+        self.eeprom.words.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        for (word, bytes) in self.eeprom.words.iter_mut().zip(data.chunks_exact(2)) {
+            *word = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Tilted;
+
+    impl Accelerometer for Tilted {
+        fn tilt(&mut self) -> (f32, f32) {
+            (1.0, -1.0)
+        }
+    }
+
+    fn enabled() -> Mbc7 {
+        let mut mbc = Mbc7::new(vec![0; 4 * ROM_BANK_SIZE]);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x40);
+        mbc
+    }
+
+    /// Clock a number of bits into the EEPROM, returning the bits read from DO
+    fn send(mbc: &mut Mbc7, value: u32, bits: u8) -> u32 {
+        let mut output = 0;
+        for bit in (0..bits).rev() {
+            let di = if value >> bit & 1 != 0 { DI } else { 0 };
+            mbc.write_ram(0x0080, CS | di);
+            mbc.write_ram(0x0080, CS | CLK | di);
+            output = output << 1 | (mbc.read_ram(0x0080) & DO) as u32;
+        }
+        output
+    }
+
+    #[test]
+    fn test_accelerometer() {
+        let mut mbc = enabled();
+        mbc.set_accelerometer(Box::new(Tilted));
+        // The sensor isn't saved
+        assert!(!mbc.write_ram(0x0000, ERASE_VALUE));
+        assert!(!mbc.write_ram(0x0010, LATCH_VALUE));
+        let x = mbc.read_ram(0x0020) as u16 | (mbc.read_ram(0x0030) as u16) << 8;
+        let y = mbc.read_ram(0x0040) as u16 | (mbc.read_ram(0x0050) as u16) << 8;
+        assert_eq!(x, ACCELEROMETER_CENTER + 0x70);
+        assert_eq!(y, ACCELEROMETER_CENTER - 0x70);
+    }
+
+    #[test]
+    fn test_eeprom_write_and_read() {
+        let mut mbc = enabled();
+        // EWEN, start bit, opcode 00, address 11xxxxxx
+        send(&mut mbc, 0b100_1100_0000, 11);
+        mbc.write_ram(0x0080, 0);
+        // WRITE 0xBEEF to word 5
+        send(&mut mbc, 0b101_0000_0101, 11);
+        send(&mut mbc, 0xBEEF, 16);
+        mbc.write_ram(0x0080, 0);
+        // READ word 5, the first bit is the dummy 0
+        send(&mut mbc, 0b110_0000_0101, 11);
+        let word = send(&mut mbc, 0, 16);
+        assert_eq!(word, 0xBEEF);
+    }
+}
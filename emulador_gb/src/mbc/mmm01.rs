@@ -0,0 +1,163 @@
+use super::{banked, restore, Mapper, RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+const MAP_BIT: u8 = 1 << 6; // Written to 0x0000-0x1FFF to lock the game selection
+
+/// MMM01 controller used by multicarts, the menu in the last 32KB selects a game which is then
+/// banked like a MBC1 cartridge inside its part of the ROM
+#[derive(Debug)]
+pub struct Mmm01 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    /// Game selection locked, until then the menu is mapped
+    mapped: bool,
+    ram_enabled: bool,
+    /// Bits 0-4 of the ROM bank, selected by the game
+    rom_bank_low: u8,
+    /// Bits 5-6 and 7-8 of the ROM bank, selected by the menu
+    rom_bank_mid: u8,
+    rom_bank_high: u8,
+    /// Bits of rom_bank_low fixed by the menu
+    rom_bank_mask: u8,
+    ram_bank_low: u8,
+    ram_bank_high: u8,
+    mode: bool,
+}
+
+impl Mmm01 {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+        Mmm01 {
+            rom,
+            ram: vec![0; ram_size],
+            mapped: false,
+            ram_enabled: false,
+            rom_bank_low: 0,
+            rom_bank_mid: 0,
+            rom_bank_high: 0,
+            rom_bank_mask: 0,
+            ram_bank_low: 0,
+            ram_bank_high: 0,
+            mode: false,
+        }
+    }
+
+    /// Get the ROM bank of the selected game with the bits not fixed by the menu cleared
+    fn game_base(&self) -> usize {
+        (self.rom_bank_high as usize) << 7
+            | (self.rom_bank_mid as usize) << 5
+            | (self.rom_bank_low & self.rom_bank_mask) as usize
+    }
+
+    /// Get the ROM bank mapped at 0x4000-0x7FFF
+    fn high_bank(&self) -> usize {
+        let mut selected = self.rom_bank_low & !self.rom_bank_mask;
+        // Like MBC1, bank 0 of the game maps bank 1 instead
+        if selected == 0 {
+            selected = 1;
+        }
+        self.game_base() | selected as usize
+    }
+
+    /// Get the offset of a RAM address in the RAM image
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return None;
+        }
+        let low = if self.mode { self.ram_bank_low } else { 0 };
+        let bank = (self.ram_bank_high << 2 | low) as usize;
+        Some((bank * RAM_BANK_SIZE + address as usize) % self.ram.len())
+    }
+}
+
+impl Mapper for Mmm01 {
+    fn read_rom(&self, address: u16) -> u8 {
+        let offset = address as usize % ROM_BANK_SIZE;
+        if !self.mapped {
+            // The menu lives in the last 32KB of the ROM
+            let banks = (self.rom.len() / ROM_BANK_SIZE).max(2);
+            let bank = banks - 2 + address as usize / ROM_BANK_SIZE;
+            return banked(&self.rom, bank, ROM_BANK_SIZE, offset);
+        }
+        if address < 0x4000 {
+            banked(&self.rom, self.game_base(), ROM_BANK_SIZE, offset)
+        } else {
+            banked(&self.rom, self.high_bank(), ROM_BANK_SIZE, offset)
+        }
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => {
+                self.ram_enabled = value & 0x0F == 0x0A;
+                if value & MAP_BIT != 0 {
+                    self.mapped = true;
+                }
+            }
+            0x2000..=0x3FFF => {
+                self.rom_bank_low = (self.rom_bank_low & self.rom_bank_mask) | (value & 0x1F & !self.rom_bank_mask);
+                if !self.mapped {
+                    self.rom_bank_mid = (value >> 5) & 0x03;
+                }
+            }
+            0x4000..=0x5FFF => {
+                self.ram_bank_low = value & 0x03;
+                if !self.mapped {
+                    self.ram_bank_high = (value >> 2) & 0x03;
+                    self.rom_bank_high = (value >> 4) & 0x03;
+                }
+            }
+            _ => {
+                self.mode = value & 0x01 != 0;
+                if !self.mapped {
+                    self.rom_bank_mask = (value << 1) & 0x1E;
+                }
+            }
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        self.ram_offset(address).map_or(0xFF, |offset| self.ram[offset])
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) -> bool {
+        let Some(offset) = self.ram_offset(address) else {
+            return false;
+        };
+        self.ram[offset] = value;
+        true
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        restore(&mut self.ram, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_menu_then_game() {
+        let mut rom = vec![0; 64 * ROM_BANK_SIZE];
+        for bank in 0..64 {
+            rom[bank * ROM_BANK_SIZE] = bank as u8;
+        }
+        let mut mbc = Mmm01::new(rom, 0);
+        assert_eq!(mbc.read_rom(0x0000), 62);
+        assert_eq!(mbc.read_rom(0x4000), 63);
+        // Select the game at bank 0x20 with 4 banks, the menu fixes bits 2-4
+        mbc.write_rom(0x2000, 0x20);
+        mbc.write_rom(0x6000, 0x0E);
+        mbc.write_rom(0x0000, MAP_BIT);
+        assert_eq!(mbc.read_rom(0x0000), 0x20);
+        assert_eq!(mbc.read_rom(0x4000), 0x21);
+        mbc.write_rom(0x2000, 0x1F);
+        assert_eq!(mbc.read_rom(0x4000), 0x23);
+        // The outer bank can't be changed after mapping
+        mbc.write_rom(0x2000, 0x40);
+        assert_eq!(mbc.read_rom(0x0000), 0x20);
+    }
+}
@@ -0,0 +1,114 @@
+use std::fmt;
+
+use crate::cartridge::{CartridgeError, Header};
+
+mod huc1;
+mod huc3;
+mod input;
+mod mbc1;
+mod mbc2;
+mod mbc3;
+mod mbc5;
+mod mbc6;
+mod mbc7;
+mod mmm01;
+mod rom_only;
+mod rtc;
+
+pub use huc1::Huc1;
+pub use huc3::Huc3;
+pub use input::{Accelerometer, DarkInfrared, FlatAccelerometer, InfraredPort};
+pub use mbc1::Mbc1;
+pub use mbc2::Mbc2;
+pub use mbc3::Mbc3;
+pub use mbc5::Mbc5;
+pub use mbc6::Mbc6;
+pub use mbc7::Mbc7;
+pub use mmm01::Mmm01;
+pub use rom_only::RomOnly;
+pub use rtc::{Rtc, RtcClock, RTC_FOOTER_SIZE};
+
+pub const ROM_BANK_SIZE: usize = 0x4000; // Size of the banks mapped at ROM_BANK_0 and ROM_BANK_1
+pub const RAM_BANK_SIZE: usize = 0x2000; // Size of the banks mapped at CARTRIDGE_RAM
+
+/// Called with the new state of the rumble motor each time it's switched on or off
+pub type RumbleCallback = Box<dyn FnMut(bool)>;
+
+/// Memory bank controller of a cartridge, it decodes the ROM and external RAM areas
+pub trait Mapper: fmt::Debug {
+    /// Read a byte from the ROM area 0x0000-0x7FFF
+    fn read_rom(&self, address: u16) -> u8;
+
+    /// Write to the ROM area 0x0000-0x7FFF, used to set the controller registers
+    fn write_rom(&mut self, address: u16, value: u8);
+
+    /// Read a byte from the external RAM area, the address is relative to 0xA000
+    fn read_ram(&self, address: u16) -> u8;
+
+    /// Write a byte to the external RAM area, the address is relative to 0xA000. Returns true
+    /// if the write changed the state kept by the battery
+    fn write_ram(&mut self, address: u16, value: u8) -> bool;
+
+    /// Advance the hardware of the cartridge, the cycles are 4.19MHz clock cycles
+    fn step(&mut self, _cycles: u32) {}
+
+    /// Observe the rumble motor, ignored by cartridges without one
+    fn set_rumble_callback(&mut self, _callback: RumbleCallback) {}
+
+    /// Connect the tilt sensor of the host, ignored by cartridges without one
+    fn set_accelerometer(&mut self, _accelerometer: Box<dyn Accelerometer>) {}
+
+    /// Connect the infrared port of the host, ignored by cartridges without one
+    fn set_infrared(&mut self, _infrared: Box<dyn InfraredPort>) {}
+
+    /// Get the battery backed memory, written to the .sav file
+    fn save_data(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Restore the battery backed memory from the contents of a .sav file
+    fn load_save_data(&mut self, _data: &[u8]) {}
+
+    /// Get the real-time clock stored after the memory in the .sav file
+    fn rtc(&self) -> Option<&Rtc> {
+        None
+    }
+
+    /// Get the real-time clock to restore it from the .sav file
+    fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        None
+    }
+}
+
+/// Create the mapper declared by the cartridge type byte at 0x0147
+pub fn new_mapper(header: &Header, rom: Vec<u8>, rtc_clock: RtcClock) -> Result<Box<dyn Mapper>, CartridgeError> {
+    match header.cartridge_type {
+        0x00 | 0x08 | 0x09 => Ok(Box::new(RomOnly::new(rom, header.ram_size))),
+        0x01..=0x03 => Ok(Box::new(Mbc1::new(rom, header.ram_size))),
+        0x05 | 0x06 => Ok(Box::new(Mbc2::new(rom))),
+        0x0B..=0x0D => Ok(Box::new(Mmm01::new(rom, header.ram_size))),
+        0x0F | 0x10 => Ok(Box::new(Mbc3::new(rom, header.ram_size, Some(rtc_clock)))),
+        0x11..=0x13 => Ok(Box::new(Mbc3::new(rom, header.ram_size, None))),
+        0x19..=0x1B => Ok(Box::new(Mbc5::new(rom, header.ram_size, false))),
+        0x1C..=0x1E => Ok(Box::new(Mbc5::new(rom, header.ram_size, true))),
+        0x20 => Ok(Box::new(Mbc6::new(rom, header.ram_size))),
+        0x22 => Ok(Box::new(Mbc7::new(rom))),
+        0xFE => Ok(Box::new(Huc3::new(rom, header.ram_size, rtc_clock))),
+        0xFF => Ok(Box::new(Huc1::new(rom, header.ram_size))),
+        cartridge_type => Err(CartridgeError::UnsupportedCartridgeType(cartridge_type)),
+    }
+}
+
+/// Get a byte of a bank, banks past the end of the image wrap around
+fn banked(data: &[u8], bank: usize, bank_size: usize, offset: usize) -> u8 {
+    if data.is_empty() {
+        return 0xFF;
+    }
+    data[(bank * bank_size + offset) % data.len()]
+}
+
+/// Copy a saved memory image, a shorter save leaves the rest of the memory untouched
+fn restore(memory: &mut [u8], data: &[u8]) {
+    let length = memory.len().min(data.len());
+    memory[..length].copy_from_slice(&data[..length]);
+}
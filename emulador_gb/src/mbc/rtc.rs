@@ -0,0 +1,317 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub(super) const CYCLES_PER_SECOND: u32 = 4_194_304;
+
+const SECONDS: u8 = 0x08; // RTC register numbers selected through the RAM bank register
+const MINUTES: u8 = 0x09;
+const HOURS: u8 = 0x0A;
+const DAYS_LOW: u8 = 0x0B;
+const DAYS_HIGH: u8 = 0x0C; // Bit 0 day bit 8, bit 6 halt, bit 7 day carry
+
+const HALT_BIT: u8 = 1 << 6;
+const CARRY_BIT: u8 = 1 << 7;
+
+pub const RTC_FOOTER_SIZE: usize = 48; // BGB/SameBoy footer appended to the RAM in .sav files
+const RTC_FOOTER_SIZE_32: usize = 44; // Older footer with a 32 bit timestamp
+
+/// Source of time used to advance a real-time clock
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RtcClock {
+    /// Advances with the cycles run by the emulator, deterministic
+    Emulated,
+    /// Advances with the clock of the host, like the real cartridge
+    Host,
+}
+
+/// Real-time clock of MBC3 cartridges
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rtc {
+    clock: RtcClock,
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    days: u16,
+    halt: bool,
+    carry: bool,
+    /// Copy of the registers taken by the latch sequence, indexed from SECONDS
+    latched: [u8; 5],
+    /// Cycles of the current second when using the emulated clock
+    cycles: u32,
+    /// Last time the counters were updated when using the host clock
+    last_update: u64,
+}
+
+/// Get the seconds since the unix epoch of the host
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+impl Rtc {
+    pub fn new(clock: RtcClock) -> Self {
+        Rtc {
+            clock,
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            days: 0,
+            halt: false,
+            carry: false,
+            latched: [0; 5],
+            cycles: 0,
+            last_update: unix_time(),
+        }
+    }
+
+    /// Check if a RAM bank number selects a RTC register
+    pub fn is_register(bank: u8) -> bool {
+        (SECONDS..=DAYS_HIGH).contains(&bank)
+    }
+
+    /// Advance the emulated clock, the cycles are 4.19MHz clock cycles
+    pub fn step(&mut self, cycles: u32) {
+        if self.clock != RtcClock::Emulated {
+            return;
+        }
+        self.cycles += cycles;
+        let seconds = self.cycles / CYCLES_PER_SECOND;
+        self.cycles %= CYCLES_PER_SECOND;
+        self.advance(seconds as u64);
+    }
+
+    /// Catch up with the host clock
+    fn update(&mut self) {
+        if self.clock != RtcClock::Host {
+            return;
+        }
+        let now = unix_time();
+        let elapsed = now.saturating_sub(self.last_update);
+        self.last_update = now;
+        self.advance(elapsed);
+    }
+
+    /// Copy the counters to the latched registers read by the CPU
+    pub fn latch(&mut self) {
+        self.update();
+        self.latched = [
+            self.seconds,
+            self.minutes,
+            self.hours,
+            self.days as u8,
+            self.days_high(),
+        ];
+    }
+
+    /// Read a latched register
+    pub fn read(&self, register: u8) -> u8 {
+        self.latched[(register - SECONDS) as usize]
+    }
+
+    /// Write a register, the counters are modified directly
+    pub fn write(&mut self, register: u8, value: u8) {
+        self.update();
+        match register {
+            SECONDS => {
+                self.seconds = value & 0x3F;
+                // Writing the seconds resets the sub-second counter
+                self.cycles = 0;
+            }
+            MINUTES => self.minutes = value & 0x3F,
+            HOURS => self.hours = value & 0x1F,
+            DAYS_LOW => self.days = (self.days & 0x100) | value as u16,
+            DAYS_HIGH => {
+                self.days = (self.days & 0xFF) | ((value as u16 & 0x01) << 8);
+                self.halt = value & HALT_BIT != 0;
+                self.carry = value & CARRY_BIT != 0;
+            }
+            _ => {}
+        }
+        self.latched[(register - SECONDS) as usize] = match register {
+            DAYS_HIGH => self.days_high(),
+            DAYS_LOW => self.days as u8,
+            HOURS => self.hours,
+            MINUTES => self.minutes,
+            _ => self.seconds,
+        };
+    }
+
+    /// Encode the counters, the latched registers and the current time as a .sav footer,
+    /// each register is a little endian 32 bit word followed by a 64 bit unix timestamp
+    pub fn save_footer(&self) -> Vec<u8> {
+        let mut footer = Vec::with_capacity(RTC_FOOTER_SIZE);
+        let counters = [self.seconds, self.minutes, self.hours, self.days as u8, self.days_high()];
+        for register in counters.iter().chain(self.latched.iter()) {
+            footer.extend_from_slice(&(*register as u32).to_le_bytes());
+        }
+        footer.extend_from_slice(&self.timestamp().to_le_bytes());
+        footer
+    }
+
+    /// Restore the clock from a .sav footer, returns false if the footer is not valid
+    pub fn load_footer(&mut self, footer: &[u8]) -> bool {
+        if footer.len() != RTC_FOOTER_SIZE && footer.len() != RTC_FOOTER_SIZE_32 {
+            return false;
+        }
+        let register = |index: usize| footer[index * 4];
+        self.seconds = register(0) & 0x3F;
+        self.minutes = register(1) & 0x3F;
+        self.hours = register(2) & 0x1F;
+        self.days = register(3) as u16 | (register(4) as u16 & 0x01) << 8;
+        self.halt = register(4) & HALT_BIT != 0;
+        self.carry = register(4) & CARRY_BIT != 0;
+        for (index, latched) in self.latched.iter_mut().enumerate() {
+            *latched = register(5 + index);
+        }
+        let mut timestamp = [0; 8];
+        timestamp[..footer.len() - 40].copy_from_slice(&footer[40..]);
+        self.cycles = 0;
+        // The host clock catches up with the time spent while the emulator was closed
+        self.last_update = u64::from_le_bytes(timestamp);
+        self.update();
+        true
+    }
+
+    /// Get the time stored in the footer, the emulated clock doesn't follow the host
+    fn timestamp(&self) -> u64 {
+        match self.clock {
+            RtcClock::Emulated => unix_time(),
+            RtcClock::Host => self.last_update,
+        }
+    }
+
+    /// Get the value of the DH register
+    fn days_high(&self) -> u8 {
+        let mut value = (self.days >> 8) as u8 & 0x01;
+        if self.halt {
+            value |= HALT_BIT;
+        }
+        if self.carry {
+            value |= CARRY_BIT;
+        }
+        value
+    }
+
+    /// Advance the counters a number of seconds unless the clock is halted
+    fn advance(&mut self, mut seconds: u64) {
+        if self.halt {
+            return;
+        }
+        // Out of range values count up to the register limit before wrapping, so they are
+        // ticked one second at a time until every counter is valid again
+        while seconds > 0 && (self.seconds >= 60 || self.minutes >= 60 || self.hours >= 24) {
+            self.tick();
+            seconds -= 1;
+        }
+        if seconds == 0 {
+            return;
+        }
+        let total = self.seconds as u64 + seconds;
+        self.seconds = (total % 60) as u8;
+        let total = self.minutes as u64 + total / 60;
+        self.minutes = (total % 60) as u8;
+        let total = self.hours as u64 + total / 60;
+        self.hours = (total % 24) as u8;
+        let total = self.days as u64 + total / 24;
+        if total > 0x1FF {
+            self.carry = true;
+        }
+        self.days = (total & 0x1FF) as u16;
+    }
+
+    /// Advance the counters by one second
+    fn tick(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3F;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+        self.minutes = (self.minutes + 1) & 0x3F;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+        self.hours = (self.hours + 1) & 0x1F;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+        self.days += 1;
+        if self.days > 0x1FF {
+            self.days = 0;
+            self.carry = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_emulated_clock() {
+        let mut rtc = Rtc::new(RtcClock::Emulated);
+        rtc.step(CYCLES_PER_SECOND * 61);
+        rtc.latch();
+        assert_eq!(rtc.read(SECONDS), 1);
+        assert_eq!(rtc.read(MINUTES), 1);
+        rtc.write(DAYS_HIGH, HALT_BIT);
+        rtc.step(CYCLES_PER_SECOND * 10);
+        rtc.latch();
+        assert_eq!(rtc.read(SECONDS), 1);
+    }
+
+    #[test]
+    fn test_day_carry() {
+        let mut rtc = Rtc::new(RtcClock::Emulated);
+        rtc.write(DAYS_LOW, 0xFF);
+        rtc.write(DAYS_HIGH, 0x01);
+        rtc.write(HOURS, 23);
+        rtc.write(MINUTES, 59);
+        rtc.write(SECONDS, 59);
+        rtc.step(CYCLES_PER_SECOND);
+        rtc.latch();
+        assert_eq!(rtc.read(DAYS_LOW), 0);
+        assert_eq!(rtc.read(DAYS_HIGH), CARRY_BIT);
+    }
+
+    #[test]
+    fn test_footer_round_trip() {
+        let mut rtc = Rtc::new(RtcClock::Emulated);
+        rtc.write(DAYS_LOW, 0x34);
+        rtc.write(DAYS_HIGH, 0x01 | HALT_BIT);
+        rtc.write(HOURS, 12);
+        rtc.latch();
+        let footer = rtc.save_footer();
+        assert_eq!(footer.len(), RTC_FOOTER_SIZE);
+        assert_eq!(footer[8], 12);
+        assert_eq!(footer[16], 0x01 | HALT_BIT);
+        let mut loaded = Rtc::new(RtcClock::Emulated);
+        assert!(loaded.load_footer(&footer));
+        assert_eq!(loaded, Rtc { last_update: loaded.last_update, ..rtc });
+        assert!(!loaded.load_footer(&footer[..40]));
+    }
+
+    #[test]
+    fn test_host_clock_catches_up() {
+        let mut rtc = Rtc::new(RtcClock::Host);
+        let mut footer = rtc.save_footer();
+        // Saved 90 seconds ago with the old 32 bit timestamp
+        footer.truncate(RTC_FOOTER_SIZE_32);
+        footer[40..].copy_from_slice(&((unix_time() - 90) as u32).to_le_bytes());
+        assert!(rtc.load_footer(&footer));
+        rtc.latch();
+        assert_eq!(rtc.read(MINUTES), 1);
+        assert!((30..=31).contains(&rtc.read(SECONDS)));
+    }
+
+    #[test]
+    fn test_invalid_seconds_wrap_without_carry() {
+        let mut rtc = Rtc::new(RtcClock::Emulated);
+        rtc.write(SECONDS, 63);
+        rtc.step(CYCLES_PER_SECOND);
+        rtc.latch();
+        assert_eq!(rtc.read(SECONDS), 0);
+        assert_eq!(rtc.read(MINUTES), 0);
+    }
+}
@@ -0,0 +1,606 @@
+use crate::apu::{Apu, NR10, WAVE_RAM_END};
+use crate::cartridge::{Cartridge, CgbSupport};
+use crate::dma::{Dma, DMA};
+use crate::hdma::{Hdma, BLOCK_CYCLES, BLOCK_SIZE, HDMA1, HDMA5};
+use crate::interrupts::{Interrupt, INTERRUPT_ENABLE, INTERRUPT_FLAG};
+use crate::joypad::{Button, Joypad, JOYP};
+use crate::ppu::{Ppu, BCPS, LCDC, OCPD, VBK, WX};
+use crate::serial::{Serial, SerialDevice, SB, SC};
+use crate::timer::{Timer, DIV, TAC};
+
+pub const ROM_BANK_0: usize = 0x0000; // ROM Bank 0 (32KB) HOME BANK
+pub const ROM_BANK_1: usize = 0x4000; // ROM Bank 1 (32KB)
+pub const VRAM: usize = 0x8000; // VRAM (8KB) Background tiles
+pub const CARTRIDGE_RAM:usize = 0xA000;
+pub const WORK_RAM: usize = 0xC000; // RAM Bank 0 (8KB)
+pub const ECHO_RAM: usize = 0xE000; // Mirror of WORK_RAM up to OAM
+pub const OAM: usize = 0xFE00; // OAM (Sprites) (160 bytes) also tiles
+pub const UNUSABLE: usize = 0xFEA0; // Not connected, reads return open bus
+pub const IO_REGISTERS: usize = 0xFF00; // IO Registros (80 bytes)
+pub const HIGH_RAM: usize = 0xFF80; // Memoria de alto rendimiento (128 bytes) //Acceso un ciclo mas rapido
+
+pub const KEY1: u16 = 0xFF4D; // CGB speed switch, bit 0 prepares it and bit 7 is the current speed
+pub const SVBK: u16 = 0xFF70; // CGB work RAM bank mapped at 0xD000
+
+const WORK_RAM_SIZE: usize = ECHO_RAM - WORK_RAM;
+const WORK_RAM_BANK_SIZE: usize = 0x1000;
+const WORK_RAM_BANKS: usize = 8; // Bank 0 is fixed at 0xC000, CGB switches banks 1-7 at 0xD000
+const IO_REGISTERS_SIZE: usize = HIGH_RAM - IO_REGISTERS;
+const HIGH_RAM_SIZE: usize = INTERRUPT_ENABLE as usize - HIGH_RAM;
+
+const PREPARE_SPEED_SWITCH: u8 = 1 << 0; // KEY1 bits
+const DOUBLE_SPEED: u8 = 1 << 7;
+
+/// Value read from addresses that nothing drives
+pub const OPEN_BUS: u8 = 0xFF;
+
+/// Hardware being emulated
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Model {
+    /// Original game boy
+    Dmg,
+    /// Game boy color, with banked VRAM and work RAM and the double speed mode
+    Cgb,
+}
+
+impl Model {
+    /// Get the model a cartridge runs on, games with CGB support get the game boy color
+    pub fn for_cartridge(cartridge: &Cartridge) -> Self {
+        match cartridge.header().cgb {
+            CgbSupport::None => Model::Dmg,
+            CgbSupport::Compatible | CgbSupport::Only => Model::Cgb,
+        }
+    }
+}
+
+/// Bus used by the CPU to access the memory map
+pub trait MemoryBus {
+    /// Read a byte from the bus
+    fn read8(&self, address: u16) -> u8;
+
+    /// Write a byte to the bus
+    fn write8(&mut self, address: u16, value: u8);
+
+    /// Read a little endian word from the bus
+    fn read16(&self, address: u16) -> u16 {
+        self.read8(address) as u16 | (self.read8(address.wrapping_add(1)) as u16) << 8
+    }
+
+    /// Write a little endian word to the bus
+    fn write16(&mut self, address: u16, value: u16) {
+        self.write8(address, value as u8);
+        self.write8(address.wrapping_add(1), (value >> 8) as u8);
+    }
+}
+
+/// Game boy memory map, each region is backed by its own component
+#[derive(Debug)]
+pub struct Memory {
+    model: Model,
+    cartridge: Option<Cartridge>,
+    /// Owns VRAM, OAM and the LCD registers
+    ppu: Ppu,
+    dma: Dma,
+    hdma: Hdma,
+    /// M-cycles the CPU has to wait for the VRAM DMA
+    stall_cycles: u32,
+    timer: Timer,
+    joypad: Joypad,
+    serial: Serial,
+    apu: Apu,
+    /// All the work RAM banks, the DMG only uses the first two
+    work_ram: [u8; WORK_RAM_BANK_SIZE * WORK_RAM_BANKS],
+    /// Work RAM bank selected by SVBK, 1-7
+    work_ram_bank: usize,
+    /// A speed switch was prepared through KEY1 and happens on the next STOP
+    speed_switch_prepared: bool,
+    /// CGB double speed mode, the CPU, the timer, the serial port and the OAM DMA run twice as fast
+    double_speed: bool,
+    io_registers: [u8; IO_REGISTERS_SIZE],
+    high_ram: [u8; HIGH_RAM_SIZE],
+    interrupt_enable: u8,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Implement the Memory struct
+impl Memory {
+    /// Create the memory map of a DMG
+    pub fn new() -> Self {
+        Self::with_model(Model::Dmg)
+    }
+
+    /// Create the memory map of a model
+    pub fn with_model(model: Model) -> Self {
+        Memory {
+            model,
+            cartridge: None,
+            ppu: Ppu::with_model(model),
+            dma: Dma::new(),
+            hdma: Hdma::new(),
+            stall_cycles: 0,
+            timer: Timer::new(),
+            joypad: Joypad::new(),
+            serial: Serial::new(),
+            apu: Apu::new(),
+            work_ram: [0; WORK_RAM_BANK_SIZE * WORK_RAM_BANKS],
+            work_ram_bank: 1,
+            speed_switch_prepared: false,
+            double_speed: false,
+            io_registers: [0; IO_REGISTERS_SIZE],
+            high_ram: [0; HIGH_RAM_SIZE],
+            interrupt_enable: 0,
+        }
+    }
+
+    /// Get the model being emulated
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    /// Check if the CGB double speed mode is on
+    pub fn is_double_speed(&self) -> bool {
+        self.double_speed
+    }
+
+    /// Perform the speed switch prepared through KEY1, called by STOP. Returns false when there
+    /// wasn't one and STOP has to enter STOP mode
+    pub fn switch_speed(&mut self) -> bool {
+        if !self.speed_switch_prepared {
+            return false;
+        }
+        self.speed_switch_prepared = false;
+        self.double_speed = !self.double_speed;
+        true
+    }
+
+    /// Insert a cartridge, mapping its ROM at ROM_BANK_0 and its RAM at CARTRIDGE_RAM
+    pub fn load_cartridge(&mut self, cartridge: Cartridge) {
+        self.cartridge = Some(cartridge);
+    }
+
+    /// Get the inserted cartridge
+    pub fn cartridge(&self) -> Option<&Cartridge> {
+        self.cartridge.as_ref()
+    }
+
+    /// Get the inserted cartridge mutably
+    pub fn cartridge_mut(&mut self) -> Option<&mut Cartridge> {
+        self.cartridge.as_mut()
+    }
+
+    /// Get the PPU
+    pub fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    /// Get the PPU mutably
+    pub fn ppu_mut(&mut self) -> &mut Ppu {
+        &mut self.ppu
+    }
+
+    /// Get the OAM DMA controller
+    pub fn dma(&self) -> &Dma {
+        &self.dma
+    }
+
+    /// Get the VRAM DMA controller
+    pub fn hdma(&self) -> &Hdma {
+        &self.hdma
+    }
+
+    /// Take the M-cycles the CPU has to wait for the VRAM DMA since the last call
+    pub fn take_stall_cycles(&mut self) -> u32 {
+        std::mem::take(&mut self.stall_cycles)
+    }
+
+    /// Get the joypad
+    pub fn joypad(&self) -> &Joypad {
+        &self.joypad
+    }
+
+    /// Hold a button of the joypad, requesting the Joypad interrupt if one of its lines falls
+    pub fn press(&mut self, button: Button) {
+        self.io_registers[INTERRUPT_FLAG as usize - IO_REGISTERS] |= self.joypad.press(button);
+    }
+
+    /// Let go of a button of the joypad
+    pub fn release(&mut self, button: Button) {
+        self.joypad.release(button);
+    }
+
+    /// Get the serial port
+    pub fn serial(&self) -> &Serial {
+        &self.serial
+    }
+
+    /// Plug a device into the link port
+    pub fn set_serial_device(&mut self, device: Box<dyn SerialDevice>) {
+        self.serial.set_device(device);
+    }
+
+    /// Get the APU
+    pub fn apu(&self) -> &Apu {
+        &self.apu
+    }
+
+    /// Get the APU mutably, to set the sample rate and take the samples
+    pub fn apu_mut(&mut self) -> &mut Apu {
+        &mut self.apu
+    }
+
+    /// Advance the components of the memory map, the cycles are CPU clock cycles, 4 per M-cycle.
+    /// In double speed mode the PPU, the APU and the cartridge clock see half of them
+    pub fn step(&mut self, cycles: u32) {
+        let normal_cycles = if self.double_speed { cycles / 2 } else { cycles };
+        if let Some(cartridge) = self.cartridge.as_mut() {
+            cartridge.step(normal_cycles);
+        }
+        for _ in 0..self.dma.advance(cycles) {
+            if let Some((source, offset)) = self.dma.tick() {
+                let value = self.dma_read(source);
+                self.dma.latch(value);
+                self.ppu.write_oam_dma(offset, value);
+            }
+        }
+        self.apu.step(normal_cycles);
+        let interrupts = self.ppu.step(normal_cycles) | self.timer.step(cycles) | self.serial.step(cycles);
+        self.io_registers[INTERRUPT_FLAG as usize - IO_REGISTERS] |= interrupts;
+        for _ in 0..self.ppu.take_hblanks() {
+            if self.hdma.is_hblank_active() {
+                self.copy_vram_dma_block();
+            }
+        }
+    }
+
+    /// Copy the next block of the VRAM DMA, the CPU waits for it
+    fn copy_vram_dma_block(&mut self) {
+        let Some((source, destination)) = self.hdma.next_block() else {
+            return;
+        };
+        for offset in 0..BLOCK_SIZE {
+            let address = source.wrapping_add(offset);
+            let value = match address as usize {
+                // VRAM can't be a source, E000-FFFF reads cartridge RAM
+                VRAM..CARTRIDGE_RAM => OPEN_BUS,
+                ECHO_RAM.. => self.read_mapped(address - (ECHO_RAM - CARTRIDGE_RAM) as u16),
+                _ => self.read_mapped(address),
+            };
+            self.ppu.write_vram_dma(destination + offset, value);
+        }
+        // The block takes the same time in both speeds, twice the M-cycles in double speed
+        self.stall_cycles += if self.double_speed { BLOCK_CYCLES * 2 } else { BLOCK_CYCLES };
+    }
+
+    /// Advance the hardware that keeps running in STOP mode, the link port can still be clocked
+    /// by the other side
+    pub fn step_stopped(&mut self, cycles: u32) {
+        self.io_registers[INTERRUPT_FLAG as usize - IO_REGISTERS] |= self.serial.step(cycles);
+    }
+
+    /// Get the interrupts that are both requested and enabled
+    pub fn pending_interrupts(&self) -> u8 {
+        self.interrupt_enable & self.io_registers[INTERRUPT_FLAG as usize - IO_REGISTERS] & 0x1F
+    }
+
+    /// Set the bit of an interrupt in the IF register
+    pub fn request_interrupt(&mut self, interrupt: Interrupt) {
+        self.io_registers[INTERRUPT_FLAG as usize - IO_REGISTERS] |= interrupt.bit();
+    }
+
+    /// Clear the bit of an interrupt in the IF register
+    pub fn clear_interrupt(&mut self, interrupt: Interrupt) {
+        self.io_registers[INTERRUPT_FLAG as usize - IO_REGISTERS] &= !interrupt.bit();
+    }
+
+    /// Get the index in work RAM of an offset from 0xC000, 0x1000 and up is the bank of SVBK
+    fn work_ram_index(&self, offset: usize) -> usize {
+        if offset < WORK_RAM_BANK_SIZE {
+            offset
+        } else {
+            self.work_ram_bank * WORK_RAM_BANK_SIZE + offset - WORK_RAM_BANK_SIZE
+        }
+    }
+
+    /// Read a byte for the OAM DMA, it sees the memory map without the CPU restrictions
+    fn dma_read(&self, address: u16) -> u8 {
+        let address_usize = address as usize;
+        match address_usize {
+            VRAM..CARTRIDGE_RAM => self.ppu.vram()[address_usize - VRAM],
+            // Sources past work RAM read its echo
+            ECHO_RAM.. => self.work_ram[self.work_ram_index((address_usize - ECHO_RAM) % WORK_RAM_SIZE)],
+            _ => self.read_mapped(address),
+        }
+    }
+
+    /// Get the value the CPU sees when its access collides with a running OAM DMA. OAM is busy,
+    /// and the bus used by the DMA (video for VRAM, external for the rest) returns its byte
+    fn dma_conflict(&self, address: u16) -> Option<u8> {
+        let source = self.dma.source()? as usize;
+        let address = address as usize;
+        let is_video = |address: usize| (VRAM..CARTRIDGE_RAM).contains(&address);
+        match address {
+            OAM..UNUSABLE => Some(OPEN_BUS),
+            UNUSABLE.. => None,
+            _ if is_video(address) == is_video(source) => Some(self.dma.value()),
+            _ => None,
+        }
+    }
+
+    /// Read the component mapped at an address
+    fn read_mapped(&self, address: u16) -> u8 {
+        let address_usize = address as usize;
+        match address_usize {
+            ROM_BANK_0..VRAM => self.cartridge.as_ref().map_or(OPEN_BUS, |cartridge| cartridge.read_rom(address)),
+            VRAM..CARTRIDGE_RAM => self.ppu.read_vram(address - VRAM as u16),
+            CARTRIDGE_RAM..WORK_RAM => self.cartridge.as_ref().map_or(OPEN_BUS, |cartridge| {
+                cartridge.read_ram(address - CARTRIDGE_RAM as u16)
+            }),
+            WORK_RAM..ECHO_RAM => self.work_ram[self.work_ram_index(address_usize - WORK_RAM)],
+            ECHO_RAM..OAM => self.work_ram[self.work_ram_index(address_usize - ECHO_RAM)],
+            OAM..UNUSABLE => self.ppu.read_oam(address - OAM as u16),
+            UNUSABLE..IO_REGISTERS => OPEN_BUS,
+            IO_REGISTERS..HIGH_RAM => self.read_io(address),
+            HIGH_RAM..0xFFFF => self.high_ram[address_usize - HIGH_RAM],
+            _ => self.interrupt_enable,
+        }
+    }
+
+    /// Read an IO register
+    fn read_io(&self, address: u16) -> u8 {
+        let value = self.io_registers[address as usize - IO_REGISTERS];
+        match address {
+            JOYP => self.joypad.read_register(),
+            SB | SC => self.serial.read_register(address),
+            // Only the lower 5 bits of IF are wired
+            INTERRUPT_FLAG => value | 0xE0,
+            DIV..=TAC => self.timer.read_register(address),
+            NR10..=WAVE_RAM_END => self.apu.read_register(address),
+            DMA => self.dma.read_register(),
+            LCDC..=WX => self.ppu.read_register(address),
+            // The CGB registers aren't there on DMG
+            KEY1 | VBK | HDMA1..=HDMA5 | BCPS..=OCPD | SVBK if self.model == Model::Dmg => OPEN_BUS,
+            KEY1 => {
+                let speed = if self.double_speed { DOUBLE_SPEED } else { 0 };
+                0x7E | speed | self.speed_switch_prepared as u8
+            }
+            VBK | BCPS..=OCPD => self.ppu.read_register(address),
+            HDMA1..=HDMA5 => self.hdma.read_register(address),
+            SVBK => 0xF8 | self.work_ram_bank as u8,
+            _ => value,
+        }
+    }
+
+    /// Write an IO register
+    fn write_io(&mut self, address: u16, value: u8) {
+        match address {
+            JOYP => {
+                let interrupts = self.joypad.write_register(value);
+                self.io_registers[INTERRUPT_FLAG as usize - IO_REGISTERS] |= interrupts;
+            }
+            SB | SC => self.serial.write_register(address, value),
+            DIV..=TAC => self.timer.write_register(address, value),
+            NR10..=WAVE_RAM_END => self.apu.write_register(address, value),
+            DMA => self.dma.write_register(value),
+            LCDC..=WX => self.ppu.write_register(address, value),
+            KEY1 | VBK | HDMA1..=HDMA5 | BCPS..=OCPD | SVBK if self.model == Model::Dmg => {}
+            KEY1 => self.speed_switch_prepared = value & PREPARE_SPEED_SWITCH != 0,
+            VBK | BCPS..=OCPD => self.ppu.write_register(address, value),
+            HDMA1..=HDMA5 => {
+                for _ in 0..self.hdma.write_register(address, value) {
+                    self.copy_vram_dma_block();
+                }
+                // With the LCD off an HBlank transfer copies its first block right away
+                if address == HDMA5 && self.hdma.is_hblank_active() && !self.ppu.lcd_enabled() {
+                    self.copy_vram_dma_block();
+                }
+            }
+            // Bank 0 selects bank 1
+            SVBK => self.work_ram_bank = ((value & 0x07) as usize).max(1),
+            _ => self.io_registers[address as usize - IO_REGISTERS] = value,
+        }
+    }
+}
+
+impl MemoryBus for Memory {
+    fn read8(&self, address: u16) -> u8 {
+        self.dma_conflict(address).unwrap_or_else(|| self.read_mapped(address))
+    }
+
+    fn write8(&mut self, address: u16, value: u8) {
+        if self.dma_conflict(address).is_some() {
+            return;
+        }
+        let address_usize = address as usize;
+        match address_usize {
+            ROM_BANK_0..VRAM => {
+                if let Some(cartridge) = self.cartridge.as_mut() {
+                    cartridge.write_rom(address, value);
+                }
+            }
+            VRAM..CARTRIDGE_RAM => self.ppu.write_vram(address - VRAM as u16, value),
+            CARTRIDGE_RAM..WORK_RAM => {
+                if let Some(cartridge) = self.cartridge.as_mut() {
+                    cartridge.write_ram(address - CARTRIDGE_RAM as u16, value);
+                }
+            }
+            WORK_RAM..ECHO_RAM => self.work_ram[self.work_ram_index(address_usize - WORK_RAM)] = value,
+            ECHO_RAM..OAM => self.work_ram[self.work_ram_index(address_usize - ECHO_RAM)] = value,
+            OAM..UNUSABLE => self.ppu.write_oam(address - OAM as u16, value),
+            UNUSABLE..IO_REGISTERS => {}
+            IO_REGISTERS..HIGH_RAM => self.write_io(address, value),
+            HIGH_RAM..0xFFFF => self.high_ram[address_usize - HIGH_RAM] = value,
+            _ => self.interrupt_enable = value,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test_rom;
+    use crate::hdma::{HDMA2, HDMA3, HDMA4};
+
+    #[test]
+    fn test_echo_ram() {
+        let mut memory = Memory::new();
+        memory.write8(0xC123, 0x42);
+        assert_eq!(memory.read8(0xE123), 0x42);
+        memory.write8(0xFDFF, 0x24);
+        assert_eq!(memory.read8(0xDDFF), 0x24);
+    }
+
+    #[test]
+    fn test_read_only_and_open_bus() {
+        let mut memory = Memory::new();
+        assert_eq!(memory.read8(0x0100), OPEN_BUS);
+        memory.load_cartridge(Cartridge::from_bytes(test_rom(0x00, 0x00, 0x00)).unwrap());
+        memory.write8(0x4000, 0x00);
+        assert_eq!(memory.read8(0x4000), 0x01);
+        assert_eq!(memory.read8(0xA000), OPEN_BUS);
+        memory.write8(0xFEA0, 0x00);
+        assert_eq!(memory.read8(0xFEA0), OPEN_BUS);
+        assert_eq!(memory.read8(INTERRUPT_FLAG), 0xE0);
+    }
+
+    #[test]
+    fn test_oam_dma() {
+        let mut memory = Memory::new();
+        memory.write8(LCDC, 0);
+        for offset in 0..0xA0 {
+            memory.write8(0xC100 + offset, offset as u8);
+        }
+        memory.write8(0x8000, 0x42);
+        memory.write8(DMA, 0xC1);
+        memory.step(2 * 4);
+        // Work RAM is on the bus of the DMA, it reads the last byte copied, VRAM and HRAM are free
+        assert_eq!(memory.read8(0xC000), 0xFF);
+        memory.step(4);
+        assert_eq!(memory.read8(0xD000), 0x00);
+        memory.step(4);
+        assert_eq!(memory.read8(0xD000), 0x01);
+        assert_eq!(memory.read8(0xFE00), OPEN_BUS);
+        assert_eq!(memory.read8(0x8000), 0x42);
+        memory.write8(0xFF80, 0x24);
+        assert_eq!(memory.read8(0xFF80), 0x24);
+        memory.step(158 * 4);
+        assert!(!memory.dma().is_active());
+        assert_eq!(memory.read8(0xFE00), 0x00);
+        assert_eq!(memory.read8(0xFE9F), 0x9F);
+        assert_eq!(memory.read8(DMA), 0xC1);
+    }
+
+    #[test]
+    fn test_cgb_banks() {
+        let mut memory = Memory::with_model(Model::Cgb);
+        assert_eq!(memory.read8(SVBK), 0xF9);
+        memory.write8(0xD000, 0x01);
+        memory.write8(SVBK, 0x07);
+        assert_eq!(memory.read8(SVBK), 0xFF);
+        assert_eq!(memory.read8(0xD000), 0x00);
+        memory.write8(0xD000, 0x07);
+        assert_eq!(memory.read8(0xF000), 0x07);
+        // Bank 0 selects bank 1, 0xC000 is always bank 0
+        memory.write8(SVBK, 0x00);
+        assert_eq!(memory.read8(0xD000), 0x01);
+        memory.write8(0xC000, 0x42);
+        memory.write8(SVBK, 0x03);
+        assert_eq!(memory.read8(0xC000), 0x42);
+        // VRAM bank 1
+        memory.write8(LCDC, 0x00);
+        memory.write8(0x8000, 0x10);
+        memory.write8(VBK, 0x01);
+        assert_eq!(memory.read8(VBK), 0xFF);
+        assert_eq!(memory.read8(0x8000), 0x00);
+        memory.write8(0x8000, 0x11);
+        memory.write8(VBK, 0x00);
+        assert_eq!(memory.read8(VBK), 0xFE);
+        assert_eq!(memory.read8(0x8000), 0x10);
+        // The DMG doesn't have them
+        let mut memory = Memory::new();
+        memory.write8(SVBK, 0x02);
+        memory.write8(0xD000, 0x01);
+        assert_eq!(memory.read8(SVBK), OPEN_BUS);
+        assert_eq!(memory.read8(KEY1), OPEN_BUS);
+        assert_eq!(memory.read8(0xD000), 0x01);
+    }
+
+    #[test]
+    fn test_double_speed() {
+        let mut memory = Memory::with_model(Model::Cgb);
+        assert_eq!(memory.read8(KEY1), 0x7E);
+        assert!(!memory.switch_speed());
+        memory.write8(KEY1, 0x01);
+        assert_eq!(memory.read8(KEY1), 0x7F);
+        assert!(memory.switch_speed());
+        assert_eq!(memory.read8(KEY1), 0xFE);
+        // The timer runs at the speed of the CPU and the PPU doesn't
+        memory.write8(DIV, 0);
+        memory.step(456);
+        assert_eq!(memory.read8(DIV), 1);
+        assert_eq!(memory.read8(crate::ppu::LY), 0);
+        memory.step(456);
+        assert_eq!(memory.read8(crate::ppu::LY), 1);
+    }
+
+    #[test]
+    fn test_general_vram_dma() {
+        let mut memory = Memory::with_model(Model::Cgb);
+        memory.write8(LCDC, 0);
+        for offset in 0..0x20 {
+            memory.write8(0xD100 + offset, offset as u8 + 1);
+        }
+        memory.write8(VBK, 1);
+        for (address, value) in [(HDMA1, 0xD1), (HDMA2, 0x00), (HDMA3, 0x88), (HDMA4, 0x10), (HDMA5, 0x01)] {
+            memory.write8(address, value);
+        }
+        assert_eq!(memory.read8(HDMA5), 0xFF);
+        assert_eq!(memory.read8(0x8810), 0x01);
+        assert_eq!(memory.read8(0x882F), 0x20);
+        assert_eq!(memory.read8(0x8830), 0x00);
+        memory.write8(VBK, 0);
+        assert_eq!(memory.read8(0x8810), 0x00);
+        assert_eq!(memory.take_stall_cycles(), 2 * BLOCK_CYCLES);
+        assert_eq!(memory.take_stall_cycles(), 0);
+    }
+
+    #[test]
+    fn test_hblank_vram_dma() {
+        let mut memory = Memory::with_model(Model::Cgb);
+        for offset in 0..0x30 {
+            memory.write8(0xC000 + offset, 0x42);
+        }
+        for (address, value) in [(HDMA1, 0xC0), (HDMA2, 0x00), (HDMA3, 0x00), (HDMA4, 0x00), (HDMA5, 0x82)] {
+            memory.write8(address, value);
+        }
+        assert_eq!(memory.read8(HDMA5), 0x02);
+        // One block at the start of each HBlank, mode 3 of the first line lasts until dot 252
+        memory.step(252);
+        assert_eq!(memory.read8(HDMA5), 0x01);
+        assert_eq!(memory.read8(0x800F), 0x42);
+        assert_eq!(memory.read8(0x8010), 0x00);
+        assert_eq!(memory.take_stall_cycles(), BLOCK_CYCLES);
+        memory.step(456);
+        assert_eq!(memory.read8(HDMA5), 0x00);
+        assert_eq!(memory.read8(0x801F), 0x42);
+        // Stopping it keeps the block left
+        memory.write8(HDMA5, 0x00);
+        assert_eq!(memory.read8(HDMA5), 0x80);
+        memory.step(456);
+        assert_eq!(memory.read8(0x8020), 0x00);
+    }
+
+    #[test]
+    fn test_word_access() {
+        let mut memory = Memory::new();
+        memory.write16(0xFF80, 0xBEEF);
+        assert_eq!(memory.read8(0xFF80), 0xEF);
+        assert_eq!(memory.read16(0xFF80), 0xBEEF);
+        memory.write8(0xFFFF, 0x1F);
+        assert_eq!(memory.read8(INTERRUPT_ENABLE), 0x1F);
+    }
+}
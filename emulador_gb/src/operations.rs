@@ -125,7 +125,7 @@ pub fn dec(value: u8) -> Result{
 pub fn add_sp(value:u16, offset:u8) -> Result16{
     let (result, carry) = value.overflowing_add(offset as u16);
     Result16 {
-        value: result as u16,
+        value: result,
         zero: Some(false),
         add_sub: Some(false),
         half_carry: Some((value & 0x0F) + (offset as u16 & 0x0F) > 0x0F),
@@ -196,7 +196,7 @@ pub fn sla(value:u8) -> Result {
 }
 
 pub fn sra(value:u8) -> Result {
-    let carry = value & 0x80 != 0;
+    let carry = value & 0x01 != 0;
     let result = (value >> 1) | (value & 0x80);
     Result {
         value: result,
@@ -207,6 +207,62 @@ pub fn sra(value:u8) -> Result {
     }
 }
 
+pub fn swap(value: u8) -> Result {
+    let result = value.rotate_left(4);
+    Result {
+        value: result,
+        zero: Some(result == 0),
+        add_sub: Some(false),
+        half_carry: Some(false),
+        carry: Some(false),
+    }
+}
+
+pub fn srl(value: u8) -> Result {
+    let carry = value & 0x01 != 0;
+    let result = value >> 1;
+    Result {
+        value: result,
+        zero: Some(result == 0),
+        add_sub: Some(false),
+        half_carry: Some(false),
+        carry: Some(carry),
+    }
+}
+
+/// Test a bit, the value is returned unchanged and carry is not affected
+pub fn bit(value: u8, index: u8) -> Result {
+    Result {
+        value,
+        zero: Some(value & (1 << index) == 0),
+        add_sub: Some(false),
+        half_carry: Some(true),
+        carry: None,
+    }
+}
+
+/// Reset a bit, no flags are affected
+pub fn res(value: u8, index: u8) -> Result {
+    Result {
+        value: value & !(1 << index),
+        zero: None,
+        add_sub: None,
+        half_carry: None,
+        carry: None,
+    }
+}
+
+/// Set a bit, no flags are affected
+pub fn set(value: u8, index: u8) -> Result {
+    Result {
+        value: value | (1 << index),
+        zero: None,
+        add_sub: None,
+        half_carry: None,
+        carry: None,
+    }
+}
+
 #[cfg(test)]
     mod tests {
         use super::*;
@@ -290,4 +346,49 @@ pub fn sra(value:u8) -> Result {
             assert_eq!(result.half_carry, Some(false));
             assert_eq!(result.carry, Some(false));
         }
-    }
+
+        #[test]
+        fn test_sra() {
+            let result = sra(0x81);
+            assert_eq!(result.value, 0xC0);
+            assert_eq!(result.zero, Some(false));
+            assert_eq!(result.carry, Some(true));
+        }
+
+        #[test]
+        fn test_swap() {
+            let result = swap(0xF1);
+            assert_eq!(result.value, 0x1F);
+            assert_eq!(result.zero, Some(false));
+            assert_eq!(result.add_sub, Some(false));
+            assert_eq!(result.half_carry, Some(false));
+            assert_eq!(result.carry, Some(false));
+        }
+
+        #[test]
+        fn test_srl() {
+            let result = srl(0x01);
+            assert_eq!(result.value, 0);
+            assert_eq!(result.zero, Some(true));
+            assert_eq!(result.add_sub, Some(false));
+            assert_eq!(result.half_carry, Some(false));
+            assert_eq!(result.carry, Some(true));
+        }
+
+        #[test]
+        fn test_bit() {
+            let result = bit(0x80, 7);
+            assert_eq!(result.value, 0x80);
+            assert_eq!(result.zero, Some(false));
+            assert_eq!(result.half_carry, Some(true));
+            assert_eq!(result.carry, None);
+            assert_eq!(bit(0x80, 6).zero, Some(true));
+        }
+
+        #[test]
+        fn test_res_set() {
+            assert_eq!(res(0xFF, 3).value, 0xF7);
+            assert_eq!(set(0x00, 3).value, 0x08);
+            assert_eq!(set(0x00, 3).zero, None);
+        }
+    }
\ No newline at end of file
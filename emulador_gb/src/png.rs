@@ -0,0 +1,126 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+const GRAYSCALE: u8 = 0; // Color type with a single channel
+const STORED_BLOCK_SIZE: usize = 0xFFFF; // Largest block deflate can store without compression
+const ADLER_MODULO: u32 = 65521;
+
+/// Gray levels of the 4 shades, from white to black
+pub const SHADE_LEVELS: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+
+/// Get the CRC-32 of a PNG chunk
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { crc >> 1 ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// Get the Adler-32 checksum at the end of a zlib stream
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % ADLER_MODULO;
+        b = (b + a) % ADLER_MODULO;
+    }
+    b << 16 | a
+}
+
+/// Wrap data in a zlib stream made of stored deflate blocks, the images are small enough to
+/// not need compression
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01];
+    let mut blocks = data.chunks(STORED_BLOCK_SIZE).peekable();
+    if blocks.peek().is_none() {
+        stream.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let length = block.len() as u16;
+        stream.push(last as u8);
+        stream.extend_from_slice(&length.to_le_bytes());
+        stream.extend_from_slice(&(!length).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+    stream
+}
+
+fn push_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Encode an 8 bit grayscale image, the pixels are given row by row
+pub fn encode_grayscale(width: usize, height: usize, pixels: &[u8]) -> Vec<u8> {
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // Bit depth, color type, compression, filter and interlace methods
+    header.extend_from_slice(&[8, GRAYSCALE, 0, 0, 0]);
+    // Every row starts with the filter type, 0 leaves it unfiltered
+    let mut rows = Vec::with_capacity((width + 1) * height);
+    for row in pixels.chunks(width.max(1)).take(height) {
+        rows.push(0);
+        rows.extend_from_slice(row);
+    }
+    let mut png = SIGNATURE.to_vec();
+    push_chunk(&mut png, b"IHDR", &header);
+    push_chunk(&mut png, b"IDAT", &zlib_stored(&rows));
+    push_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+/// Encode shades from 0 (white) to 3 (black), like the frames of the PPU
+pub fn encode_shades(width: usize, height: usize, shades: &[u8]) -> Vec<u8> {
+    let pixels: Vec<u8> = shades.iter().map(|shade| SHADE_LEVELS[*shade as usize & 0x03]).collect();
+    encode_grayscale(width, height, &pixels)
+}
+
+/// Write shades from 0 (white) to 3 (black) to a PNG file
+pub fn write_shades<P: AsRef<Path>>(path: P, width: usize, height: usize, shades: &[u8]) -> io::Result<()> {
+    fs::write(path, encode_shades(width, height, shades))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn test_encode() {
+        let png = encode_shades(2, 2, &[0, 1, 2, 3]);
+        assert_eq!(png[..8], SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..24], &[0, 0, 0, 2, 0, 0, 0, 2]);
+        // The image data is stored as is after the zlib and block headers
+        assert_eq!(&png[37..41], b"IDAT");
+        assert_eq!(&png[41..48], &[0x78, 0x01, 0x01, 6, 0, 0xF9, 0xFF]);
+        assert_eq!(&png[48..54], &[0, 0xFF, 0xAA, 0, 0x55, 0x00]);
+        assert_eq!(&png[png.len() - 12..], &[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]);
+    }
+
+    #[test]
+    fn test_large_image() {
+        let stream = zlib_stored(&vec![0x55; STORED_BLOCK_SIZE + 10]);
+        assert_eq!(stream[2], 0x00);
+        assert_eq!(stream[2 + 5 + STORED_BLOCK_SIZE], 0x01);
+        assert_eq!(stream.len(), 2 + 5 * 2 + STORED_BLOCK_SIZE + 10 + 4);
+    }
+}
@@ -0,0 +1,296 @@
+use super::{pixel_color, Ppu, BG_ENABLE, SCREEN_WIDTH, SPRITE_ENABLE};
+use crate::memory::Model;
+
+const SPRITE_FETCH_CYCLES: u8 = 6; // Dots the fetcher spends reading a sprite tile
+const SPRITE_MAX_WAIT: u8 = 5; // Dots the first sprite of a background tile can wait for the fetcher
+
+/// Step of the background fetcher, each one takes 2 dots except Push that retries every dot
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum FetcherStep {
+    Tile,
+    DataLow,
+    DataHigh,
+    Push,
+}
+
+/// Background fetcher and pixel FIFO of the line being drawn
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(super) struct Fifo {
+    /// Color numbers waiting to be shifted out, the background FIFO never holds more than a tile
+    pixels: [u8; 8],
+    len: u8,
+    /// CGB attributes of the tile in the FIFO
+    attributes: u8,
+    step: FetcherStep,
+    /// Dots spent in the current step
+    step_dots: u8,
+    /// Tile column fetched next, relative to the start of the line or of the window
+    column: u8,
+    tile_low: u8,
+    tile_high: u8,
+    tile_attributes: u8,
+    /// The first fetch of a line is thrown away
+    first_fetch: bool,
+    /// Pixels dropped at the start of the line to apply the fine scroll
+    discard: u8,
+    /// Next pixel of the screen
+    x: u8,
+    fetching_window: bool,
+    window_drawn: bool,
+    /// Bit per entry of the line sprites that was already fetched
+    sprites_fetched: u16,
+    /// Background tile of the last sprite fetch, later sprites on it don't wait for the fetcher
+    sprite_tile: Option<u8>,
+    /// Dots the fetcher and the shifter are paused by a sprite fetch
+    stall: u8,
+}
+
+impl Fifo {
+    pub(super) fn new() -> Self {
+        Fifo {
+            pixels: [0; 8],
+            len: 0,
+            attributes: 0,
+            step: FetcherStep::Tile,
+            step_dots: 0,
+            column: 0,
+            tile_low: 0,
+            tile_high: 0,
+            tile_attributes: 0,
+            first_fetch: true,
+            discard: 0,
+            x: 0,
+            fetching_window: false,
+            window_drawn: false,
+            sprites_fetched: 0,
+            sprite_tile: None,
+            stall: 0,
+        }
+    }
+
+    /// Restart the fetcher, dropping the pixels in the FIFO
+    fn restart(&mut self) {
+        self.len = 0;
+        self.step = FetcherStep::Tile;
+        self.step_dots = 0;
+        self.column = 0;
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let color = self.pixels[8 - self.len as usize];
+        self.len -= 1;
+        Some(color)
+    }
+}
+
+impl Ppu {
+    /// Advance mode 3 by one dot, returns true once the 160 pixels of the line are out
+    pub(super) fn step_fifo(&mut self) -> bool {
+        if self.dot == super::OAM_SCAN_CYCLES + 1 {
+            // The fine scroll is read when the line starts
+            self.fifo.discard = self.scx % 8;
+        }
+        if self.fifo.stall > 0 {
+            self.fifo.stall -= 1;
+            return false;
+        }
+        if self.start_sprite_fetch() {
+            return false;
+        }
+        if !self.fifo.fetching_window && self.fifo.discard == 0 && self.window_covers(self.fifo.x) {
+            // The window restarts the fetcher with an empty FIFO
+            self.fifo.restart();
+            self.fifo.fetching_window = true;
+            self.fifo.window_drawn = true;
+        }
+        if let Some(color) = self.fifo.pop() {
+            if self.fifo.discard > 0 {
+                self.fifo.discard -= 1;
+            } else {
+                self.output_pixel(color);
+            }
+        }
+        self.tick_fetcher();
+        if self.fifo.x as usize == SCREEN_WIDTH {
+            if self.fifo.window_drawn {
+                self.window_line += 1;
+            }
+            return true;
+        }
+        false
+    }
+
+    /// Pause the shifter and the fetcher when a sprite starts at the next pixel. The first sprite
+    /// on a background tile also waits for the fetcher to finish the tile, which takes longer the
+    /// further left the sprite is
+    fn start_sprite_fetch(&mut self) -> bool {
+        if self.lcdc & SPRITE_ENABLE == 0 || self.fifo.discard > 0 {
+            return false;
+        }
+        let x = self.fifo.x as u16 + 8;
+        let Some(index) = self
+            .sprites
+            .iter()
+            .enumerate()
+            .position(|(index, sprite)| self.fifo.sprites_fetched & 1 << index == 0 && sprite.x as u16 <= x)
+        else {
+            return false;
+        };
+        self.fifo.sprites_fetched |= 1 << index;
+        // Sprites hidden past the left edge count as being at the start of a tile
+        let position = self.sprites[index].x.saturating_sub(8).wrapping_add(self.scx);
+        let position = if self.sprites[index].x < 8 { 0 } else { position };
+        let tile = position / 8;
+        let wait = if self.fifo.sprite_tile == Some(tile) {
+            0
+        } else {
+            SPRITE_MAX_WAIT.saturating_sub(position % 8)
+        };
+        self.fifo.sprite_tile = Some(tile);
+        // This dot is the first one of the fetch
+        self.fifo.stall = SPRITE_FETCH_CYCLES + wait - 1;
+        true
+    }
+
+    /// Write a pixel of the line with the registers in use at this dot
+    fn output_pixel(&mut self, color: u8) {
+        let visible = self.lcdc & BG_ENABLE != 0 || self.model == Model::Cgb;
+        let bg = visible.then_some((color, self.fifo.attributes));
+        let pixel = self.mix_pixel(self.fifo.x, bg);
+        self.put_pixel(self.fifo.x, pixel);
+        self.fifo.x += 1;
+    }
+
+    /// Advance the background fetcher by one dot
+    fn tick_fetcher(&mut self) {
+        if self.fifo.step == FetcherStep::Push {
+            if self.fifo.len > 0 {
+                return;
+            }
+            if self.fifo.first_fetch {
+                self.fifo.first_fetch = false;
+            } else {
+                for (pixel, color) in self.fifo.pixels.iter_mut().enumerate() {
+                    *color = pixel_color(self.fifo.tile_low, self.fifo.tile_high, pixel as u8);
+                }
+                self.fifo.len = 8;
+                self.fifo.attributes = self.fifo.tile_attributes;
+                self.fifo.column += 1;
+            }
+            self.fifo.step = FetcherStep::Tile;
+            return;
+        }
+        self.fifo.step_dots += 1;
+        if self.fifo.step_dots < 2 {
+            return;
+        }
+        self.fifo.step_dots = 0;
+        self.fifo.step = match self.fifo.step {
+            FetcherStep::Tile => FetcherStep::DataLow,
+            FetcherStep::DataLow => FetcherStep::DataHigh,
+            _ => {
+                // The map, the scroll and the tile data are read when the row is fetched
+                let (low, high, attributes) = if self.fifo.fetching_window {
+                    self.tile_row(self.window_map(), self.fifo.column, self.window_line)
+                } else {
+                    let column = self.scx / 8 + self.fifo.column;
+                    self.tile_row(self.bg_map(), column, self.ly.wrapping_add(self.scy))
+                };
+                self.fifo.tile_low = low;
+                self.fifo.tile_high = high;
+                self.fifo.tile_attributes = attributes;
+                FetcherStep::Push
+            }
+        };
+        if self.fifo.step == FetcherStep::Push && self.fifo.len == 0 {
+            self.tick_fetcher();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+
+    /// Run the OAM scan and return the dots mode 3 lasts on the first line
+    fn mode3_length(ppu: &mut Ppu) -> u32 {
+        ppu.set_renderer(Renderer::Fifo);
+        ppu.step(OAM_SCAN_CYCLES);
+        let mut dots = 0;
+        while ppu.mode() == Mode::PixelTransfer {
+            ppu.step(1);
+            dots += 1;
+        }
+        dots
+    }
+
+    #[test]
+    fn test_mode3_length() {
+        assert_eq!(mode3_length(&mut Ppu::new()), 172);
+        let mut ppu = Ppu::new();
+        ppu.write_register(SCX, 3);
+        assert_eq!(mode3_length(&mut ppu), 175);
+        let mut ppu = Ppu::new();
+        ppu.write_register(LCDC, LCD_ENABLE | WINDOW_ENABLE | BG_ENABLE);
+        ppu.write_register(WX, 87);
+        assert_eq!(mode3_length(&mut ppu), 178);
+    }
+
+    #[test]
+    fn test_sprite_penalty() {
+        let mut ppu = Ppu::new();
+        ppu.write_register(LCDC, 0);
+        // A sprite at the start of a tile waits for the whole fetch, one at its right half
+        // doesn't, and a second one on the same tile doesn't either
+        ppu.write_oam(0, 16);
+        ppu.write_oam(1, 8);
+        ppu.write_oam(4, 16);
+        ppu.write_oam(5, 8 + 20);
+        ppu.write_oam(8, 16);
+        ppu.write_oam(9, 8 + 22);
+        ppu.write_register(LCDC, LCD_ENABLE | SPRITE_ENABLE | BG_ENABLE);
+        assert_eq!(mode3_length(&mut ppu), 172 + 11 + 7 + 6);
+        assert_eq!(ppu.line_sprites().len(), 3);
+    }
+
+    #[test]
+    fn test_matches_scanline() {
+        let mut ppu = Ppu::new();
+        ppu.write_register(LCDC, 0);
+        for address in 0..0x1800 {
+            ppu.write_vram(address, (address * 7 + address / 16) as u8);
+        }
+        for address in 0x1800..0x2000 {
+            ppu.write_vram(address, (address % 5) as u8);
+        }
+        ppu.write_register(SCX, 13);
+        ppu.write_register(SCY, 7);
+        ppu.write_register(WY, 40);
+        ppu.write_register(WX, 50);
+        ppu.write_register(LCDC, LCD_ENABLE | WINDOW_ENABLE | WINDOW_MAP | TILE_DATA | BG_ENABLE);
+        ppu.step(SCANLINE_CYCLES * LINES_PER_FRAME as u32);
+        let scanline = ppu.frame().to_vec();
+        ppu.set_renderer(Renderer::Fifo);
+        ppu.step(SCANLINE_CYCLES * LINES_PER_FRAME as u32);
+        assert_eq!(ppu.frame(), &scanline[..]);
+    }
+
+    #[test]
+    fn test_mid_line_palette() {
+        let mut ppu = Ppu::new();
+        ppu.write_register(LCDC, 0);
+        ppu.write_vram(0x0000, 0xFF);
+        ppu.write_register(LCDC, LCD_ENABLE | TILE_DATA | BG_ENABLE);
+        ppu.write_register(BGP, 0x00);
+        ppu.set_renderer(Renderer::Fifo);
+        // Pixel 0 is shifted out on the 13th dot of mode 3, change the palette after 20 pixels
+        ppu.step(OAM_SCAN_CYCLES + 12 + 20);
+        ppu.write_register(BGP, 0xFC);
+        ppu.step(SCANLINE_CYCLES * LINES_PER_FRAME as u32);
+        assert_eq!(ppu.frame()[19], 0);
+        assert_eq!(ppu.frame()[20], 3);
+    }
+}
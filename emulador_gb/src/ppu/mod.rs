@@ -0,0 +1,912 @@
+use crate::interrupts::Interrupt;
+use crate::memory::{Model, OPEN_BUS};
+
+mod fifo;
+mod palette;
+
+use fifo::Fifo;
+use palette::PaletteRam;
+
+pub use palette::correct_color;
+
+pub const LCDC: u16 = 0xFF40; // LCD control
+pub const STAT: u16 = 0xFF41; // LCD status, bits 3-6 select the STAT interrupt sources
+pub const SCY: u16 = 0xFF42; // Background scroll
+pub const SCX: u16 = 0xFF43;
+pub const LY: u16 = 0xFF44; // Line being drawn, read only
+pub const LYC: u16 = 0xFF45; // Line compared with LY
+pub const BGP: u16 = 0xFF47; // Background palette
+pub const OBP0: u16 = 0xFF48; // Sprite palettes
+pub const OBP1: u16 = 0xFF49;
+pub const WY: u16 = 0xFF4A; // Window position, WX is offset by 7
+pub const WX: u16 = 0xFF4B;
+pub const VBK: u16 = 0xFF4F; // CGB VRAM bank mapped for the CPU
+pub const BCPS: u16 = 0xFF68; // CGB background palette index, bit 7 increments it after writes to BCPD
+pub const BCPD: u16 = 0xFF69; // CGB background palette data
+pub const OCPS: u16 = 0xFF6A; // CGB sprite palette index and data
+pub const OCPD: u16 = 0xFF6B;
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+pub const VRAM_SIZE: usize = 0x2000;
+pub const VRAM_BANKS: usize = 2; // The DMG only has bank 0
+pub const OAM_SIZE: usize = 0xA0;
+
+const OAM_SCAN_CYCLES: u32 = 80; // Dots of each mode, mode 3 doesn't stall in the scanline renderer
+const PIXEL_TRANSFER_CYCLES: u32 = 172;
+const SCANLINE_CYCLES: u32 = 456;
+const LINES_PER_FRAME: u8 = 154;
+const SPRITES_PER_LINE: usize = 10;
+
+const LCD_ENABLE: u8 = 1 << 7; // LCDC bits
+const WINDOW_MAP: u8 = 1 << 6;
+const WINDOW_ENABLE: u8 = 1 << 5;
+const TILE_DATA: u8 = 1 << 4;
+const BG_MAP: u8 = 1 << 3;
+const SPRITE_SIZE: u8 = 1 << 2;
+const SPRITE_ENABLE: u8 = 1 << 1;
+const BG_ENABLE: u8 = 1 << 0; // On CGB the background stays on and sprites get over it when cleared
+
+const BEHIND_BG: u8 = 1 << 7; // Sprite attribute bits
+const Y_FLIP: u8 = 1 << 6;
+const X_FLIP: u8 = 1 << 5;
+const PALETTE: u8 = 1 << 4;
+const VRAM_BANK: u8 = 1 << 3; // CGB attribute bits, shared by sprites and background tiles
+const CGB_PALETTE: u8 = 0x07;
+const BG_PRIORITY: u8 = 1 << 7; // Background tile attribute, it covers the sprites
+
+/// RGB555 colors of the DMG shades, from white to black
+const DMG_COLORS: [u16; 4] = [0x7FFF, 0x56B5, 0x294A, 0x0000];
+
+const LYC_INTERRUPT: u8 = 1 << 6; // STAT bits
+const OAM_INTERRUPT: u8 = 1 << 5;
+const VBLANK_INTERRUPT: u8 = 1 << 4;
+const HBLANK_INTERRUPT: u8 = 1 << 3;
+const COINCIDENCE: u8 = 1 << 2;
+
+/// State of the PPU, the number is the value of the lower bits of STAT
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Mode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    PixelTransfer = 3,
+}
+
+/// Way the PPU turns VRAM into pixels
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Renderer {
+    /// Draws each line at once when mode 3 ends, mode 3 always lasts 172 dots
+    Scanline,
+    /// Models the background fetcher and the pixel FIFO dot by dot, mode 3 length and mid-line
+    /// register writes behave like the hardware
+    Fifo,
+}
+
+/// Entry of OAM selected for a line
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Sprite {
+    /// Position in OAM, 0-39
+    pub index: u8,
+    /// Y position plus 16
+    pub y: u8,
+    /// X position plus 8
+    pub x: u8,
+    pub tile: u8,
+    pub attributes: u8,
+}
+
+/// Picture processing unit, owns VRAM and OAM and draws the lines during mode 3
+#[derive(Clone, Debug)]
+pub struct Ppu {
+    model: Model,
+    vram: [[u8; VRAM_SIZE]; VRAM_BANKS],
+    /// VRAM bank selected by VBK
+    vram_bank: usize,
+    oam: [u8; OAM_SIZE],
+    lcdc: u8,
+    /// Interrupt selection bits of STAT, the rest is computed on read
+    stat: u8,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+    bg_palettes: PaletteRam,
+    sprite_palettes: PaletteRam,
+    mode: Mode,
+    renderer: Renderer,
+    /// Dots elapsed in the current line
+    dot: u32,
+    /// Sprites found by the OAM scan of the current line
+    sprites: Vec<Sprite>,
+    /// Sprites selected for each line, only recorded while debugging
+    sprite_log: Option<Vec<Vec<Sprite>>>,
+    /// Set once LY matched WY during the frame, the window can only be drawn after that
+    wy_triggered: bool,
+    /// Line of the window drawn next, it only advances on lines where the window is visible
+    window_line: u8,
+    fifo: Fifo,
+    /// The STAT interrupt is raised on the rising edge of the OR of its sources
+    stat_line: bool,
+    /// HBlanks started since they were last taken, they drive the CGB HBlank DMA
+    hblanks: u32,
+    /// Shades 0-3 of the frame being drawn and of the last complete frame
+    back_buffer: Vec<u8>,
+    front_buffer: Vec<u8>,
+    /// RGB555 colors of the frame being drawn and of the last complete frame
+    color_back_buffer: Vec<u16>,
+    color_front_buffer: Vec<u16>,
+    /// Colors go through the curve of the CGB LCD
+    color_correction: bool,
+    frame_count: u64,
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ppu {
+    /// Create a DMG PPU with the registers left by the boot ROM
+    pub fn new() -> Self {
+        Self::with_model(Model::Dmg)
+    }
+
+    /// Create the PPU of a model with the registers left by the boot ROM
+    pub fn with_model(model: Model) -> Self {
+        Ppu {
+            model,
+            vram: [[0; VRAM_SIZE]; VRAM_BANKS],
+            vram_bank: 0,
+            oam: [0; OAM_SIZE],
+            lcdc: 0x91,
+            stat: 0,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0xFC,
+            obp0: 0xFF,
+            obp1: 0xFF,
+            wy: 0,
+            wx: 0,
+            bg_palettes: PaletteRam::new(),
+            sprite_palettes: PaletteRam::new(),
+            mode: Mode::OamScan,
+            renderer: Renderer::Scanline,
+            dot: 0,
+            sprites: Vec::with_capacity(SPRITES_PER_LINE),
+            sprite_log: None,
+            wy_triggered: false,
+            window_line: 0,
+            fifo: Fifo::new(),
+            stat_line: false,
+            hblanks: 0,
+            back_buffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            front_buffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            color_back_buffer: vec![DMG_COLORS[0]; SCREEN_WIDTH * SCREEN_HEIGHT],
+            color_front_buffer: vec![DMG_COLORS[0]; SCREEN_WIDTH * SCREEN_HEIGHT],
+            color_correction: false,
+            frame_count: 0,
+        }
+    }
+
+    /// Get the last complete frame, 160x144 shades from 0 (white) to 3 (black) row by row. On
+    /// CGB they are the color numbers before the palettes
+    pub fn frame(&self) -> &[u8] {
+        &self.front_buffer
+    }
+
+    /// Get the last complete frame, 160x144 RGB555 colors row by row, red in the lowest bits
+    pub fn frame_rgb555(&self) -> &[u16] {
+        &self.color_front_buffer
+    }
+
+    /// Pass the colors through the curve of the CGB LCD, the change applies from the next pixel
+    pub fn set_color_correction(&mut self, enabled: bool) {
+        self.color_correction = enabled;
+    }
+
+    /// Check if the colors go through the curve of the CGB LCD
+    pub fn color_correction(&self) -> bool {
+        self.color_correction
+    }
+
+    /// Get the number of frames completed, it increases when VBlank starts
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// Get the current mode
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Get the renderer in use
+    pub fn renderer(&self) -> Renderer {
+        self.renderer
+    }
+
+    /// Choose the renderer, the change applies from the next line
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = renderer;
+    }
+
+    /// Get the sprites selected for the current line
+    pub fn line_sprites(&self) -> &[Sprite] {
+        &self.sprites
+    }
+
+    /// Record the sprites selected for every line, to inspect them with sprite_log
+    pub fn set_sprite_log(&mut self, enabled: bool) {
+        self.sprite_log = enabled.then(|| vec![Vec::new(); SCREEN_HEIGHT]);
+    }
+
+    /// Get the sprites selected for a line the last time it was drawn, if the log is enabled
+    pub fn sprite_log(&self, line: u8) -> Option<&[Sprite]> {
+        self.sprite_log.as_ref()?.get(line as usize).map(Vec::as_slice)
+    }
+
+    /// Describe the sprites selected for each line that has any, one line of text per scanline
+    pub fn dump_sprite_log(&self) -> String {
+        let Some(log) = self.sprite_log.as_ref() else {
+            return String::new();
+        };
+        let mut dump = String::new();
+        for (line, sprites) in log.iter().enumerate().filter(|(_, sprites)| !sprites.is_empty()) {
+            dump += &format!("LY {:3}:", line);
+            for sprite in sprites {
+                dump += &format!(
+                    " #{:02} x={:3} y={:3} tile={:02X} attr={:02X}",
+                    sprite.index, sprite.x, sprite.y, sprite.tile, sprite.attributes
+                );
+            }
+            dump.push('\n');
+        }
+        dump
+    }
+
+    /// Take the number of HBlanks started since the last call
+    pub fn take_hblanks(&mut self) -> u32 {
+        std::mem::take(&mut self.hblanks)
+    }
+
+    /// Check if the LCD is on
+    pub fn lcd_enabled(&self) -> bool {
+        self.lcdc & LCD_ENABLE != 0
+    }
+
+    /// Advance the PPU, the cycles are 4.19MHz clock cycles.
+    /// Returns the IF bits of the interrupts requested
+    pub fn step(&mut self, cycles: u32) -> u8 {
+        if !self.lcd_enabled() {
+            return 0;
+        }
+        let mut interrupts = 0;
+        for _ in 0..cycles {
+            self.dot += 1;
+            match self.mode {
+                Mode::OamScan if self.dot == OAM_SCAN_CYCLES => {
+                    self.scan_oam();
+                    self.mode = Mode::PixelTransfer;
+                    self.fifo = Fifo::new();
+                }
+                Mode::PixelTransfer => {
+                    let done = match self.renderer {
+                        Renderer::Scanline if self.dot == OAM_SCAN_CYCLES + PIXEL_TRANSFER_CYCLES => {
+                            self.render_line();
+                            true
+                        }
+                        Renderer::Scanline => false,
+                        Renderer::Fifo => self.step_fifo(),
+                    };
+                    if done {
+                        self.mode = Mode::HBlank;
+                        self.hblanks += 1;
+                    }
+                }
+                Mode::HBlank | Mode::VBlank if self.dot == SCANLINE_CYCLES => {
+                    self.dot = 0;
+                    self.ly += 1;
+                    if self.ly == SCREEN_HEIGHT as u8 {
+                        self.mode = Mode::VBlank;
+                        self.wy_triggered = false;
+                        std::mem::swap(&mut self.front_buffer, &mut self.back_buffer);
+                        std::mem::swap(&mut self.color_front_buffer, &mut self.color_back_buffer);
+                        self.frame_count += 1;
+                        interrupts |= Interrupt::VBlank.bit();
+                    } else if self.ly == LINES_PER_FRAME {
+                        self.ly = 0;
+                        self.window_line = 0;
+                        self.mode = Mode::OamScan;
+                    } else if self.ly < SCREEN_HEIGHT as u8 {
+                        self.mode = Mode::OamScan;
+                    }
+                }
+                _ => {}
+            }
+            if self.update_stat_line() {
+                interrupts |= Interrupt::Stat.bit();
+            }
+        }
+        interrupts
+    }
+
+    /// Recompute the STAT interrupt line, returns true on a rising edge
+    fn update_stat_line(&mut self) -> bool {
+        let line = (self.stat & LYC_INTERRUPT != 0 && self.ly == self.lyc)
+            || match self.mode {
+                Mode::HBlank => self.stat & HBLANK_INTERRUPT != 0,
+                Mode::VBlank => self.stat & VBLANK_INTERRUPT != 0,
+                Mode::OamScan => self.stat & OAM_INTERRUPT != 0,
+                Mode::PixelTransfer => false,
+            };
+        let rising = line && !self.stat_line;
+        self.stat_line = line;
+        rising
+    }
+
+    /// Read a PPU register
+    pub fn read_register(&self, address: u16) -> u8 {
+        match address {
+            LCDC => self.lcdc,
+            STAT => {
+                let coincidence = if self.ly == self.lyc { COINCIDENCE } else { 0 };
+                // The mode reads as 0 while the LCD is off
+                let mode = if self.lcd_enabled() { self.mode as u8 } else { 0 };
+                0x80 | self.stat | coincidence | mode
+            }
+            SCY => self.scy,
+            SCX => self.scx,
+            LY => self.ly,
+            LYC => self.lyc,
+            BGP => self.bgp,
+            OBP0 => self.obp0,
+            OBP1 => self.obp1,
+            WY => self.wy,
+            WX => self.wx,
+            VBK => 0xFE | self.vram_bank as u8,
+            BCPS => self.bg_palettes.read_index(),
+            OCPS => self.sprite_palettes.read_index(),
+            // Palette RAM is used by the PPU during mode 3
+            BCPD | OCPD if !self.palettes_accessible() => OPEN_BUS,
+            BCPD => self.bg_palettes.read_data(),
+            OCPD => self.sprite_palettes.read_data(),
+            _ => OPEN_BUS,
+        }
+    }
+
+    /// Write a PPU register
+    pub fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            LCDC => {
+                let was_enabled = self.lcd_enabled();
+                self.lcdc = value;
+                if was_enabled && !self.lcd_enabled() {
+                    // Turning the LCD off resets the line and blanks the screen
+                    self.ly = 0;
+                    self.dot = 0;
+                    self.window_line = 0;
+                    self.wy_triggered = false;
+                    self.mode = Mode::HBlank;
+                    self.stat_line = false;
+                    self.front_buffer.fill(0);
+                    let white = self.output_color(DMG_COLORS[0]);
+                    self.color_front_buffer.fill(white);
+                } else if !was_enabled && self.lcd_enabled() {
+                    self.mode = Mode::OamScan;
+                }
+            }
+            STAT => self.stat = value & (LYC_INTERRUPT | OAM_INTERRUPT | VBLANK_INTERRUPT | HBLANK_INTERRUPT),
+            SCY => self.scy = value,
+            SCX => self.scx = value,
+            LY => {}
+            LYC => self.lyc = value,
+            BGP => self.bgp = value,
+            OBP0 => self.obp0 = value,
+            OBP1 => self.obp1 = value,
+            WY => self.wy = value,
+            WX => self.wx = value,
+            VBK => self.vram_bank = (value & 0x01) as usize,
+            BCPS => self.bg_palettes.write_index(value),
+            BCPD => self.bg_palettes.write_data(value, self.palettes_accessible()),
+            OCPS => self.sprite_palettes.write_index(value),
+            OCPD => self.sprite_palettes.write_data(value, self.palettes_accessible()),
+            _ => {}
+        }
+    }
+
+    fn palettes_accessible(&self) -> bool {
+        !self.lcd_enabled() || self.mode != Mode::PixelTransfer
+    }
+
+    /// Read the VRAM bank selected by VBK, the address is relative to 0x8000. The CPU can't
+    /// access it during mode 3
+    pub fn read_vram(&self, address: u16) -> u8 {
+        if self.lcd_enabled() && self.mode == Mode::PixelTransfer {
+            return OPEN_BUS;
+        }
+        self.vram[self.vram_bank][address as usize]
+    }
+
+    /// Write the VRAM bank selected by VBK, the address is relative to 0x8000
+    pub fn write_vram(&mut self, address: u16, value: u8) {
+        if self.lcd_enabled() && self.mode == Mode::PixelTransfer {
+            return;
+        }
+        self.vram[self.vram_bank][address as usize] = value;
+    }
+
+    /// Get the VRAM bank selected by VBK regardless of the mode, as seen by the DMA controllers
+    pub fn vram(&self) -> &[u8] {
+        &self.vram[self.vram_bank]
+    }
+
+    /// Write the VRAM bank selected by VBK regardless of the mode, used by the VRAM DMA
+    pub fn write_vram_dma(&mut self, address: u16, value: u8) {
+        self.vram[self.vram_bank][address as usize] = value;
+    }
+
+    /// Write OAM regardless of the mode, used by the OAM DMA
+    pub fn write_oam_dma(&mut self, address: u16, value: u8) {
+        self.oam[address as usize] = value;
+    }
+
+    /// Check if the CPU can access OAM, it's used by the PPU during modes 2 and 3
+    fn oam_accessible(&self) -> bool {
+        !self.lcd_enabled() || matches!(self.mode, Mode::HBlank | Mode::VBlank)
+    }
+
+    /// Read OAM, the address is relative to 0xFE00
+    pub fn read_oam(&self, address: u16) -> u8 {
+        if !self.oam_accessible() {
+            return OPEN_BUS;
+        }
+        self.oam[address as usize]
+    }
+
+    /// Write OAM, the address is relative to 0xFE00
+    pub fn write_oam(&mut self, address: u16, value: u8) {
+        if self.oam_accessible() {
+            self.oam[address as usize] = value;
+        }
+    }
+
+    /// Select the sprites that overlap the current line, up to 10 in OAM order
+    fn scan_oam(&mut self) {
+        if self.ly == self.wy {
+            self.wy_triggered = true;
+        }
+        let height = if self.lcdc & SPRITE_SIZE != 0 { 16 } else { 8 };
+        let line = self.ly as u16 + 16;
+        self.sprites.clear();
+        for (index, entry) in self.oam.chunks_exact(4).enumerate() {
+            let y = entry[0] as u16;
+            if line >= y && line < y + height {
+                self.sprites.push(Sprite {
+                    index: index as u8,
+                    y: entry[0],
+                    x: entry[1],
+                    tile: entry[2],
+                    attributes: entry[3],
+                });
+                if self.sprites.len() == SPRITES_PER_LINE {
+                    break;
+                }
+            }
+        }
+        if let Some(entry) = self.sprite_log.as_mut().and_then(|log| log.get_mut(self.ly as usize)) {
+            entry.clone_from(&self.sprites);
+        }
+    }
+
+    /// Find the sprite pixel shown at a pixel of the current line. On DMG the sprite with the
+    /// lowest X wins and ties go to the first one in OAM, on CGB the first one in OAM wins.
+    /// Transparent pixels let the next one show. Returns the color number and the attributes of
+    /// the sprite
+    fn sprite_pixel(&self, x: u8) -> Option<(u8, u8)> {
+        if self.lcdc & SPRITE_ENABLE == 0 {
+            return None;
+        }
+        let height = if self.lcdc & SPRITE_SIZE != 0 { 16 } else { 8 };
+        let mut best: Option<(&Sprite, u8)> = None;
+        for sprite in &self.sprites {
+            let column = x as i16 + 8 - sprite.x as i16;
+            if !(0..8).contains(&column) {
+                continue;
+            }
+            if best.is_some_and(|(best, _)| self.model == Model::Cgb || best.x <= sprite.x) {
+                continue;
+            }
+            let mut row = self.ly as u16 + 16 - sprite.y as u16;
+            // The size can be changed after the OAM scan
+            if row >= height {
+                continue;
+            }
+            if sprite.attributes & Y_FLIP != 0 {
+                row = height - 1 - row;
+            }
+            let column = if sprite.attributes & X_FLIP != 0 { 7 - column } else { column };
+            // In 8x16 mode the top tile is the even one
+            let tile = if height == 16 { sprite.tile & 0xFE } else { sprite.tile };
+            let address = (tile as u16 * 16 + row * 2) as usize;
+            let bank = self.tile_bank(sprite.attributes);
+            let color = pixel_color(self.vram[bank][address], self.vram[bank][address + 1], column as u8);
+            if color != 0 {
+                best = Some((sprite, color));
+            }
+        }
+        best.map(|(sprite, color)| (color, sprite.attributes))
+    }
+
+    /// Get the VRAM bank of a tile from its CGB attributes
+    fn tile_bank(&self, attributes: u8) -> usize {
+        (self.model == Model::Cgb && attributes & VRAM_BANK != 0) as usize
+    }
+
+    /// Get the pixel at x of the current line mixing the sprites over the background color number
+    /// and tile attributes, None when the DMG background is disabled. Returns the shade and the
+    /// RGB555 color, on CGB the shade is the color number
+    fn mix_pixel(&self, x: u8, bg: Option<(u8, u8)>) -> (u8, u16) {
+        let sprite = self.sprite_pixel(x);
+        if self.model == Model::Cgb {
+            let (bg_color, bg_attributes) = bg.unwrap_or((0, 0));
+            // With LCDC bit 0 clear the sprites are always on top, otherwise either priority bit
+            // puts the background over them unless it's color 0
+            let bg_on_top = |attributes: u8| {
+                let priority = attributes & BEHIND_BG != 0 || bg_attributes & BG_PRIORITY != 0;
+                self.lcdc & BG_ENABLE != 0 && bg_color != 0 && priority
+            };
+            return match sprite {
+                Some((color, attributes)) if !bg_on_top(attributes) => {
+                    let rgb = self.sprite_palettes.color(attributes & CGB_PALETTE, color);
+                    (color, self.output_color(rgb))
+                }
+                _ => {
+                    let rgb = self.bg_palettes.color(bg_attributes & CGB_PALETTE, bg_color);
+                    (bg_color, self.output_color(rgb))
+                }
+            };
+        }
+        let bg_color = bg.map(|(color, _)| color);
+        let shade = match (bg_color, sprite) {
+            // Sprites behind the background only show over its color 0
+            (Some(bg_color), Some((_, attributes))) if attributes & BEHIND_BG != 0 && bg_color != 0 => {
+                self.bgp >> (bg_color * 2) & 0x03
+            }
+            (_, Some((color, attributes))) => {
+                let palette = if attributes & PALETTE != 0 { self.obp1 } else { self.obp0 };
+                palette >> (color * 2) & 0x03
+            }
+            (Some(bg_color), None) => self.bgp >> (bg_color * 2) & 0x03,
+            (None, None) => 0,
+        };
+        (shade, self.output_color(DMG_COLORS[shade as usize]))
+    }
+
+    /// Apply the color correction if it's enabled
+    fn output_color(&self, color: u16) -> u16 {
+        if self.color_correction {
+            correct_color(color)
+        } else {
+            color
+        }
+    }
+
+    /// Write a pixel of the current line
+    fn put_pixel(&mut self, x: u8, (shade, color): (u8, u16)) {
+        let index = self.ly as usize * SCREEN_WIDTH + x as usize;
+        self.back_buffer[index] = shade;
+        self.color_back_buffer[index] = color;
+    }
+
+    /// Get the address in VRAM of a background or window tile
+    fn tile_address(&self, tile: u8) -> u16 {
+        // With the unsigned addressing tiles start at 0x8000, otherwise they are signed from 0x9000
+        if self.lcdc & TILE_DATA != 0 {
+            tile as u16 * 16
+        } else {
+            (0x1000 + (tile as i8 as i16) * 16) as u16
+        }
+    }
+
+    /// Get the two bytes of a row of the background or window tile at a map position and the
+    /// CGB attributes of the tile, with the flips applied
+    fn tile_row(&self, map: u16, column: u8, y: u8) -> (u8, u8, u8) {
+        let map_address = (map + (y as u16 / 8) * 32 + column as u16 % 32) as usize;
+        let tile = self.vram[0][map_address];
+        // The CGB keeps the attributes of each map entry at the same address in bank 1
+        let attributes = if self.model == Model::Cgb { self.vram[1][map_address] } else { 0 };
+        let row = if attributes & Y_FLIP != 0 { 7 - y % 8 } else { y % 8 };
+        let address = (self.tile_address(tile) + row as u16 * 2) as usize;
+        let bank = self.tile_bank(attributes);
+        let (low, high) = (self.vram[bank][address], self.vram[bank][address + 1]);
+        if attributes & X_FLIP != 0 {
+            (low.reverse_bits(), high.reverse_bits(), attributes)
+        } else {
+            (low, high, attributes)
+        }
+    }
+
+    /// Get the background tile map selected by LCDC
+    fn bg_map(&self) -> u16 {
+        if self.lcdc & BG_MAP != 0 {
+            0x1C00
+        } else {
+            0x1800
+        }
+    }
+
+    /// Get the window tile map selected by LCDC
+    fn window_map(&self) -> u16 {
+        if self.lcdc & WINDOW_MAP != 0 {
+            0x1C00
+        } else {
+            0x1800
+        }
+    }
+
+    /// Check if the window starts on the current line at or before a pixel
+    fn window_covers(&self, x: u8) -> bool {
+        self.lcdc & WINDOW_ENABLE != 0 && self.wy_triggered && self.wx <= 166 && x as u16 + 7 >= self.wx as u16
+    }
+
+    /// Draw the current line
+    fn render_line(&mut self) {
+        let mut window_drawn = false;
+        for x in 0..SCREEN_WIDTH as u8 {
+            let bg = if self.lcdc & BG_ENABLE == 0 && self.model == Model::Dmg {
+                // The background and the window are blank, not even the palette applies
+                None
+            } else if self.window_covers(x) {
+                window_drawn = true;
+                let window_x = x + 7 - self.wx;
+                let (low, high, attributes) = self.tile_row(self.window_map(), window_x / 8, self.window_line);
+                Some((pixel_color(low, high, window_x % 8), attributes))
+            } else {
+                let bg_x = x.wrapping_add(self.scx);
+                let (low, high, attributes) = self.tile_row(self.bg_map(), bg_x / 8, self.ly.wrapping_add(self.scy));
+                Some((pixel_color(low, high, bg_x % 8), attributes))
+            };
+            let pixel = self.mix_pixel(x, bg);
+            self.put_pixel(x, pixel);
+        }
+        if window_drawn {
+            self.window_line += 1;
+        }
+    }
+}
+
+/// Get the color number 0-3 of a pixel of a tile row, pixel 0 is the leftmost
+pub(crate) fn pixel_color(low: u8, high: u8, pixel: u8) -> u8 {
+    let bit = 7 - pixel;
+    (high >> bit & 1) << 1 | (low >> bit & 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mode_timing() {
+        let mut ppu = Ppu::new();
+        assert_eq!(ppu.mode(), Mode::OamScan);
+        ppu.step(OAM_SCAN_CYCLES);
+        assert_eq!(ppu.mode(), Mode::PixelTransfer);
+        assert_eq!(ppu.read_vram(0), OPEN_BUS);
+        ppu.step(PIXEL_TRANSFER_CYCLES);
+        assert_eq!(ppu.mode(), Mode::HBlank);
+        ppu.step(SCANLINE_CYCLES - OAM_SCAN_CYCLES - PIXEL_TRANSFER_CYCLES);
+        assert_eq!(ppu.read_register(LY), 1);
+        assert_eq!(ppu.mode(), Mode::OamScan);
+    }
+
+    #[test]
+    fn test_vblank() {
+        let mut ppu = Ppu::new();
+        let interrupts = ppu.step(SCANLINE_CYCLES * 144 - 1);
+        assert_eq!(interrupts, 0);
+        assert_eq!(ppu.step(1), Interrupt::VBlank.bit());
+        assert_eq!(ppu.mode(), Mode::VBlank);
+        assert_eq!(ppu.frame_count(), 1);
+        ppu.step(SCANLINE_CYCLES * 10);
+        assert_eq!(ppu.read_register(LY), 0);
+        assert_eq!(ppu.mode(), Mode::OamScan);
+    }
+
+    #[test]
+    fn test_lyc_interrupt() {
+        let mut ppu = Ppu::new();
+        ppu.write_register(LYC, 2);
+        ppu.write_register(STAT, LYC_INTERRUPT);
+        assert_eq!(ppu.step(SCANLINE_CYCLES * 2 - 1), 0);
+        assert_eq!(ppu.step(1), Interrupt::Stat.bit());
+        assert_eq!(ppu.read_register(STAT), 0x80 | LYC_INTERRUPT | COINCIDENCE | Mode::OamScan as u8);
+        // The line stays high for the rest of the line so modes don't raise it again
+        ppu.write_register(STAT, LYC_INTERRUPT | HBLANK_INTERRUPT);
+        assert_eq!(ppu.step(SCANLINE_CYCLES - 1), 0);
+    }
+
+    #[test]
+    fn test_render_background() {
+        let mut ppu = Ppu::new();
+        ppu.write_register(LCDC, 0);
+        // Tile 1 has a dark first row, map entry 1 of the first row uses it
+        ppu.write_vram(0x0010, 0xFF);
+        ppu.write_vram(0x0011, 0xFF);
+        ppu.write_vram(0x1801, 0x01);
+        ppu.write_register(SCX, 4);
+        ppu.write_register(LCDC, LCD_ENABLE | TILE_DATA | BG_ENABLE);
+        ppu.step(SCANLINE_CYCLES * 144);
+        let frame = ppu.frame();
+        assert_eq!(frame[0], 0);
+        assert_eq!(frame[3], 0);
+        assert_eq!(frame[4], 3);
+        assert_eq!(frame[11], 3);
+        assert_eq!(frame[12], 0);
+        assert_eq!(frame[SCREEN_WIDTH + 4], 0);
+    }
+
+    /// Write a sprite to OAM while the LCD is off
+    fn write_sprite(ppu: &mut Ppu, index: u16, y: u8, x: u8, tile: u8, attributes: u8) {
+        for (offset, value) in [y, x, tile, attributes].into_iter().enumerate() {
+            ppu.write_oam(index * 4 + offset as u16, value);
+        }
+    }
+
+    /// Run a frame and get the first line
+    fn first_line(ppu: &mut Ppu) -> Vec<u8> {
+        ppu.step(SCANLINE_CYCLES * LINES_PER_FRAME as u32);
+        ppu.frame()[..SCREEN_WIDTH].to_vec()
+    }
+
+    #[test]
+    fn test_sprite_priority() {
+        for renderer in [Renderer::Scanline, Renderer::Fifo] {
+            let mut ppu = Ppu::new();
+            ppu.set_renderer(renderer);
+            ppu.write_register(LCDC, 0);
+            // Tile 1 is solid color 1, tile 2 solid color 3 with a transparent left half
+            for row in 0..8 {
+                ppu.write_vram(0x10 + row * 2, 0xFF);
+                ppu.write_vram(0x20 + row * 2, 0x0F);
+                ppu.write_vram(0x21 + row * 2, 0x0F);
+            }
+            // Background tile 1 from x 32 on
+            for column in 4..20 {
+                ppu.write_vram(0x1800 + column, 0x01);
+            }
+            write_sprite(&mut ppu, 0, 16, 8 + 4, 1, 0);
+            write_sprite(&mut ppu, 1, 16, 8, 2, PALETTE);
+            write_sprite(&mut ppu, 2, 16, 8 + 16, 1, 0);
+            write_sprite(&mut ppu, 3, 16, 8 + 16, 2, 0);
+            write_sprite(&mut ppu, 4, 16, 8 + 30, 1, BEHIND_BG);
+            ppu.write_register(OBP0, 0xE4);
+            ppu.write_register(OBP1, 0b10_00_00_00);
+            ppu.write_register(LCDC, LCD_ENABLE | TILE_DATA | SPRITE_ENABLE | BG_ENABLE);
+            let line = first_line(&mut ppu);
+            // Sprite 1 has the lowest X but is transparent until x 4, where it covers sprite 0
+            assert_eq!(&line[0..4], &[0; 4]);
+            assert_eq!(&line[4..8], &[2; 4]);
+            assert_eq!(&line[8..12], &[1; 4]);
+            // Same X, the first in OAM wins
+            assert_eq!(&line[16..24], &[1; 8]);
+            // Behind the background, only visible over color 0
+            assert_eq!(&line[30..32], &[1; 2]);
+            assert_eq!(&line[32..38], &[3; 6]);
+        }
+    }
+
+    #[test]
+    fn test_cgb_attributes() {
+        const RED: u16 = 0x001F;
+        const GREEN: u16 = 0x03E0;
+        const BLUE: u16 = 0x7C00;
+        const WHITE: u16 = 0x7FFF;
+        for renderer in [Renderer::Scanline, Renderer::Fifo] {
+            let mut ppu = Ppu::with_model(Model::Cgb);
+            ppu.set_renderer(renderer);
+            ppu.write_register(LCDC, 0);
+            // Background palette 1 color 1 is red, palette 2 color 3 is green, sprite palette 3
+            // color 1 is blue
+            let colors = [(BCPS, BCPD, 0x8A, RED), (BCPS, BCPD, 0x96, GREEN), (OCPS, OCPD, 0x9A, BLUE)];
+            for (palettes, data, index, color) in colors {
+                ppu.write_register(palettes, index);
+                ppu.write_register(data, color as u8);
+                ppu.write_register(data, (color >> 8) as u8);
+            }
+            assert_eq!(ppu.read_register(BCPS), 0xD8);
+            // Tile 1 is color 1 on its left half in bank 0 and color 3 in bank 1, tile 2 is solid
+            // color 1 for the sprite
+            for row in 0..8 {
+                ppu.write_vram(0x10 + row * 2, 0xF0);
+                ppu.write_vram(0x20 + row * 2, 0xFF);
+            }
+            for column in [0, 1, 2, 4] {
+                ppu.write_vram(0x1800 + column, 0x01);
+            }
+            ppu.write_register(VBK, 1);
+            for row in 0..16 {
+                ppu.write_vram(0x10 + row, 0xFF);
+            }
+            for (column, attributes) in [(0, 1), (1, 1 | X_FLIP), (2, 2 | VRAM_BANK), (4, 1 | BG_PRIORITY)] {
+                ppu.write_vram(0x1800 + column, attributes);
+            }
+            ppu.write_register(VBK, 0);
+            write_sprite(&mut ppu, 0, 16, 8 + 32, 2, 3);
+            ppu.write_register(LCDC, LCD_ENABLE | TILE_DATA | SPRITE_ENABLE | BG_ENABLE);
+            ppu.step(SCANLINE_CYCLES * LINES_PER_FRAME as u32);
+            let line = &ppu.frame_rgb555()[..SCREEN_WIDTH];
+            assert_eq!(&line[0..8], &[RED, RED, RED, RED, WHITE, WHITE, WHITE, WHITE]);
+            assert_eq!(&line[8..16], &[WHITE, WHITE, WHITE, WHITE, RED, RED, RED, RED]);
+            assert_eq!(&line[16..24], &[GREEN; 8]);
+            // The priority bit of the tile puts it over the sprite except for color 0
+            assert_eq!(&line[32..40], &[RED, RED, RED, RED, BLUE, BLUE, BLUE, BLUE]);
+            assert_eq!(ppu.frame()[32], 1);
+            // LCDC bit 0 clear keeps the background and puts the sprites on top
+            ppu.write_register(LCDC, LCD_ENABLE | TILE_DATA | SPRITE_ENABLE);
+            ppu.step(SCANLINE_CYCLES * LINES_PER_FRAME as u32);
+            let line = &ppu.frame_rgb555()[..SCREEN_WIDTH];
+            assert_eq!(line[0], RED);
+            assert_eq!(&line[32..40], &[BLUE; 8]);
+            ppu.set_color_correction(true);
+            ppu.step(SCANLINE_CYCLES * LINES_PER_FRAME as u32);
+            assert_eq!(ppu.frame_rgb555()[0], correct_color(RED));
+        }
+    }
+
+    #[test]
+    fn test_palette_access() {
+        let mut ppu = Ppu::with_model(Model::Cgb);
+        ppu.write_register(OCPS, 0x80);
+        ppu.step(OAM_SCAN_CYCLES);
+        // Palette RAM is blocked in mode 3 but the index still advances
+        ppu.write_register(OCPD, 0x00);
+        assert_eq!(ppu.read_register(OCPD), OPEN_BUS);
+        assert_eq!(ppu.read_register(OCPS), 0xC1);
+        ppu.step(PIXEL_TRANSFER_CYCLES);
+        ppu.write_register(OCPS, 0x00);
+        assert_eq!(ppu.read_register(OCPD), 0xFF);
+    }
+
+    #[test]
+    fn test_ten_sprites_per_line() {
+        let mut ppu = Ppu::new();
+        ppu.write_register(LCDC, 0);
+        ppu.write_vram(0x10, 0xFF);
+        for index in 0..12 {
+            write_sprite(&mut ppu, index, 16, 8 + index as u8 * 10, 1, 0);
+        }
+        ppu.write_register(OBP0, 0xE4);
+        ppu.write_register(LCDC, LCD_ENABLE | SPRITE_ENABLE | TILE_DATA | BG_ENABLE);
+        let line = first_line(&mut ppu);
+        assert_eq!(line[90], 1);
+        assert_eq!(line[100], 0);
+        assert_eq!(line[110], 0);
+    }
+
+    #[test]
+    fn test_tall_flipped_sprite() {
+        let mut ppu = Ppu::new();
+        ppu.write_register(LCDC, 0);
+        // Last row of tile 3 has only its leftmost pixel set
+        ppu.write_vram(0x3E, 0x80);
+        ppu.set_sprite_log(true);
+        write_sprite(&mut ppu, 5, 16, 8, 2, Y_FLIP | X_FLIP);
+        ppu.write_register(OBP0, 0xE4);
+        ppu.write_register(LCDC, LCD_ENABLE | SPRITE_ENABLE | SPRITE_SIZE | TILE_DATA | BG_ENABLE);
+        let line = first_line(&mut ppu);
+        assert_eq!(line[7], 1);
+        assert_eq!(line[0], 0);
+        assert_eq!(ppu.sprite_log(15).unwrap().len(), 1);
+        assert!(ppu.sprite_log(16).unwrap().is_empty());
+        assert!(ppu.dump_sprite_log().starts_with("LY   0: #05 x=  8 y= 16 tile=02 attr=60"));
+    }
+}
@@ -0,0 +1,97 @@
+const PALETTE_RAM_SIZE: usize = 64; // 8 palettes of 4 colors, 2 bytes per color
+const AUTO_INCREMENT: u8 = 1 << 7; // BCPS/OCPS bit that advances the index after each data write
+
+/// CGB palette RAM of the background or the sprites, accessed through an index register
+/// (BCPS/OCPS) and a data register (BCPD/OCPD)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(super) struct PaletteRam {
+    data: [u8; PALETTE_RAM_SIZE],
+    /// Byte accessed through the data register, 0-63
+    index: u8,
+    auto_increment: bool,
+}
+
+impl PaletteRam {
+    /// Create palette RAM with every color white
+    pub(super) fn new() -> Self {
+        PaletteRam {
+            data: [0xFF; PALETTE_RAM_SIZE],
+            index: 0,
+            auto_increment: false,
+        }
+    }
+
+    /// Read the index register, bit 6 isn't wired
+    pub(super) fn read_index(&self) -> u8 {
+        0x40 | if self.auto_increment { AUTO_INCREMENT } else { 0 } | self.index
+    }
+
+    pub(super) fn write_index(&mut self, value: u8) {
+        self.index = value & 0x3F;
+        self.auto_increment = value & AUTO_INCREMENT != 0;
+    }
+
+    pub(super) fn read_data(&self) -> u8 {
+        self.data[self.index as usize]
+    }
+
+    /// Write the data register, the index advances even when the PPU blocks the write
+    pub(super) fn write_data(&mut self, value: u8, accessible: bool) {
+        if accessible {
+            self.data[self.index as usize] = value;
+        }
+        if self.auto_increment {
+            self.index = (self.index + 1) & 0x3F;
+        }
+    }
+
+    /// Get the RGB555 color of a color number in a palette, red in the lowest bits
+    pub(super) fn color(&self, palette: u8, color: u8) -> u16 {
+        let index = (palette as usize & 0x07) * 8 + color as usize * 2;
+        u16::from_le_bytes([self.data[index], self.data[index + 1]]) & 0x7FFF
+    }
+}
+
+/// Approximate the colors of the CGB LCD, which bleeds the channels into each other and can't
+/// show the full brightness
+pub fn correct_color(color: u16) -> u16 {
+    let red = (color & 0x1F) as u32;
+    let green = (color >> 5 & 0x1F) as u32;
+    let blue = (color >> 10 & 0x1F) as u32;
+    let corrected_red = (red * 26 + green * 4 + blue * 2).min(960) >> 5;
+    let corrected_green = (green * 24 + blue * 8).min(960) >> 5;
+    let corrected_blue = (red * 6 + green * 4 + blue * 22).min(960) >> 5;
+    (corrected_red | corrected_green << 5 | corrected_blue << 10) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auto_increment() {
+        let mut palettes = PaletteRam::new();
+        palettes.write_index(AUTO_INCREMENT | 0x3E);
+        assert_eq!(palettes.read_index(), 0xFE);
+        palettes.write_data(0x1F, true);
+        palettes.write_data(0x7C, true);
+        // The index wraps around to the first palette
+        assert_eq!(palettes.read_index(), 0xC0);
+        assert_eq!(palettes.color(7, 3), 0x7C1F);
+        palettes.write_data(0x00, false);
+        assert_eq!(palettes.read_index(), 0xC1);
+        palettes.write_index(0x00);
+        assert_eq!(palettes.read_data(), 0xFF);
+        palettes.write_data(0x12, true);
+        assert_eq!(palettes.read_index(), 0x40);
+        assert_eq!(palettes.read_data(), 0x12);
+    }
+
+    #[test]
+    fn test_color_correction() {
+        assert_eq!(correct_color(0x0000), 0x0000);
+        // White can't reach full brightness and pure red bleeds into blue
+        assert_eq!(correct_color(0x7FFF), 30 | 30 << 5 | 30 << 10);
+        assert_eq!(correct_color(0x001F), 25 | 5 << 10);
+    }
+}
@@ -0,0 +1,387 @@
+use std::cell::RefCell;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::png;
+use crate::ppu::pixel_color;
+use crate::serial::SerialDevice;
+
+pub const PRINT_WIDTH: usize = 160; // Pixels across the paper, 20 tiles
+
+const MAGIC: [u8; 2] = [0x88, 0x33]; // Start of every packet
+const ALIVE: u8 = 0x81; // Sent back while the game boy sends the first byte after the checksum
+
+const INIT: u8 = 0x01; // Clear the image buffer
+const PRINT: u8 = 0x02; // Print the image buffer, with the sheets, margins, palette and exposure
+const DATA: u8 = 0x04; // Add tile rows to the image buffer, an empty packet ends the image
+const STATUS: u8 = 0x0F; // Only ask for the status
+
+const CHECKSUM_ERROR: u8 = 1 << 0;
+const PRINTING: u8 = 1 << 1;
+const IMAGE_FULL: u8 = 1 << 2;
+const UNPROCESSED_DATA: u8 = 1 << 3;
+
+const TILE_SIZE: usize = 16; // Bytes of a 2bpp 8x8 tile
+const TILES_PER_ROW: usize = PRINT_WIDTH / 8;
+const BUFFER_SIZE: usize = 0x1680; // Image buffer, 18 rows of tiles
+const DEFAULT_PALETTE: u8 = 0xE4; // Used when a game sends a palette of 0
+const PRINT_LINE_CYCLES: u32 = 43_690; // 4.19MHz cycles to print a line, about 1.5s for a screen
+
+/// Part of a packet the printer expects next
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Receive {
+    Magic(usize),
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    Alive,
+    Status,
+}
+
+/// Image printed on paper, the margins above and below are left out
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PrintedImage {
+    /// Lines of PRINT_WIDTH shades from 0 (white) to 3 (black)
+    pub pixels: Vec<u8>,
+}
+
+impl PrintedImage {
+    /// Get the height of the image in pixels
+    pub fn height(&self) -> usize {
+        self.pixels.len() / PRINT_WIDTH
+    }
+
+    /// Encode the image as a PNG file
+    pub fn to_png(&self) -> Vec<u8> {
+        png::encode_shades(PRINT_WIDTH, self.height(), &self.pixels)
+    }
+
+    /// Write the image to a PNG file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        png::write_shades(path, PRINT_WIDTH, self.height(), &self.pixels)
+    }
+}
+
+/// Game Boy Printer on the link port.
+///
+/// Prints with no margin below are joined to the next one on the same sheet, a sheet is
+/// complete once a print feeds paper after it. Clones share the sheets printed, so a clone can
+/// be kept to read them after plugging one in
+#[derive(Clone, Debug)]
+pub struct Printer {
+    receive: Receive,
+    command: u8,
+    compressed: bool,
+    length: u16,
+    /// Packet data received so far, still compressed
+    packet: Vec<u8>,
+    checksum: u16,
+    status: u8,
+    /// Tile rows received since the last print
+    buffer: Vec<u8>,
+    /// Lines printed on the sheet that hasn't been cut yet
+    sheet: Vec<u8>,
+    /// 4.19MHz cycles until the print completes
+    printing: u32,
+    /// Folder the sheets are written to as PNG files
+    output: Option<PathBuf>,
+    sheets: Rc<RefCell<Vec<PrintedImage>>>,
+}
+
+impl Default for Printer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Printer {
+    /// Create a printer that keeps the sheets in memory
+    pub fn new() -> Self {
+        Printer {
+            receive: Receive::Magic(0),
+            command: 0,
+            compressed: false,
+            length: 0,
+            packet: Vec::new(),
+            checksum: 0,
+            status: 0,
+            buffer: Vec::new(),
+            sheet: Vec::new(),
+            printing: 0,
+            output: None,
+            sheets: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    /// Create a printer that also writes every sheet to print_NNN.png files in a folder
+    pub fn with_output<P: AsRef<Path>>(folder: P) -> Self {
+        Printer {
+            output: Some(folder.as_ref().to_path_buf()),
+            ..Self::new()
+        }
+    }
+
+    /// Get the sheets printed so far
+    pub fn sheets(&self) -> Vec<PrintedImage> {
+        self.sheets.borrow().clone()
+    }
+
+    /// Get the value sent back for the byte received and advance the packet
+    fn receive(&mut self, value: u8) -> u8 {
+        let mut reply = 0x00;
+        self.receive = match self.receive {
+            Receive::Magic(index) if value == MAGIC[index] => {
+                if index + 1 < MAGIC.len() {
+                    Receive::Magic(index + 1)
+                } else {
+                    Receive::Command
+                }
+            }
+            // Anything else before the packet starts is ignored
+            Receive::Magic(_) => Receive::Magic(usize::from(value == MAGIC[0])),
+            Receive::Command => {
+                self.command = value;
+                self.checksum = value as u16;
+                Receive::Compression
+            }
+            Receive::Compression => {
+                self.compressed = value & 0x01 != 0;
+                self.checksum = self.checksum.wrapping_add(value as u16);
+                Receive::LengthLow
+            }
+            Receive::LengthLow => {
+                self.length = value as u16;
+                self.checksum = self.checksum.wrapping_add(value as u16);
+                Receive::LengthHigh
+            }
+            Receive::LengthHigh => {
+                self.length |= (value as u16) << 8;
+                self.checksum = self.checksum.wrapping_add(value as u16);
+                self.packet.clear();
+                if self.length == 0 {
+                    Receive::ChecksumLow
+                } else {
+                    Receive::Data
+                }
+            }
+            Receive::Data => {
+                self.packet.push(value);
+                self.checksum = self.checksum.wrapping_add(value as u16);
+                if self.packet.len() == self.length as usize {
+                    Receive::ChecksumLow
+                } else {
+                    Receive::Data
+                }
+            }
+            Receive::ChecksumLow => {
+                self.checksum ^= value as u16;
+                Receive::ChecksumHigh
+            }
+            Receive::ChecksumHigh => {
+                self.checksum ^= (value as u16) << 8;
+                Receive::Alive
+            }
+            Receive::Alive => {
+                reply = ALIVE;
+                if self.checksum == 0 {
+                    self.status &= !CHECKSUM_ERROR;
+                    self.run_command();
+                } else {
+                    self.status |= CHECKSUM_ERROR;
+                }
+                Receive::Status
+            }
+            Receive::Status => {
+                reply = self.status;
+                Receive::Magic(0)
+            }
+        };
+        reply
+    }
+
+    /// Carry out a packet that passed the checksum
+    fn run_command(&mut self) {
+        match self.command {
+            INIT => {
+                self.buffer.clear();
+                self.status = 0;
+            }
+            PRINT if self.packet.len() >= 4 => self.print(self.packet[1], self.packet[2]),
+            DATA => {
+                let data = if self.compressed {
+                    decompress(&self.packet)
+                } else {
+                    self.packet.clone()
+                };
+                let space = BUFFER_SIZE - self.buffer.len();
+                self.buffer.extend_from_slice(&data[..data.len().min(space)]);
+                self.status |= UNPROCESSED_DATA;
+                if self.buffer.len() == BUFFER_SIZE {
+                    self.status |= IMAGE_FULL;
+                }
+            }
+            STATUS => {}
+            _ => {}
+        }
+    }
+
+    /// Print the image buffer, the high nibble of the margins is the feed before the image and
+    /// the low nibble the feed after it
+    fn print(&mut self, margins: u8, palette: u8) {
+        let palette = if palette == 0 { DEFAULT_PALETTE } else { palette };
+        if margins >> 4 != 0 {
+            self.cut();
+        }
+        let rows = self.buffer.len() / (TILE_SIZE * TILES_PER_ROW);
+        for line in 0..rows * 8 {
+            for x in 0..PRINT_WIDTH {
+                let tile = (line / 8 * TILES_PER_ROW + x / 8) * TILE_SIZE;
+                let row = tile + line % 8 * 2;
+                let color = pixel_color(self.buffer[row], self.buffer[row + 1], x as u8 % 8);
+                self.sheet.push(palette >> (color * 2) & 0x03);
+            }
+        }
+        self.printing = (rows * 8) as u32 * PRINT_LINE_CYCLES;
+        self.buffer.clear();
+        self.status &= !(UNPROCESSED_DATA | IMAGE_FULL);
+        if self.printing > 0 {
+            self.status |= PRINTING;
+        }
+        if margins & 0x0F != 0 {
+            self.cut();
+        }
+    }
+
+    /// Finish the sheet being printed
+    fn cut(&mut self) {
+        if self.sheet.is_empty() {
+            return;
+        }
+        let image = PrintedImage {
+            pixels: std::mem::take(&mut self.sheet),
+        };
+        if let Some(folder) = &self.output {
+            let path = folder.join(format!("print_{:03}.png", self.sheets.borrow().len() + 1));
+            if let Err(error) = image.save(&path) {
+                eprintln!("Could not write {}: {}", path.display(), error);
+            }
+        }
+        self.sheets.borrow_mut().push(image);
+    }
+}
+
+impl SerialDevice for Printer {
+    fn exchange(&mut self, value: u8) -> u8 {
+        self.receive(value)
+    }
+
+    fn step(&mut self, cycles: u32, _external: Option<u8>) -> Option<u8> {
+        if self.printing > 0 {
+            self.printing = self.printing.saturating_sub(cycles);
+            if self.printing == 0 {
+                self.status &= !PRINTING;
+            }
+        }
+        None
+    }
+}
+
+/// Expand the run length encoding of DATA packets. A byte with the top bit set repeats the next
+/// byte (n & 0x7F) + 2 times, otherwise the next n + 1 bytes are copied
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut index = 0;
+    while index < data.len() {
+        let control = data[index] as usize;
+        index += 1;
+        if control & 0x80 != 0 {
+            let Some(value) = data.get(index) else {
+                break;
+            };
+            output.extend(std::iter::repeat_n(*value, (control & 0x7F) + 2));
+            index += 1;
+        } else {
+            let end = (index + control + 1).min(data.len());
+            output.extend_from_slice(&data[index..end]);
+            index = end;
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Send a packet and return the alive and status bytes
+    fn send(printer: &mut Printer, command: u8, compressed: bool, data: &[u8]) -> (u8, u8) {
+        let mut packet = vec![command, compressed as u8, data.len() as u8, (data.len() >> 8) as u8];
+        packet.extend_from_slice(data);
+        let checksum = packet.iter().fold(0u16, |sum, byte| sum.wrapping_add(*byte as u16));
+        for byte in MAGIC.into_iter().chain(packet).chain(checksum.to_le_bytes()) {
+            assert_eq!(printer.exchange(byte), 0x00);
+        }
+        (printer.exchange(0x00), printer.exchange(0x00))
+    }
+
+    #[test]
+    fn test_print() {
+        let mut printer = Printer::new();
+        assert_eq!(send(&mut printer, INIT, false, &[]), (ALIVE, 0x00));
+        // Two rows of tiles, the first tile of each is black and the rest is white
+        let mut data = vec![0x00; TILE_SIZE * TILES_PER_ROW * 2];
+        data[..TILE_SIZE].fill(0xFF);
+        data[TILE_SIZE * TILES_PER_ROW..][..TILE_SIZE].fill(0xFF);
+        assert_eq!(send(&mut printer, DATA, false, &data), (ALIVE, UNPROCESSED_DATA));
+        assert_eq!(send(&mut printer, DATA, false, &[]), (ALIVE, UNPROCESSED_DATA));
+        assert_eq!(send(&mut printer, PRINT, false, &[1, 0x01, 0xE4, 0x40]), (ALIVE, PRINTING));
+        printer.step(16 * PRINT_LINE_CYCLES, None);
+        assert_eq!(send(&mut printer, STATUS, false, &[]), (ALIVE, 0x00));
+        let sheets = printer.sheets();
+        assert_eq!(sheets.len(), 1);
+        assert_eq!(sheets[0].height(), 16);
+        assert_eq!(sheets[0].pixels[7], 3);
+        assert_eq!(sheets[0].pixels[8], 0);
+        assert_eq!(sheets[0].pixels[15 * PRINT_WIDTH], 3);
+    }
+
+    #[test]
+    fn test_compression_and_checksum() {
+        assert_eq!(decompress(&[0x81, 0xAA, 0x01, 0x12, 0x34]), vec![0xAA, 0xAA, 0xAA, 0x12, 0x34]);
+        let mut printer = Printer::new();
+        // A whole row of black tiles in runs of 64 bytes
+        let row = [0x80 | 62, 0xFF].repeat(TILE_SIZE * TILES_PER_ROW / 64);
+        send(&mut printer, DATA, true, &row);
+        send(&mut printer, PRINT, false, &[1, 0x00, 0x00, 0x40]);
+        // Without margin after it the print waits for the next one on the same sheet
+        assert!(printer.sheets().is_empty());
+        send(&mut printer, PRINT, false, &[1, 0x10, 0xE4, 0x40]);
+        assert_eq!(printer.sheets().len(), 1);
+        assert_eq!(printer.sheets()[0].pixels, vec![3; PRINT_WIDTH * 8]);
+        // A corrupted packet is reported and ignored
+        let mut packet = MAGIC.to_vec();
+        packet.extend_from_slice(&[INIT, 0, 0, 0, 0x02, 0x00]);
+        for byte in packet {
+            printer.exchange(byte);
+        }
+        assert_eq!(printer.exchange(0x00), ALIVE);
+        assert_eq!(printer.exchange(0x00) & CHECKSUM_ERROR, CHECKSUM_ERROR);
+    }
+
+    #[test]
+    fn test_png_output() {
+        let directory = std::env::temp_dir().join(format!("emulador_gb_printer_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let mut printer = Printer::with_output(&directory);
+        send(&mut printer, DATA, false, &[0x00; TILE_SIZE * TILES_PER_ROW]);
+        send(&mut printer, PRINT, false, &[1, 0x03, 0xE4, 0x40]);
+        let png = std::fs::read(directory.join("print_001.png")).unwrap();
+        assert_eq!(png, printer.sheets()[0].to_png());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
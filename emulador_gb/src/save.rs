@@ -0,0 +1,157 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::mbc::Mapper;
+
+const CYCLES_PER_SECOND: f64 = 4_194_304.0;
+pub const DEFAULT_AUTOSAVE_INTERVAL: Duration = Duration::from_secs(1); // Emulated time a write waits before flushing
+
+/// Get the path of the save file of a ROM, next to it with the .sav extension
+pub fn save_path<P: AsRef<Path>>(rom_path: P) -> PathBuf {
+    rom_path.as_ref().with_extension("sav")
+}
+
+/// Build the contents of a .sav file, the battery backed memory followed by the RTC footer
+pub fn encode(mapper: &dyn Mapper) -> Vec<u8> {
+    let mut data = mapper.save_data();
+    if let Some(rtc) = mapper.rtc() {
+        data.extend_from_slice(&rtc.save_footer());
+    }
+    data
+}
+
+/// Restore a mapper from the contents of a .sav file, a missing or invalid footer keeps the clock
+pub fn decode(mapper: &mut dyn Mapper, data: &[u8]) {
+    let memory_size = mapper.save_data().len().min(data.len());
+    mapper.load_save_data(&data[..memory_size]);
+    if let Some(rtc) = mapper.rtc_mut() {
+        rtc.load_footer(&data[memory_size..]);
+    }
+}
+
+/// Save file of a battery backed cartridge, flushed once the memory has been dirty for a while
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BatterySave {
+    path: PathBuf,
+    /// Emulated cycles between the first write and the flush, None only saves on demand
+    interval: Option<u64>,
+    dirty: bool,
+    /// Cycles since the memory became dirty
+    elapsed: u64,
+}
+
+impl BatterySave {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        let mut save = BatterySave {
+            path: path.as_ref().to_path_buf(),
+            interval: None,
+            dirty: false,
+            elapsed: 0,
+        };
+        save.set_interval(Some(DEFAULT_AUTOSAVE_INTERVAL));
+        save
+    }
+
+    /// Get the path of the .sav file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Set how much emulated time the memory stays dirty before it's flushed
+    pub fn set_interval(&mut self, interval: Option<Duration>) {
+        self.interval = interval.map(|interval| (interval.as_secs_f64() * CYCLES_PER_SECOND) as u64);
+    }
+
+    /// Record a write to the battery backed memory
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    /// Check if there are writes that haven't been flushed
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Advance the interval, returns true when the memory should be flushed
+    pub fn step(&mut self, cycles: u32) -> bool {
+        let Some(interval) = self.interval else {
+            return false;
+        };
+        if !self.dirty {
+            return false;
+        }
+        self.elapsed += cycles as u64;
+        if self.elapsed < interval {
+            return false;
+        }
+        self.elapsed = 0;
+        true
+    }
+
+    /// Restore the mapper from the .sav file, a missing file leaves it untouched
+    pub fn load(&self, mapper: &mut dyn Mapper) -> io::Result<()> {
+        match fs::read(&self.path) {
+            Ok(data) => {
+                decode(mapper, &data);
+                Ok(())
+            }
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Write the .sav file, the old save is only replaced once the new one is complete
+    pub fn flush(&mut self, mapper: &dyn Mapper) -> io::Result<()> {
+        let temporary = self.path.with_extension("sav.tmp");
+        fs::write(&temporary, encode(mapper))?;
+        fs::rename(&temporary, &self.path)?;
+        self.dirty = false;
+        self.elapsed = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mbc::{Mbc3, RtcClock, RTC_FOOTER_SIZE};
+
+    #[test]
+    fn test_save_path() {
+        assert_eq!(save_path("roms/pokemon.gb"), PathBuf::from("roms/pokemon.sav"));
+        assert_eq!(save_path("game"), PathBuf::from("game.sav"));
+    }
+
+    #[test]
+    fn test_encode_and_decode() {
+        let mut mbc = Mbc3::new(vec![0; 0x8000], 0x2000, Some(RtcClock::Emulated));
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(0x0123, 0x42);
+        mbc.write_rom(0x4000, 0x0A);
+        mbc.write_ram(0x0000, 5);
+        let data = encode(&mbc);
+        assert_eq!(data.len(), 0x2000 + RTC_FOOTER_SIZE);
+        let mut loaded = Mbc3::new(vec![0; 0x8000], 0x2000, Some(RtcClock::Emulated));
+        decode(&mut loaded, &data);
+        loaded.write_rom(0x0000, 0x0A);
+        assert_eq!(loaded.read_ram(0x0123), 0x42);
+        loaded.write_rom(0x4000, 0x0A);
+        loaded.write_rom(0x6000, 0x00);
+        loaded.write_rom(0x6000, 0x01);
+        assert_eq!(loaded.read_ram(0x0000), 5);
+    }
+
+    #[test]
+    fn test_interval() {
+        let mut save = BatterySave::new("test.sav");
+        save.set_interval(Some(Duration::from_secs(2)));
+        assert!(!save.step(CYCLES_PER_SECOND as u32 * 3));
+        save.mark_dirty();
+        assert!(!save.step(CYCLES_PER_SECOND as u32));
+        assert!(save.step(CYCLES_PER_SECOND as u32));
+        save.set_interval(None);
+        assert!(!save.step(CYCLES_PER_SECOND as u32 * 3));
+    }
+}
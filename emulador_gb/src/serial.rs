@@ -0,0 +1,254 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::interrupts::Interrupt;
+use crate::memory::OPEN_BUS;
+
+pub const SB: u16 = 0xFF01; // Serial transfer data, shifted out MSB first while the other side is shifted in
+pub const SC: u16 = 0xFF02; // Serial transfer control
+
+const TRANSFER_START: u8 = 1 << 7; // Set to start a transfer, cleared when it completes
+const INTERNAL_CLOCK: u8 = 1 << 0; // The game boy drives the clock instead of the other side
+
+const BIT_CYCLES: u32 = 512; // 4.19MHz cycles per bit with the internal 8192Hz clock
+
+/// Whatever is plugged into the link port
+pub trait SerialDevice {
+    /// Exchange a byte in a transfer clocked by the game boy, returns the byte shifted in
+    fn exchange(&mut self, value: u8) -> u8;
+
+    /// Advance the device, the cycles are 4.19MHz clock cycles. While the game boy waits on the
+    /// external clock it gets the byte in SB, and returns the byte shifted in when it clocks the transfer
+    fn step(&mut self, _cycles: u32, _external: Option<u8>) -> Option<u8> {
+        None
+    }
+}
+
+/// Nothing plugged in, the input line is pulled high and there is no external clock
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Disconnected;
+
+impl SerialDevice for Disconnected {
+    fn exchange(&mut self, _value: u8) -> u8 {
+        0xFF
+    }
+}
+
+/// Cable from the output to the input of the same game boy, every byte sent comes back
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Loopback;
+
+impl SerialDevice for Loopback {
+    fn exchange(&mut self, value: u8) -> u8 {
+        value
+    }
+}
+
+/// Records the bytes sent by the game boy, used to read the results printed by test ROMs.
+/// Clones share the recording, so a clone can be kept to read it after plugging one in
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SerialCapture {
+    bytes: Rc<RefCell<Vec<u8>>>,
+}
+
+impl SerialCapture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the bytes sent so far
+    pub fn bytes(&self) -> Vec<u8> {
+        self.bytes.borrow().clone()
+    }
+
+    /// Get the bytes sent so far as text
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.bytes.borrow()).into_owned()
+    }
+
+    /// Forget the bytes sent so far
+    pub fn clear(&mut self) {
+        self.bytes.borrow_mut().clear();
+    }
+}
+
+impl SerialDevice for SerialCapture {
+    fn exchange(&mut self, value: u8) -> u8 {
+        self.bytes.borrow_mut().push(value);
+        0xFF
+    }
+}
+
+/// Serial port behind SB and SC, shifts a byte out and another in over 8 clocks
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    device: Box<dyn SerialDevice>,
+    /// Byte received from the device, shifted into SB bit by bit
+    incoming: u8,
+    /// Bits left in the transfer clocked by the game boy
+    bits: u8,
+    /// 4.19MHz cycles since the last bit
+    cycles: u32,
+}
+
+impl fmt::Debug for Serial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Serial")
+            .field("sb", &self.sb)
+            .field("sc", &self.sc)
+            .field("bits", &self.bits)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for Serial {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Serial {
+    pub fn new() -> Self {
+        Serial {
+            sb: 0,
+            sc: 0,
+            device: Box::new(Disconnected),
+            incoming: 0xFF,
+            bits: 0,
+            cycles: 0,
+        }
+    }
+
+    /// Plug a device into the link port
+    pub fn set_device(&mut self, device: Box<dyn SerialDevice>) {
+        self.device = device;
+    }
+
+    /// Check if a transfer was requested and hasn't completed
+    pub fn is_transferring(&self) -> bool {
+        self.sc & TRANSFER_START != 0
+    }
+
+    /// Advance the transfer, the cycles are 4.19MHz clock cycles.
+    /// Returns the IF bits of the interrupts requested
+    pub fn step(&mut self, cycles: u32) -> u8 {
+        let external = self.is_transferring() && self.sc & INTERNAL_CLOCK == 0;
+        // The other side shifts the whole byte at its own pace
+        if let Some(value) = self.device.step(cycles, external.then_some(self.sb)) {
+            if external {
+                self.sb = value;
+                return self.complete();
+            }
+        }
+        if !self.is_transferring() || external {
+            return 0;
+        }
+        self.cycles += cycles;
+        while self.cycles >= BIT_CYCLES && self.bits > 0 {
+            self.cycles -= BIT_CYCLES;
+            self.bits -= 1;
+            self.sb = self.sb << 1 | self.incoming >> self.bits & 0x01;
+            if self.bits == 0 {
+                return self.complete();
+            }
+        }
+        0
+    }
+
+    fn complete(&mut self) -> u8 {
+        self.sc &= !TRANSFER_START;
+        self.cycles = 0;
+        Interrupt::Serial.bit()
+    }
+
+    /// Read a serial register
+    pub fn read_register(&self, address: u16) -> u8 {
+        match address {
+            SB => self.sb,
+            // Only the start and clock bits are wired
+            SC => 0x7E | self.sc,
+            _ => OPEN_BUS,
+        }
+    }
+
+    /// Write a serial register
+    pub fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            SB => self.sb = value,
+            SC => {
+                self.sc = value & (TRANSFER_START | INTERNAL_CLOCK);
+                if self.sc == TRANSFER_START | INTERNAL_CLOCK {
+                    // The device sees the whole byte at the start, the answer comes in with the clocks
+                    self.incoming = self.device.exchange(self.sb);
+                    self.bits = 8;
+                    self.cycles = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Device clocking a single transfer from the other side
+    struct ExternalMaster(Option<u8>);
+
+    impl SerialDevice for ExternalMaster {
+        fn exchange(&mut self, _value: u8) -> u8 {
+            0xFF
+        }
+
+        fn step(&mut self, _cycles: u32, external: Option<u8>) -> Option<u8> {
+            external.and(self.0.take())
+        }
+    }
+
+    #[test]
+    fn test_internal_clock() {
+        let capture = SerialCapture::new();
+        let mut serial = Serial::new();
+        serial.set_device(Box::new(capture.clone()));
+        for byte in b"Passed" {
+            serial.write_register(SB, *byte);
+            serial.write_register(SC, 0x81);
+            assert_eq!(serial.read_register(SC), 0xFF);
+            assert_eq!(serial.step(BIT_CYCLES * 8 - 1), 0);
+            assert_eq!(serial.step(1), Interrupt::Serial.bit());
+            assert_eq!(serial.read_register(SC), 0x7F);
+            assert_eq!(serial.read_register(SB), 0xFF);
+        }
+        assert_eq!(capture.text(), "Passed");
+    }
+
+    #[test]
+    fn test_loopback() {
+        let mut serial = Serial::new();
+        serial.set_device(Box::new(Loopback));
+        serial.write_register(SB, 0xA5);
+        serial.write_register(SC, 0x81);
+        // Half of the byte has been shifted through the cable
+        serial.step(BIT_CYCLES * 4);
+        assert_eq!(serial.read_register(SB), 0x5A);
+        serial.step(BIT_CYCLES * 4);
+        assert_eq!(serial.read_register(SB), 0xA5);
+    }
+
+    #[test]
+    fn test_external_clock() {
+        let mut serial = Serial::new();
+        serial.write_register(SB, 0x42);
+        serial.write_register(SC, 0x80);
+        // Nothing drives the clock, the transfer waits forever
+        assert_eq!(serial.step(BIT_CYCLES * 16), 0);
+        assert!(serial.is_transferring());
+        serial.set_device(Box::new(ExternalMaster(Some(0x24))));
+        assert_eq!(serial.step(4), Interrupt::Serial.bit());
+        assert_eq!(serial.read_register(SB), 0x24);
+        assert!(!serial.is_transferring());
+    }
+}
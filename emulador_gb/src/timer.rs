@@ -0,0 +1,230 @@
+use crate::interrupts::Interrupt;
+use crate::memory::OPEN_BUS;
+
+pub const DIV: u16 = 0xFF04; // Upper byte of the system counter, reset on write
+pub const TIMA: u16 = 0xFF05; // Timer counter
+pub const TMA: u16 = 0xFF06; // Value loaded into TIMA when it overflows
+pub const TAC: u16 = 0xFF07; // Timer enable and clock select
+
+const TIMER_ENABLE: u8 = 1 << 2;
+
+/// Progress of the reload that follows a TIMA overflow
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Reload {
+    None,
+    /// TIMA overflowed and reads 0 for an M-cycle, a write to TIMA cancels the reload
+    Pending,
+    /// TIMA was just loaded from TMA, writes to TIMA are ignored and writes to TMA also go to TIMA
+    Reloading,
+}
+
+/// Timer driven by the 16 bit system counter. TIMA increments on the falling edge of a counter
+/// bit selected by TAC ANDed with the enable bit, so writes to DIV and TAC can also increment it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Timer {
+    /// System counter, increments every 4.19MHz clock cycle
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    reload: Reload,
+    /// T-cycles that don't add up to an M-cycle yet
+    cycles: u32,
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Timer {
+    /// Create a timer with the counter left by the boot ROM
+    pub fn new() -> Self {
+        Timer {
+            counter: 0xABCC,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            reload: Reload::None,
+            cycles: 0,
+        }
+    }
+
+    /// Get the bit of the system counter selected by TAC
+    fn selected_bit(&self) -> u16 {
+        match self.tac & 0x03 {
+            0b00 => 1 << 9,
+            0b01 => 1 << 3,
+            0b10 => 1 << 5,
+            _ => 1 << 7,
+        }
+    }
+
+    /// Get the input of the falling edge detector
+    fn signal(&self) -> bool {
+        self.tac & TIMER_ENABLE != 0 && self.counter & self.selected_bit() != 0
+    }
+
+    fn increment(&mut self) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        if overflow {
+            self.reload = Reload::Pending;
+        }
+    }
+
+    /// Advance the timer, the cycles are 4.19MHz clock cycles.
+    /// Returns the IF bits of the interrupts requested
+    pub fn step(&mut self, cycles: u32) -> u8 {
+        self.cycles += cycles;
+        let mut interrupts = 0;
+        while self.cycles >= 4 {
+            self.cycles -= 4;
+            if self.tick() {
+                interrupts |= Interrupt::Timer.bit();
+            }
+        }
+        interrupts
+    }
+
+    /// Advance the timer by one M-cycle, returns true when the interrupt is requested
+    fn tick(&mut self) -> bool {
+        let mut interrupt = false;
+        match self.reload {
+            Reload::Pending => {
+                self.tima = self.tma;
+                self.reload = Reload::Reloading;
+                interrupt = true;
+            }
+            Reload::Reloading => self.reload = Reload::None,
+            Reload::None => {}
+        }
+        let signal = self.signal();
+        self.counter = self.counter.wrapping_add(4);
+        if signal && !self.signal() {
+            self.increment();
+        }
+        interrupt
+    }
+
+    /// Clear the system counter, done by writes to DIV and by STOP
+    pub fn reset_counter(&mut self) {
+        let signal = self.signal();
+        self.counter = 0;
+        // Clearing the selected bit is a falling edge
+        if signal {
+            self.increment();
+        }
+    }
+
+    /// Read a timer register
+    pub fn read_register(&self, address: u16) -> u8 {
+        match address {
+            DIV => (self.counter >> 8) as u8,
+            TIMA => self.tima,
+            TMA => self.tma,
+            TAC => 0xF8 | self.tac,
+            _ => OPEN_BUS,
+        }
+    }
+
+    /// Write a timer register
+    pub fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            DIV => self.reset_counter(),
+            TIMA => match self.reload {
+                Reload::Pending => {
+                    self.tima = value;
+                    self.reload = Reload::None;
+                }
+                Reload::Reloading => {}
+                Reload::None => self.tima = value,
+            },
+            TMA => {
+                self.tma = value;
+                if self.reload == Reload::Reloading {
+                    self.tima = value;
+                }
+            }
+            TAC => {
+                let signal = self.signal();
+                self.tac = value & 0x07;
+                // Disabling the timer or selecting a clear bit is a falling edge
+                if signal && !self.signal() {
+                    self.increment();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enabled_timer(tac: u8) -> Timer {
+        let mut timer = Timer::new();
+        timer.write_register(DIV, 0);
+        timer.write_register(TAC, tac);
+        timer
+    }
+
+    #[test]
+    fn test_frequencies() {
+        for (tac, period) in [(0x04, 1024), (0x05, 16), (0x06, 64), (0x07, 256)] {
+            let mut timer = enabled_timer(tac);
+            timer.step(period * 10);
+            assert_eq!(timer.read_register(TIMA), 10);
+        }
+        let mut timer = enabled_timer(0x05);
+        timer.step(0x1000);
+        assert_eq!(timer.read_register(DIV), 0x10);
+    }
+
+    #[test]
+    fn test_overflow_delay() {
+        let mut timer = enabled_timer(0x05);
+        timer.write_register(TMA, 0x80);
+        timer.write_register(TIMA, 0xFF);
+        assert_eq!(timer.step(16), 0);
+        // TIMA reads 0 for an M-cycle before the reload and the interrupt
+        assert_eq!(timer.read_register(TIMA), 0x00);
+        assert_eq!(timer.step(4), Interrupt::Timer.bit());
+        assert_eq!(timer.read_register(TIMA), 0x80);
+    }
+
+    #[test]
+    fn test_reload_writes() {
+        // Writing TIMA during the delay cancels the reload
+        let mut timer = enabled_timer(0x05);
+        timer.write_register(TIMA, 0xFF);
+        timer.step(16);
+        timer.write_register(TIMA, 0x42);
+        assert_eq!(timer.step(4), 0);
+        assert_eq!(timer.read_register(TIMA), 0x42);
+        // During the reload cycle TIMA writes are ignored and TMA writes go through
+        let mut timer = enabled_timer(0x05);
+        timer.write_register(TIMA, 0xFF);
+        timer.step(20);
+        timer.write_register(TIMA, 0x42);
+        assert_eq!(timer.read_register(TIMA), 0x00);
+        timer.write_register(TMA, 0x24);
+        assert_eq!(timer.read_register(TIMA), 0x24);
+    }
+
+    #[test]
+    fn test_div_and_tac_glitches() {
+        // Resetting DIV while the selected bit is set increments TIMA
+        let mut timer = enabled_timer(0x05);
+        timer.step(8);
+        timer.write_register(DIV, 0);
+        assert_eq!(timer.read_register(TIMA), 1);
+        // So does disabling the timer
+        timer.step(8);
+        timer.write_register(TAC, 0x01);
+        assert_eq!(timer.read_register(TIMA), 2);
+        assert_eq!(timer.read_register(TAC), 0xF9);
+    }
+}